@@ -0,0 +1,259 @@
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::{anyhow, bail};
+
+use crate::commands::{typed::TYPED_COMMAND_LIST, KCommand};
+
+/// what to open, `-` reads stdin into a buffer without a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File {
+        path: PathBuf,
+        /// 1-based line and column of `path:line:col`
+        position: Option<(usize, usize)>,
+    },
+    Stdin,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub inputs: Vec<Input>,
+    /// `+<cmd>`, typed commands run in order once everything is opened
+    pub commands: Vec<String>,
+    /// `-c`, keys or `:` commands for `--headless`
+    pub script: Vec<String>,
+    pub headless: bool,
+    pub readonly: bool,
+    pub config: Option<PathBuf>,
+    /// `--session <name>`, restored before the files are opened
+    pub session: Option<String>,
+    pub log: Option<PathBuf>,
+    pub help: bool,
+    pub version: bool,
+    pub check_config: bool,
+    pub dump_keymap: bool,
+}
+
+impl Args {
+    /// `args` without the name of the program
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> anyhow::Result<Self> {
+        let mut result = Self::default();
+        let mut args = args.into_iter();
+        let mut options = true;
+        while let Some(arg) = args.next() {
+            let Some(text) = arg.to_str().filter(|_| options) else {
+                result.inputs.push(Input::file(arg.into()));
+                continue;
+            };
+            let mut value =
+                |name: &str| args.next().ok_or_else(|| anyhow!("{} takes a value", name));
+            match text {
+                "--" => options = false,
+                "-" => result.inputs.push(Input::Stdin),
+                "-h" | "--help" => result.help = true,
+                "-V" | "--version" => result.version = true,
+                "-R" | "--readonly" => result.readonly = true,
+                "--config" => result.config = Some(value(text)?.into()),
+                "--session" => match value(text)?.into_string() {
+                    Ok(name) => result.session = Some(name),
+                    Err(_) => bail!("{} takes UTF-8", text),
+                },
+                "--log" => result.log = Some(value(text)?.into()),
+                "--headless" => result.headless = true,
+                "-c" | "--command" => match value(text)?.into_string() {
+                    Ok(step) => result.script.push(step),
+                    Err(_) => bail!("{} takes UTF-8", text),
+                },
+                "--check-config" => result.check_config = true,
+                "--dump-keymap" => result.dump_keymap = true,
+                _ if text.starts_with('-') => bail!("unknown option: {}", text),
+                _ => match text.strip_prefix('+') {
+                    Some("") => bail!("+ takes a command, e.g. `+w`"),
+                    Some(command) => result.commands.push(command.to_string()),
+                    None => result.inputs.push(Input::file(arg.into())),
+                },
+            }
+        }
+        if !result.script.is_empty() && !result.headless {
+            bail!("-c needs --headless");
+        }
+        Ok(result)
+    }
+}
+
+impl Input {
+    /// `path:line:col` or `path:line`, unless a file by the whole name exists
+    fn file(path: PathBuf) -> Self {
+        let position = match path.to_str() {
+            Some(text) if !path.exists() => split_position(text),
+            _ => None,
+        };
+        match position {
+            Some((path, line, col)) => Self::File {
+                path: path.into(),
+                position: Some((line, col)),
+            },
+            None => Self::File {
+                path,
+                position: None,
+            },
+        }
+    }
+}
+
+fn split_position(text: &str) -> Option<(&str, usize, usize)> {
+    let number = |s: &str| s.parse::<usize>().ok().filter(|n| *n > 0);
+    let (rest, last) = text.rsplit_once(':')?;
+    let last = number(last)?;
+    match rest.rsplit_once(':') {
+        Some((path, line)) if !path.is_empty() => match number(line) {
+            Some(line) => Some((path, line, last)),
+            None => Some((rest, last, 1)),
+        },
+        _ if !rest.is_empty() => Some((rest, last, 1)),
+        _ => None,
+    }
+}
+
+pub fn version() -> String {
+    format!("kk {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// the options and the commands `+<cmd>` takes, from the command lists
+pub fn help() -> String {
+    let mut help = format!(
+        "{}
+
+usage: kk [options] [files...]
+
+  <file>[:line[:col]]  open the file, at the position if given
+  -                    read stdin into a buffer without a file
+  +<cmd>               run a typed command once the files are open, e.g. `+w`
+  -R, --readonly       open the files read-only
+  --headless           run the script without a terminal, write what changed, exit
+  -c, --command <step> a step of the script for --headless, keys like in the
+                       config (`g g d`) or a typed command (`:w`), may be repeated
+  --session <name>     restore the session saved with `:session save <name>`
+  --config <file>      read the config from <file> instead of the config directory
+  --log <file>         write the log to <file>, RUST_LOG sets the level
+  --check-config       print the warnings of the config and exit
+  --dump-keymap        print the keymap in use and exit
+  -V, --version        print the version and exit
+  -h, --help           print this and exit
+  --                   what follows are files
+
+typed commands:
+",
+        version()
+    );
+    let typed: Vec<_> = TYPED_COMMAND_LIST
+        .iter()
+        .map(|c| {
+            let names: Vec<_> = std::iter::once(c.name)
+                .chain(c.aliases.iter().copied())
+                .collect();
+            (names.join(", "), c.doc)
+        })
+        .collect();
+    let commands: Vec<_> = KCommand::STATIC_COMMAND_LIST
+        .iter()
+        .map(|c| (c.name.to_string(), c.doc))
+        .collect();
+    let width = typed
+        .iter()
+        .chain(&commands)
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, doc) in typed {
+        help.push_str(&format!("  {:width$}  {}\n", name, doc));
+    }
+    help.push_str("\ncommands, also run by `+<name>`:\n");
+    for (name, doc) in commands {
+        help.push_str(&format!("  {:width$}  {}\n", name, doc));
+    }
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use super::{help, Args, Input};
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(OsString::from))
+    }
+
+    fn file(path: &str, position: Option<(usize, usize)>) -> Input {
+        Input::File {
+            path: PathBuf::from(path),
+            position,
+        }
+    }
+
+    #[test]
+    fn files_and_positions() {
+        let args = parse(&[
+            "a.rs:3:7", "b.rs:12", "c.rs", "-", "d.rs:x:2", ":4", "--", "-R",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.inputs,
+            vec![
+                file("a.rs", Some((3, 7))),
+                file("b.rs", Some((12, 1))),
+                file("c.rs", None),
+                Input::Stdin,
+                file("d.rs:x", Some((2, 1))),
+                file(":4", None),
+                file("-R", None),
+            ]
+        );
+        assert!(!args.readonly);
+    }
+
+    #[test]
+    fn options_and_commands() {
+        let args = parse(&[
+            "-R",
+            "+w",
+            "+theme default",
+            "--headless",
+            "-c",
+            "d",
+            "--command",
+            ":w",
+            "--config",
+            "kk.toml",
+            "--log",
+            "kk.log",
+            "--session",
+            "work",
+            "a",
+        ])
+        .unwrap();
+        assert!(args.readonly);
+        assert_eq!(args.commands, vec!["w", "theme default"]);
+        assert_eq!(args.script, vec!["d", ":w"]);
+        assert_eq!(args.config, Some(PathBuf::from("kk.toml")));
+        assert_eq!(args.log, Some(PathBuf::from("kk.log")));
+        assert_eq!(args.session.as_deref(), Some("work"));
+        assert_eq!(args.inputs, vec![file("a", None)]);
+
+        assert!(parse(&["--help"]).unwrap().help);
+        assert!(parse(&["-V"]).unwrap().version);
+        assert!(parse(&["--nope"]).is_err());
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["+"]).is_err());
+        assert!(parse(&["-c", "d"]).is_err());
+    }
+
+    #[test]
+    fn help_lists_commands() {
+        let help = help();
+        assert!(help.contains("  quit, q "));
+        assert!(help.contains("  command_palette "));
+        assert!(help.contains("--readonly"));
+    }
+}
@@ -0,0 +1,38 @@
+use super::{insert_char, Context};
+
+pub fn completion(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.trigger_completion();
+    Ok(())
+}
+
+/// Moves through the open menu or opens it. Inserts the key instead when there is no word
+/// before the cursor, so `tab` still indents.
+fn cycle_completion(cx: &mut Context, forward: bool) -> anyhow::Result<()> {
+    if let Some(completion) = &mut cx.editor.completion {
+        completion.move_selection(forward);
+        return Ok(());
+    }
+    let (view, doc) = cx.editor.current_ref();
+    let head = view.selection.primary().head;
+    let after_word = head > 0 && !doc.text().char(head - 1).is_whitespace();
+    match after_word {
+        true => completion(cx),
+        false => insert_char(cx),
+    }
+}
+
+pub fn completion_next(cx: &mut Context) -> anyhow::Result<()> {
+    cycle_completion(cx, true)
+}
+
+pub fn completion_prev(cx: &mut Context) -> anyhow::Result<()> {
+    cycle_completion(cx, false)
+}
+
+/// inserts the key if no menu is open, e.g. a newline when bound to `ret`
+pub fn completion_accept(cx: &mut Context) -> anyhow::Result<()> {
+    match cx.editor.accept_completion() {
+        true => Ok(()),
+        false => insert_char(cx),
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+
+use kk_core::{
+    diagnostic::{Diagnostic, Diagnostics},
+    selection::Selection,
+};
+use ropey::Rope;
+
+use crate::{editor::KEditor, ui::picker::Picker};
+
+use super::Context;
+
+fn goto_diag(cx: &mut Context, next: bool) -> anyhow::Result<()> {
+    let count = cx.count();
+    let (view, doc) = cx.editor.current();
+    let mut pos = view.selection.primary().head;
+    for _ in 0..count {
+        let diagnostic = match next {
+            true => doc.diagnostics().next(pos),
+            false => doc.diagnostics().prev(pos),
+        };
+        match diagnostic {
+            Some(d) => pos = d.from,
+            None => break,
+        }
+    }
+    if pos == view.selection.primary().head {
+        cx.editor.set_status("no more diagnostics");
+        return Ok(());
+    }
+    cx.editor.push_jump();
+    cx.editor.view.selection = Selection::point(pos);
+    Ok(())
+}
+
+pub fn goto_next_diag(cx: &mut Context) -> anyhow::Result<()> {
+    goto_diag(cx, true)
+}
+
+pub fn goto_prev_diag(cx: &mut Context) -> anyhow::Result<()> {
+    goto_diag(cx, false)
+}
+
+/// `path:line:col severity message`, relative to the working directory
+fn diagnostic_label(path: &Path, text: Option<&Rope>, d: &Diagnostic) -> String {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let path = path.strip_prefix(&cwd).unwrap_or(path).display();
+    let position = match text {
+        Some(text) if d.from <= text.len_chars() => {
+            let line = text.char_to_line(d.from);
+            format!("{}:{}", line + 1, d.from - text.line_to_char(line) + 1)
+        }
+        _ => format!("@{}", d.from),
+    };
+    let source = d
+        .source
+        .as_ref()
+        .map(|s| format!(" ({})", s))
+        .unwrap_or_default();
+    format!(
+        "{}:{} {} {}{}",
+        path,
+        position,
+        d.severity.name(),
+        d.message,
+        source
+    )
+}
+
+/// picker with the diagnostics of all files, open or not
+pub fn diagnostics(cx: &mut Context) -> anyhow::Result<()> {
+    let editor = &*cx.editor;
+    let mut files: Vec<(PathBuf, Option<Rope>, &Diagnostics)> = editor
+        .documents
+        .values()
+        .filter(|doc| !doc.diagnostics().is_empty())
+        .filter_map(|doc| {
+            let path = std::path::absolute(doc.path()?).ok()?;
+            Some((path, Some(doc.text().clone()), doc.diagnostics()))
+        })
+        .collect();
+    for (path, diagnostics) in &editor.diagnostics {
+        let text = std::fs::File::open(path)
+            .ok()
+            .and_then(|file| Rope::from_reader(std::io::BufReader::new(file)).ok());
+        files.push((path.clone(), text, diagnostics));
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut labels = Vec::new();
+    let mut targets = Vec::new();
+    for (path, text, diagnostics) in &files {
+        for d in diagnostics.iter() {
+            labels.push(diagnostic_label(path, text.as_ref(), d));
+            targets.push((path.clone(), d.from));
+        }
+    }
+    if labels.is_empty() {
+        cx.editor.set_status("no diagnostics");
+        return Ok(());
+    }
+    cx.editor.picker = Some(Picker::new("diagnostics", labels, move |editor, i| {
+        let (path, pos) = &targets[i];
+        jump_to(editor, path, *pos)
+    }));
+    Ok(())
+}
+
+fn jump_to(editor: &mut KEditor, path: &Path, pos: usize) -> anyhow::Result<()> {
+    editor.push_jump();
+    let id = editor.open(path)?;
+    let pos = pos.min(editor.documents[&id].text().len_chars());
+    editor.view.selection = Selection::point(pos);
+    Ok(())
+}
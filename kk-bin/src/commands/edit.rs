@@ -0,0 +1,160 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use kk_core::{
+    selection::{Range, Selection},
+    transaction::{Assoc, Change, ChangeSet},
+};
+use ropey::Rope;
+
+use super::Context;
+
+/// What typing `c` at `pos` inserts, `None` steps over the closing char that is there. The
+/// first char of a pair inserts both before whitespace, a closing char or the end, quotes not
+/// after a word.
+fn auto_pair(text: &Rope, pairs: &[(char, char)], pos: usize, c: char) -> Option<String> {
+    let next = text.get_char(pos);
+    if next == Some(c) && pairs.iter().any(|(_, close)| *close == c) {
+        return None;
+    }
+    let prev = pos.checked_sub(1).map(|p| text.char(p));
+    let pair = pairs
+        .iter()
+        .find(|(open, _)| *open == c)
+        .filter(|(open, close)| {
+            let next_free = next
+                .is_none_or(|n| n.is_whitespace() || pairs.iter().any(|(_, close)| *close == n));
+            next_free && (open != close || !prev.is_some_and(char::is_alphanumeric))
+        });
+    match pair {
+        Some((open, close)) => Some(format!("{}{}", open, close)),
+        None => Some(c.to_string()),
+    }
+}
+
+/// inserts the key that triggered the command at every cursor, meant to be bound to `any`
+pub fn insert_char(cx: &mut Context) -> anyhow::Result<()> {
+    // unbound shortcuts like `C-x` do not insert anything
+    if cx
+        .key
+        .is_some_and(|k| k.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT))
+    {
+        return Ok(());
+    }
+    let c = match cx.key.map(|k| k.code) {
+        Some(KeyCode::Char(c)) => c,
+        Some(KeyCode::Enter) => '\n',
+        Some(KeyCode::Tab) => '\t',
+        _ => return Ok(()),
+    };
+    let (view, doc) = cx.editor.current_ref();
+    let (text, pairs) = (doc.text(), &cx.editor.settings(doc).auto_pairs);
+    let inserts: Vec<_> = view
+        .selection
+        .iter()
+        .map(|r| (r, auto_pair(text, pairs, r.head, c)))
+        .collect();
+    let changes = ChangeSet::new(
+        inserts
+            .iter()
+            .filter_map(|(r, insert)| Some(Change::insert(r.head, insert.clone()?))),
+    );
+    // the heads end up after the typed char, before the closing one of a pair
+    let ranges = inserts.iter().map(|(r, _)| {
+        let head = changes.map_pos(r.head, Assoc::Before) + 1;
+        match r.is_point() {
+            true => Range::point(head),
+            false => Range::new(changes.map_pos(r.anchor, Assoc::After), head),
+        }
+    });
+    let selection = Selection::new(ranges.collect(), view.selection.primary_index());
+    let readonly = doc.readonly();
+    cx.editor.apply(&changes);
+    if !readonly {
+        cx.editor.view.selection = selection;
+    }
+    Ok(())
+}
+
+/// deletes both chars of an empty auto-pair
+pub fn delete_char_backward(cx: &mut Context) -> anyhow::Result<()> {
+    let (view, doc) = cx.editor.current_ref();
+    let (text, pairs) = (doc.text(), &cx.editor.settings(doc).auto_pairs);
+    let changes = ChangeSet::new(view.selection.iter().filter(|r| r.head > 0).map(|r| {
+        let around = (text.char(r.head - 1), text.get_char(r.head));
+        let empty_pair = pairs.iter().any(|p| around == (p.0, Some(p.1)));
+        match empty_pair {
+            true => Change::delete(r.head - 1, r.head + 1),
+            false => Change::delete(r.head - 1, r.head),
+        }
+    }));
+    cx.editor.apply(&changes);
+    Ok(())
+}
+
+pub fn delete_selection(cx: &mut Context) -> anyhow::Result<()> {
+    let (view, doc) = cx.editor.current();
+    let text = doc.text();
+    let changes = ChangeSet::new(
+        view.selection
+            .iter()
+            .map(|r| Change::delete(r.from(), r.to(text))),
+    );
+    cx.editor.apply(&changes);
+    Ok(())
+}
+
+pub fn undo(cx: &mut Context) -> anyhow::Result<()> {
+    if !(0..cx.count()).all(|_| cx.editor.undo()) {
+        cx.editor.set_status("nothing left to undo");
+    }
+    Ok(())
+}
+
+pub fn redo(cx: &mut Context) -> anyhow::Result<()> {
+    if !(0..cx.count()).all(|_| cx.editor.redo()) {
+        cx.editor.set_status("nothing left to redo");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::selection::Selection;
+
+    use crate::harness::Harness;
+
+    #[tokio::test]
+    async fn auto_pairs() {
+        let mut h = Harness::new("x\n");
+        h.keys("i ( [ a ] ) space \" b \" space ( x").await;
+        // not paired before a word
+        assert_eq!(h.text(), "([a]) \"b\" (xx\n");
+        assert_eq!(h.selection(), &Selection::point(12));
+
+        let mut h = Harness::new("");
+        h.keys("i d o n ' t space ( backspace").await;
+        assert_eq!(h.text(), "don't ");
+
+        let mut h = Harness::with_config("", "auto-pairs = false");
+        h.keys("i ( \"").await;
+        assert_eq!(h.text(), "(\"");
+    }
+
+    #[tokio::test]
+    async fn undo_redo() {
+        let mut h = Harness::new("x\n");
+        h.keys("i a b esc i c esc").await;
+        assert_eq!(h.text(), "abcx\n");
+        h.keys("u").await;
+        assert_eq!(h.text(), "abx\n");
+        assert_eq!(h.selection(), &Selection::point(2));
+        h.keys("u u").await;
+        assert_eq!(h.text(), "x\n");
+        assert_eq!(h.editor.status.as_ref().unwrap().0, "nothing left to undo");
+        h.keys("2 U").await;
+        assert_eq!(h.text(), "abcx\n");
+
+        // a new change drops what was undone
+        h.keys("u d U").await;
+        assert_eq!(h.text(), "ab\n");
+    }
+}
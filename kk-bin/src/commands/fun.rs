@@ -1,12 +1,25 @@
 use anyhow::bail;
+use kk_core::DocumentMode;
 
-pub fn nop() -> anyhow::Result<()> {
+use super::Context;
+
+pub fn nop(_cx: &mut Context) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn escape() -> anyhow::Result<()> {
+pub fn escape(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.set_mode(DocumentMode::Normal);
     Ok(())
 }
-pub fn error() -> anyhow::Result<()> {
+pub fn error(_cx: &mut Context) -> anyhow::Result<()> {
     bail!("Just an error  :)")
 }
+
+pub fn quit(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.quit(false)
+}
+
+pub fn suspend(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.suspend();
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use crossterm::event::KeyCode;
+use kk_core::selection::Selection;
+
+use crate::{
+    editor::{DocumentId, KEditor},
+    history::Jump,
+    ui::picker::Picker,
+};
+
+use super::Context;
+
+fn here(editor: &KEditor) -> Jump {
+    Jump {
+        doc: editor.view.doc,
+        selection: editor.view.selection.clone(),
+    }
+}
+
+pub fn jump_backward(cx: &mut Context) -> anyhow::Result<()> {
+    let here = here(cx.editor);
+    match cx.editor.jumps.backward(cx.count(), here).cloned() {
+        Some(jump) => cx.editor.go_to(&jump),
+        None => cx.editor.set_status("no older jumps"),
+    }
+    Ok(())
+}
+
+pub fn jump_forward(cx: &mut Context) -> anyhow::Result<()> {
+    match cx.editor.jumps.forward(cx.count()).cloned() {
+        Some(jump) => cx.editor.go_to(&jump),
+        None => cx.editor.set_status("no newer jumps"),
+    }
+    Ok(())
+}
+
+pub fn save_jump(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.push_jump();
+    cx.editor.set_status("saved to the jumplist");
+    Ok(())
+}
+
+fn goto_change(cx: &mut Context, older: bool) -> anyhow::Result<()> {
+    let count = cx.count();
+    let id = cx.editor.view.doc;
+    let changelist = cx.editor.changelists.entry(id).or_default();
+    let pos = match older {
+        true => changelist.older(count),
+        false => changelist.newer(count),
+    };
+    match pos {
+        Some(pos) => cx.editor.view.selection = Selection::point(pos),
+        None if older => cx.editor.set_status("no older changes"),
+        None => cx.editor.set_status("no newer changes"),
+    }
+    Ok(())
+}
+
+pub fn goto_older_change(cx: &mut Context) -> anyhow::Result<()> {
+    goto_change(cx, true)
+}
+
+pub fn goto_newer_change(cx: &mut Context) -> anyhow::Result<()> {
+    goto_change(cx, false)
+}
+
+/// the letter typed after the binding, meant to be bound to `any`
+fn mark_name(cx: &Context) -> anyhow::Result<char> {
+    match cx.key.map(|k| k.code) {
+        Some(KeyCode::Char(c)) => Ok(c),
+        _ => anyhow::bail!("marks are letters"),
+    }
+}
+
+/// `a` to `z` for the document, `A` to `Z` across documents
+pub fn set_mark(cx: &mut Context) -> anyhow::Result<()> {
+    let name = mark_name(cx)?;
+    let (view, _) = cx.editor.current_ref();
+    let (doc, pos) = (view.doc, view.selection.primary().head);
+    cx.editor.marks.set(name, doc, pos)?;
+    cx.editor.set_status(format!("mark {} set", name));
+    Ok(())
+}
+
+pub fn goto_mark(cx: &mut Context) -> anyhow::Result<()> {
+    let name = mark_name(cx)?;
+    let Some((doc, pos)) = cx.editor.marks.get(name, cx.editor.view.doc) else {
+        anyhow::bail!("mark {} is not set", name);
+    };
+    jump_to(cx.editor, doc, pos);
+    Ok(())
+}
+
+fn jump_to(editor: &mut KEditor, doc: DocumentId, pos: usize) {
+    editor.push_jump();
+    editor.go_to(&Jump {
+        doc,
+        selection: Selection::point(pos),
+    });
+}
+
+/// `path:line:col  line`, relative to the working directory
+fn position_label(editor: &KEditor, doc: DocumentId, pos: usize) -> String {
+    let doc = &editor.documents[&doc];
+    let path = match doc.path() {
+        Some(path) => {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            path.strip_prefix(&cwd)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        }
+        None => "[scratch]".to_string(),
+    };
+    let text = doc.text();
+    let pos = pos.min(text.len_chars());
+    let line = text.char_to_line(pos);
+    let content = text.line(line).to_string();
+    format!(
+        "{}:{}:{}  {}",
+        path,
+        line + 1,
+        pos - text.line_to_char(line) + 1,
+        content.trim()
+    )
+}
+
+/// picker with the jumps, the latest first
+pub fn jumplist(cx: &mut Context) -> anyhow::Result<()> {
+    let jumps: Vec<_> = cx.editor.jumps.iter().rev().cloned().collect();
+    if jumps.is_empty() {
+        cx.editor.set_status("no jumps");
+        return Ok(());
+    }
+    let labels = jumps
+        .iter()
+        .map(|j| position_label(cx.editor, j.doc, j.selection.primary().head))
+        .collect();
+    cx.editor.picker = Some(Picker::new("jumplist", labels, move |editor, i| {
+        editor.push_jump();
+        editor.go_to(&jumps[i]);
+        Ok(())
+    }));
+    Ok(())
+}
+
+/// picker with the marks of the document and the global ones
+pub fn marks(cx: &mut Context) -> anyhow::Result<()> {
+    let marks: Vec<_> = cx.editor.marks.iter(cx.editor.view.doc).collect();
+    if marks.is_empty() {
+        cx.editor.set_status("no marks");
+        return Ok(());
+    }
+    let labels = marks
+        .iter()
+        .map(|(name, doc, pos)| format!("{}  {}", name, position_label(cx.editor, *doc, *pos)))
+        .collect();
+    cx.editor.picker = Some(Picker::new("marks", labels, move |editor, i| {
+        let (_, doc, pos) = marks[i];
+        jump_to(editor, doc, pos);
+        Ok(())
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::selection::Selection;
+
+    use crate::harness::Harness;
+
+    #[tokio::test]
+    async fn jumps_and_marks() {
+        let mut h = Harness::new("one\ntwo\nthree\nfour\n");
+        h.keys("j m a j j m B").await;
+        assert_eq!(h.selection(), &Selection::point(14));
+        h.keys("' a").await;
+        assert_eq!(h.selection(), &Selection::point(4));
+        h.keys("C-o").await;
+        assert_eq!(h.selection(), &Selection::point(14));
+        h.keys("C-i").await;
+        assert_eq!(h.selection(), &Selection::point(4));
+
+        // marks stay on their text
+        h.keys("k i x x esc").await;
+        h.keys("' B").await;
+        assert_eq!(h.selection(), &Selection::point(16));
+        h.keys("' z").await;
+        assert_eq!(h.selection(), &Selection::point(16));
+        h.keys("' 1").await;
+        assert_eq!(h.selection(), &Selection::point(16));
+    }
+
+    #[tokio::test]
+    async fn changelist() {
+        let mut h = Harness::new("one\ntwo\nthree\n");
+        h.keys("i a esc j j i b esc k").await;
+        assert_eq!(h.text(), "aone\ntwo\ntbhree\n");
+        h.keys("g ;").await;
+        assert_eq!(h.selection(), &Selection::point(11));
+        h.keys("g ;").await;
+        assert_eq!(h.selection(), &Selection::point(1));
+        h.keys("g ,").await;
+        assert_eq!(h.selection(), &Selection::point(11));
+    }
+}
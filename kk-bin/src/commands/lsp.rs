@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use kk_core::selection::Selection;
+use kk_lsp::{
+    lsp::{self, Url},
+    util, Client, OffsetEncoding,
+};
+
+use crate::{
+    editor::KEditor,
+    job::Callback,
+    lsp::{diagnostic_to_lsp, doc_url},
+    ui::{picker::Picker, popup::Popup, prompt::Prompt},
+};
+
+use super::Context;
+
+/// language server and url of the current document and the position of the primary cursor
+fn cursor_context(editor: &KEditor) -> anyhow::Result<(Arc<Client>, Url, lsp::Position)> {
+    let (view, doc) = editor.current_ref();
+    let client = editor
+        .language_server(doc)
+        .ok_or_else(|| anyhow!("no language server running for this document"))?;
+    let url = doc_url(doc).ok_or_else(|| anyhow!("document has no path"))?;
+    let position = util::pos_to_lsp_pos(
+        doc.text(),
+        view.selection.primary().head,
+        client.offset_encoding(),
+    );
+    Ok((client, url, position))
+}
+
+/// opens the file of the location and puts the cursor at its start
+fn jump_to_location(
+    editor: &mut KEditor,
+    location: &lsp::Location,
+    encoding: OffsetEncoding,
+) -> anyhow::Result<()> {
+    let path = location
+        .uri
+        .to_file_path()
+        .map_err(|()| anyhow!("not a file: {}", location.uri))?;
+    editor.push_jump();
+    let id = editor.open(&path)?;
+    let pos = util::lsp_pos_to_pos(editor.documents[&id].text(), location.range.start, encoding)
+        .ok_or_else(|| anyhow!("location is out of bounds"))?;
+    editor.view.selection = Selection::point(pos);
+    Ok(())
+}
+
+/// `path:line:col` and the line itself if the document is open
+fn location_label(editor: &KEditor, location: &lsp::Location) -> String {
+    let path = location
+        .uri
+        .to_file_path()
+        .ok()
+        .map(|path| {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            path.strip_prefix(&cwd)
+                .unwrap_or(&path)
+                .display()
+                .to_string()
+        })
+        .unwrap_or_else(|| location.uri.to_string());
+    let start = location.range.start;
+    let line = editor
+        .document_by_url(&location.uri)
+        .and_then(|id| editor.documents[&id].text().get_line(start.line as usize))
+        .map(|line| line.to_string().trim().to_string())
+        .unwrap_or_default();
+    format!(
+        "{}:{}:{}  {}",
+        path,
+        start.line + 1,
+        start.character + 1,
+        line
+    )
+}
+
+/// jumps right away if there is only one location, otherwise lets the user pick
+fn goto_locations(
+    editor: &mut KEditor,
+    title: &str,
+    locations: Vec<lsp::Location>,
+    encoding: OffsetEncoding,
+) -> anyhow::Result<()> {
+    match &locations[..] {
+        [] => editor.set_status(format!("no {} found", title)),
+        [location] => jump_to_location(editor, location, encoding)?,
+        _ => {
+            let labels = locations
+                .iter()
+                .map(|l| location_label(editor, l))
+                .collect();
+            editor.picker = Some(Picker::new(title, labels, move |editor, i| {
+                jump_to_location(editor, &locations[i], encoding)
+            }));
+        }
+    }
+    Ok(())
+}
+
+fn hover_text(contents: lsp::HoverContents) -> String {
+    let marked = |marked: lsp::MarkedString| match marked {
+        lsp::MarkedString::String(s) => s,
+        lsp::MarkedString::LanguageString(code) => code.value,
+    };
+    match contents {
+        lsp::HoverContents::Scalar(contents) => marked(contents),
+        lsp::HoverContents::Array(contents) => contents
+            .into_iter()
+            .map(marked)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        lsp::HoverContents::Markup(contents) => contents.value,
+    }
+}
+
+pub fn hover(cx: &mut Context) -> anyhow::Result<()> {
+    let (client, url, position) = cursor_context(cx.editor)?;
+    let request = client.hover(url, position);
+    cx.editor.jobs.callback(async move {
+        let hover = request.await?;
+        let callback = move |editor: &mut KEditor| {
+            match hover {
+                Some(hover) => editor.popup = Some(Popup::new(hover_text(hover.contents))),
+                None => editor.set_status("no hover information"),
+            }
+            Ok(())
+        };
+        Ok(Box::new(callback) as Callback)
+    });
+    Ok(())
+}
+
+pub fn goto_definition(cx: &mut Context) -> anyhow::Result<()> {
+    let (client, url, position) = cursor_context(cx.editor)?;
+    let encoding = client.offset_encoding();
+    let request = client.goto_definition(url, position);
+    cx.editor.jobs.callback(async move {
+        let locations = match request.await? {
+            Some(lsp::GotoDefinitionResponse::Scalar(location)) => vec![location],
+            Some(lsp::GotoDefinitionResponse::Array(locations)) => locations,
+            Some(lsp::GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| lsp::Location::new(link.target_uri, link.target_selection_range))
+                .collect(),
+            None => Vec::new(),
+        };
+        let callback =
+            move |editor: &mut KEditor| goto_locations(editor, "definition", locations, encoding);
+        Ok(Box::new(callback) as Callback)
+    });
+    Ok(())
+}
+
+pub fn goto_reference(cx: &mut Context) -> anyhow::Result<()> {
+    let (client, url, position) = cursor_context(cx.editor)?;
+    let encoding = client.offset_encoding();
+    let request = client.references(url, position);
+    cx.editor.jobs.callback(async move {
+        let locations = request.await?.unwrap_or_default();
+        let callback =
+            move |editor: &mut KEditor| goto_locations(editor, "references", locations, encoding);
+        Ok(Box::new(callback) as Callback)
+    });
+    Ok(())
+}
+
+/// opens the command line with `:rename ` typed
+pub fn rename_symbol(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.prompt = Some(Prompt::with_line(":", "rename "));
+    Ok(())
+}
+
+/// renames the symbol under the cursor, used by `:rename`
+pub(super) fn rename_to(cx: &mut Context, new_name: &str) -> anyhow::Result<()> {
+    let (client, url, position) = cursor_context(cx.editor)?;
+    let encoding = client.offset_encoding();
+    let request = client.rename(url, position, new_name.to_string());
+    cx.editor.jobs.callback(async move {
+        let edit = request.await?;
+        let callback = move |editor: &mut KEditor| match edit {
+            Some(edit) => editor.apply_workspace_edit(edit, encoding),
+            None => bail!("nothing to rename"),
+        };
+        Ok(Box::new(callback) as Callback)
+    });
+    Ok(())
+}
+
+pub fn code_action(cx: &mut Context) -> anyhow::Result<()> {
+    let (client, url, _) = cursor_context(cx.editor)?;
+    let encoding = client.offset_encoding();
+    let (view, doc) = cx.editor.current_ref();
+    let (from, to) = {
+        let range = view.selection.primary();
+        (range.from(), range.to(doc.text()))
+    };
+    let range = util::range_to_lsp_range(doc.text(), from, to, encoding);
+    let diagnostics = doc
+        .diagnostics()
+        .iter()
+        .filter(|d| d.from <= to && from <= d.to)
+        .map(|d| diagnostic_to_lsp(doc.text(), d, encoding))
+        .collect();
+
+    let request = client.code_actions(url, range, diagnostics);
+    cx.editor.jobs.callback(async move {
+        let actions = request.await?.unwrap_or_default();
+        let callback = move |editor: &mut KEditor| {
+            if actions.is_empty() {
+                editor.set_status("no code actions");
+                return Ok(());
+            }
+            let labels = actions
+                .iter()
+                .map(|action| match action {
+                    lsp::CodeActionOrCommand::Command(command) => command.title.clone(),
+                    lsp::CodeActionOrCommand::CodeAction(action) => action.title.clone(),
+                })
+                .collect();
+            editor.picker = Some(Picker::new("code actions", labels, move |editor, i| {
+                let (edit, command) = match actions.into_iter().nth(i) {
+                    Some(lsp::CodeActionOrCommand::Command(command)) => (None, Some(command)),
+                    Some(lsp::CodeActionOrCommand::CodeAction(action)) => {
+                        (action.edit, action.command)
+                    }
+                    None => (None, None),
+                };
+                if let Some(edit) = edit {
+                    editor.apply_workspace_edit(edit, encoding)?;
+                }
+                if let Some(command) = command {
+                    let request = client.execute_command(command);
+                    editor.jobs.spawn(async move {
+                        request.await?;
+                        Ok(())
+                    });
+                }
+                Ok(())
+            }));
+            Ok(())
+        };
+        Ok(Box::new(callback) as Callback)
+    });
+    Ok(())
+}
+
+pub fn format(cx: &mut Context) -> anyhow::Result<()> {
+    let (client, url, _) = cursor_context(cx.editor)?;
+    let encoding = client.offset_encoding();
+    let id = cx.editor.view.doc;
+    let version = cx.editor.documents[&id].version();
+    let options = lsp::FormattingOptions {
+        tab_size: 4,
+        insert_spaces: true,
+        ..Default::default()
+    };
+    let request = client.formatting(url, options);
+    cx.editor.jobs.callback(async move {
+        let edits = request.await?.unwrap_or_default();
+        let callback = move |editor: &mut KEditor| {
+            let Some(doc) = editor.documents.get(&id) else {
+                return Ok(());
+            };
+            if doc.version() != version {
+                bail!("document changed while formatting");
+            }
+            let changes = util::edits_to_changes(doc.text(), edits, encoding);
+            editor.apply_to(id, &changes);
+            Ok(())
+        };
+        Ok(Box::new(callback) as Callback)
+    });
+    Ok(())
+}
@@ -1,8 +1,32 @@
+mod completion;
+mod diagnostic;
+mod edit;
 mod fun;
+mod jump;
+mod lsp;
+mod mode;
+mod movement;
+mod palette;
+mod split;
+mod surround;
+pub mod typed;
+use completion::*;
+use diagnostic::*;
+use edit::*;
 use fun::*;
-use std::{sync::Arc};
+use jump::*;
+use lsp::*;
+use mode::*;
+use movement::*;
+use palette::*;
+use split::*;
+use surround::*;
 
+use std::sync::Mutex;
 
+use anyhow::anyhow;
+
+use crate::{editor::KEditor, keymap::input::KeyInput, ui::prompt::PromptEvent};
 
 /// taken from helix_term::commands
 macro_rules! static_commands {
@@ -11,7 +35,7 @@ macro_rules! static_commands {
             #[allow(non_upper_case_globals)]
             pub const $name: Self = Self {
                 name: stringify!($name),
-                fun: $name,
+                fun: CommandFun::Static($name),
                 doc: $doc
             };
         )*
@@ -22,17 +46,123 @@ macro_rules! static_commands {
     }
 }
 
+/// Commands made from the config live as long as the static ones. The same command is only
+/// leaked once, so reloading an unchanged config does not grow this.
+static CONFIG_COMMANDS: Mutex<Vec<&'static KCommand>> = Mutex::new(Vec::new());
+
+fn leak(s: &str) -> &'static str {
+    Box::leak(s.into())
+}
+
+/// the config command `same` accepts, `new` is leaked if there is none yet
+fn intern(same: impl Fn(&KCommand) -> bool, new: impl FnOnce() -> KCommand) -> &'static KCommand {
+    let mut interned = CONFIG_COMMANDS.lock().expect("not poisoned");
+    if let Some(command) = interned.iter().find(|c| same(c)) {
+        return command;
+    }
+    let command = Box::leak(Box::new(new()));
+    interned.push(command);
+    command
+}
+
+/// everything a command may touch
+pub struct Context<'a> {
+    pub editor: &'a mut KEditor,
+    /// key that triggered the command, used by commands bound to `any`
+    pub key: Option<KeyInput>,
+    /// typed before the binding, e.g. `3` in `3 j`
+    pub count: Option<usize>,
+}
+
+impl Context<'_> {
+    /// how often to repeat the command, 1 without a count
+    pub fn count(&self) -> usize {
+        self.count.unwrap_or(1).max(1)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct KCommand {
     pub name: &'static str,
-    fun: fn() -> anyhow::Result<()>,
+    fun: CommandFun,
     pub doc: &'static str,
 }
-pub type ArcKCommand = Arc<KCommand>;
+
+#[derive(Debug, Clone)]
+enum CommandFun {
+    Static(fn(&mut Context) -> anyhow::Result<()>),
+    /// a line of the command prompt without the `:`
+    Typed(&'static str),
+    /// defined in `[commands]` of the config, runs the commands in order
+    Sequence(&'static [&'static KCommand]),
+}
 
 impl KCommand {
-    pub fn exec(&self) -> anyhow::Result<()> {
-        Ok((self.fun)()?)
+    pub fn exec(&self, cx: &mut Context) -> anyhow::Result<()> {
+        match self.fun {
+            CommandFun::Static(fun) => fun(cx),
+            CommandFun::Typed(line) => typed::execute(cx, line, PromptEvent::Validate),
+            CommandFun::Sequence(commands) => {
+                for command in commands {
+                    command.exec(cx)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<&'static KCommand> {
+        Self::STATIC_COMMAND_LIST.iter().find(|c| c.name == name)
+    }
+
+    /// `:w` as a command, fails if there is no such typed command
+    pub fn typed(line: &str) -> anyhow::Result<&'static KCommand> {
+        let line = line.strip_prefix(':').unwrap_or(line);
+        let parts: Vec<_> = line.split_whitespace().collect();
+        let name = parts
+            .first()
+            .ok_or_else(|| anyhow!("empty typed command"))?;
+        let command = typed::TypedCommand::from_name(name)
+            .ok_or_else(|| anyhow!("unknown typed command '{}'", name))?;
+        let name = format!(":{}", parts.join(" "));
+        let doc = match parts.len() {
+            1 => command.doc.to_string(),
+            _ => name.clone(),
+        };
+        Ok(intern(
+            |c| c.name == name && c.doc == doc && matches!(c.fun, CommandFun::Typed(_)),
+            || {
+                let name = leak(&name);
+                Self {
+                    name,
+                    fun: CommandFun::Typed(&name[1..]),
+                    doc: leak(&doc),
+                }
+            },
+        ))
+    }
+
+    /// a named sequence of `commands`, e.g. from `[commands]` of the config
+    pub fn sequence(name: &str, doc: &str, commands: Vec<&'static KCommand>) -> &'static KCommand {
+        let same_commands = |cmds: &[&'static KCommand]| {
+            cmds.len() == commands.len()
+                && cmds
+                    .iter()
+                    .zip(&commands)
+                    .all(|(a, b)| std::ptr::eq(*a, *b))
+        };
+        intern(
+            |c| {
+                c.name == name
+                    && c.doc == doc
+                    && matches!(c.fun, CommandFun::Sequence(cmds) if same_commands(cmds))
+            },
+            || Self {
+                name: leak(name),
+                fun: CommandFun::Sequence(Box::leak(commands.clone().into_boxed_slice())),
+                doc: leak(doc),
+            },
+        )
     }
 
     #[rustfmt::skip]
@@ -40,5 +170,50 @@ impl KCommand {
         escape, "Escape from current mode",
         nop, "Does Nothing",
         error, "Just an error",
+        quit, "Quit the editor",
+        suspend, "Suspend to the shell, `fg` continues",
+        normal_mode, "Enter normal mode",
+        insert_mode, "Insert before the selection",
+        append_mode, "Append after the selection",
+        command_mode, "Enter command mode",
+        command_palette, "Pick a command to run",
+        move_char_left, "Move left",
+        move_char_right, "Move right",
+        move_line_up, "Move up",
+        move_line_down, "Move down",
+        insert_char, "Insert the typed char",
+        delete_char_backward, "Delete the previous char",
+        delete_selection, "Delete the selection",
+        undo, "Undo the last change",
+        redo, "Redo the last undone change",
+        completion, "Open the completion menu",
+        completion_next, "Select the next completion or open the menu",
+        completion_prev, "Select the previous completion or open the menu",
+        completion_accept, "Accept the selected completion",
+        hover, "Show docs for the item under the cursor",
+        goto_definition, "Goto definition",
+        goto_reference, "Goto references",
+        rename_symbol, "Rename the symbol under the cursor",
+        code_action, "Pick a code action for the selection",
+        format, "Format the document with its language server",
+        diagnostics, "Pick a diagnostic in the workspace",
+        goto_next_diag, "Goto the next diagnostic",
+        goto_prev_diag, "Goto the previous diagnostic",
+        match_bracket, "Goto the matching bracket",
+        surround_add, "Surround the selections with the typed char and its pair",
+        surround_replace, "Replace the pair around the selections with the typed char and its pair",
+        surround_delete, "Delete the pair around the selections",
+        jump_backward, "Go back in the jumplist",
+        jump_forward, "Go forward in the jumplist",
+        save_jump, "Save the selection to the jumplist",
+        jumplist, "Pick a jump of the jumplist",
+        goto_older_change, "Goto the previous change in the changelist",
+        goto_newer_change, "Goto the next change in the changelist",
+        set_mark, "Set the mark of the typed letter, uppercase ones work across files",
+        goto_mark, "Goto the mark of the typed letter",
+        marks, "Pick a mark",
+        vsplit, "Show the document again in a new view on the right",
+        close_split, "Close the view, the last one stays",
+        focus_next_split, "Focus the view on the right, the first one after the last",
     );
 }
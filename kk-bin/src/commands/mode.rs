@@ -0,0 +1,34 @@
+use kk_core::{selection::Range, DocumentMode};
+
+use crate::ui::prompt::Prompt;
+
+use super::Context;
+
+pub fn normal_mode(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.set_mode(DocumentMode::Normal);
+    Ok(())
+}
+
+pub fn insert_mode(cx: &mut Context) -> anyhow::Result<()> {
+    let (view, _) = cx.editor.current();
+    view.selection = view
+        .selection
+        .transform(|r| Range::new(r.anchor.max(r.head), r.from()));
+    cx.editor.set_mode(DocumentMode::Insert);
+    Ok(())
+}
+
+pub fn append_mode(cx: &mut Context) -> anyhow::Result<()> {
+    let (view, doc) = cx.editor.current();
+    let len = doc.text().len_chars();
+    view.selection = view
+        .selection
+        .transform(|r| Range::new(r.from(), (r.anchor.max(r.head) + 1).min(len)));
+    cx.editor.set_mode(DocumentMode::Insert);
+    Ok(())
+}
+
+pub fn command_mode(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.prompt = Some(Prompt::new(":"));
+    Ok(())
+}
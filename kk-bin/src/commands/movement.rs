@@ -0,0 +1,88 @@
+use kk_core::{pairs::matching_bracket, selection::Range};
+use ropey::Rope;
+
+use super::Context;
+
+/// moves every cursor count times, collapsing the selections
+fn move_cursors(cx: &mut Context, f: impl Fn(&Rope, usize) -> usize) {
+    let count = cx.count();
+    let (view, doc) = cx.editor.current();
+    let text = doc.text();
+    view.selection = view
+        .selection
+        .transform(|r| Range::point((0..count).fold(r.head, |pos, _| f(text, pos))));
+}
+
+/// keeps the column when moving between lines, clamped to the end of the target line
+fn move_vertically(text: &Rope, pos: usize, down: bool) -> usize {
+    let line = text.char_to_line(pos);
+    let target = match down {
+        true if line + 1 < text.len_lines() => line + 1,
+        false if line > 0 => line - 1,
+        _ => return pos,
+    };
+    let col = pos - text.line_to_char(line);
+    let target_line = text.line(target);
+    let line_end = target_line.len_chars()
+        - target_line
+            .chars_at(target_line.len_chars())
+            .reversed()
+            .take_while(|c| *c == '\n' || *c == '\r')
+            .count();
+    text.line_to_char(target) + col.min(line_end)
+}
+
+pub fn move_char_left(cx: &mut Context) -> anyhow::Result<()> {
+    move_cursors(cx, |_, pos| pos.saturating_sub(1));
+    Ok(())
+}
+
+pub fn move_char_right(cx: &mut Context) -> anyhow::Result<()> {
+    move_cursors(cx, |text, pos| (pos + 1).min(text.len_chars()));
+    Ok(())
+}
+
+pub fn move_line_up(cx: &mut Context) -> anyhow::Result<()> {
+    move_cursors(cx, |text, pos| move_vertically(text, pos, false));
+    Ok(())
+}
+
+pub fn move_line_down(cx: &mut Context) -> anyhow::Result<()> {
+    move_cursors(cx, |text, pos| move_vertically(text, pos, true));
+    Ok(())
+}
+
+/// jumps to the bracket matching the one under each cursor, or the first one after it on the
+/// line
+pub fn match_bracket(cx: &mut Context) -> anyhow::Result<()> {
+    let (view, doc) = cx.editor.current_ref();
+    let (text, syntax) = (doc.text(), doc.syntax());
+    let selection = view
+        .selection
+        .transform(|r| matching_bracket(text, syntax, r.head).map_or(r, Range::point));
+    if selection == view.selection {
+        cx.editor.set_status("no matching bracket");
+        return Ok(());
+    }
+    cx.editor.push_jump();
+    cx.editor.view.selection = selection;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::selection::Selection;
+
+    use crate::harness::Harness;
+
+    #[tokio::test]
+    async fn match_bracket() {
+        let mut h = Harness::new("f(a, [b]) c\n");
+        h.keys("%").await;
+        assert_eq!(h.selection(), &Selection::point(8));
+        h.keys("%").await;
+        assert_eq!(h.selection(), &Selection::point(1));
+        h.keys("C-o").await;
+        assert_eq!(h.selection(), &Selection::point(8));
+    }
+}
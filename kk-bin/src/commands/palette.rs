@@ -0,0 +1,97 @@
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    keymap::tree::{KeyInputTypes, KeymapTree},
+    ui::{picker::Picker, prompt::Prompt},
+};
+
+use super::{typed::TYPED_COMMAND_LIST, Context, KCommand};
+
+/// what picking an entry of the palette does
+enum Action {
+    Run(&'static KCommand),
+    /// typed commands may need arguments, they open the command line
+    Prompt(&'static str),
+}
+
+struct Entry {
+    name: String,
+    /// the bindings in the current mode
+    keys: String,
+    doc: &'static str,
+    action: Action,
+}
+
+/// `space q, C-q`
+fn key_label(tree: Option<&KeymapTree>, names: &[&str]) -> String {
+    let Some(tree) = tree else {
+        return String::new();
+    };
+    let sequences: Vec<_> = names
+        .iter()
+        .flat_map(|name| tree.keys_for(name))
+        .map(|keys| {
+            keys.iter()
+                .map(KeyInputTypes::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    sequences.join(", ")
+}
+
+/// picker with every command, its bindings in the current mode and its doc
+pub fn command_palette(cx: &mut Context) -> anyhow::Result<()> {
+    let trees = cx.editor.keymap.trees();
+    let tree = trees.get(&cx.editor.mode).map(|tree| tree.as_ref());
+
+    let mut entries = Vec::new();
+    for command in cx.editor.commands() {
+        let keys = key_label(tree, &[command.name]);
+        entries.push(Entry {
+            name: command.name.to_string(),
+            keys,
+            doc: command.doc,
+            action: Action::Run(command),
+        });
+    }
+    for command in TYPED_COMMAND_LIST {
+        // bound as `:w`, not by the name of the command
+        let names: Vec<_> = std::iter::once(command.name)
+            .chain(command.aliases.iter().copied())
+            .map(|name| format!(":{}", name))
+            .collect();
+        let names: Vec<_> = names.iter().map(String::as_str).collect();
+        let keys = key_label(tree, &names);
+        entries.push(Entry {
+            name: names[0].to_string(),
+            keys,
+            doc: command.doc,
+            action: Action::Prompt(command.name),
+        });
+    }
+
+    let name_width = entries.iter().map(|e| e.name.width()).max().unwrap_or(0);
+    let keys_width = entries.iter().map(|e| e.keys.width()).max().unwrap_or(0);
+    let labels = entries
+        .iter()
+        .map(|e| format!("{:name_width$}  {:keys_width$}  {}", e.name, e.keys, e.doc))
+        .collect();
+    let actions: Vec<_> = entries.into_iter().map(|e| e.action).collect();
+    cx.editor.picker = Some(Picker::new(
+        "commands",
+        labels,
+        move |editor, i| match actions[i] {
+            Action::Run(command) => command.exec(&mut Context {
+                editor,
+                key: None,
+                count: None,
+            }),
+            Action::Prompt(name) => {
+                editor.prompt = Some(Prompt::with_line(":", format!("{} ", name)));
+                Ok(())
+            }
+        },
+    ));
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use anyhow::bail;
+
+use super::Context;
+
+pub fn vsplit(cx: &mut Context) -> anyhow::Result<()> {
+    cx.editor.vsplit();
+    Ok(())
+}
+
+pub fn close_split(cx: &mut Context) -> anyhow::Result<()> {
+    if !cx.editor.close_split() {
+        bail!("the last view can not be closed");
+    }
+    Ok(())
+}
+
+/// wraps around after the rightmost view
+pub fn focus_next_split(cx: &mut Context) -> anyhow::Result<()> {
+    let count = cx.editor.views().count();
+    let next = (cx.editor.focus() + cx.count()) % count;
+    cx.editor.focus_split(next);
+    Ok(())
+}
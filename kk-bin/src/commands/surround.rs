@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+
+use crossterm::event::KeyCode;
+use kk_core::{
+    pairs::{pair_of, surrounding_pair},
+    transaction::{Change, ChangeSet},
+};
+
+use super::Context;
+
+/// the pair of the char typed after the binding, meant to be bound to `any`
+fn typed_pair(cx: &Context) -> anyhow::Result<(char, char)> {
+    match cx.key.map(|k| k.code) {
+        Some(KeyCode::Char(c)) => Ok(pair_of(c)),
+        _ => anyhow::bail!("type a char to surround with"),
+    }
+}
+
+pub fn surround_add(cx: &mut Context) -> anyhow::Result<()> {
+    let (open, close) = typed_pair(cx)?;
+    let (view, doc) = cx.editor.current_ref();
+    let text = doc.text();
+    let changes = ChangeSet::new(view.selection.iter().flat_map(|r| {
+        [
+            Change::insert(r.from(), open.to_string()),
+            Change::insert(r.to(text), close.to_string()),
+        ]
+    }));
+    cx.editor.apply(&changes);
+    Ok(())
+}
+
+/// The innermost pair around each selection. A pair that shares a char with one before it is
+/// left out, the same char can not be changed twice.
+fn surrounding_pairs(cx: &Context) -> anyhow::Result<Vec<(usize, usize)>> {
+    let (view, doc) = cx.editor.current_ref();
+    let text = doc.text();
+    let mut pairs = view
+        .selection
+        .iter()
+        .map(|r| surrounding_pair(text, doc.syntax(), r.from(), r.to(text)))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow::anyhow!("no pair around the selection"))?;
+    pairs.sort_unstable();
+    let mut taken = HashSet::new();
+    pairs.retain(|(open, close)| {
+        let free = !taken.contains(open) && !taken.contains(close);
+        if free {
+            taken.extend([*open, *close]);
+        }
+        free
+    });
+    Ok(pairs)
+}
+
+pub fn surround_replace(cx: &mut Context) -> anyhow::Result<()> {
+    let (open, close) = typed_pair(cx)?;
+    let pairs = surrounding_pairs(cx)?;
+    let changes = ChangeSet::new(pairs.into_iter().flat_map(|(from, to)| {
+        [
+            Change::replace(from, from + 1, open.to_string()),
+            Change::replace(to, to + 1, close.to_string()),
+        ]
+    }));
+    cx.editor.apply(&changes);
+    Ok(())
+}
+
+pub fn surround_delete(cx: &mut Context) -> anyhow::Result<()> {
+    let pairs = surrounding_pairs(cx)?;
+    let changes = ChangeSet::new(
+        pairs
+            .into_iter()
+            .flat_map(|(from, to)| [Change::delete(from, from + 1), Change::delete(to, to + 1)]),
+    );
+    cx.editor.apply(&changes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::selection::{Range, Selection};
+
+    use crate::harness::Harness;
+
+    #[tokio::test]
+    async fn add_replace_delete() {
+        let mut h = Harness::new("a b\n");
+        h.editor.view.selection = Selection::new(vec![Range::point(0), Range::point(2)], 0);
+        h.keys("s a )").await;
+        assert_eq!(h.text(), "(a) (b)\n");
+        h.keys("s r \"").await;
+        assert_eq!(h.text(), "\"a\" \"b\"\n");
+        h.keys("s a [").await;
+        assert_eq!(h.text(), "\"[a]\" \"[b]\"\n");
+        h.keys("s d s d").await;
+        assert_eq!(h.text(), "a b\n");
+        h.keys("s d").await;
+        assert_eq!(h.text(), "a b\n");
+        assert!(h.editor.status.is_some());
+
+        // the cursor on the closing quote finds the quotes from there to the next string
+        let mut h = Harness::new("\"a\" \"b\"\n");
+        h.editor.view.selection = Selection::new(vec![Range::point(1), Range::point(2)], 0);
+        h.keys("s d").await;
+        assert_eq!(h.text(), "a \"b\"\n");
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::bail;
+use kk_core::syntax::Language;
+use ropey::Rope;
+
+use crate::{
+    editor::KEditor,
+    session::{self, Session},
+    ui::prompt::PromptEvent,
+};
+
+use super::Context;
+
+/// a command typed into the command prompt, e.g. `:theme default`
+#[derive(Debug, Clone)]
+pub struct TypedCommand {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub doc: &'static str,
+    fun: fn(&mut Context, &[&str], PromptEvent) -> anyhow::Result<()>,
+}
+
+impl TypedCommand {
+    /// only validated commands are executed, `Update` and `Abort` are passed to commands with a
+    /// preview
+    pub fn exec(&self, cx: &mut Context, args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+        (self.fun)(cx, args, event)
+    }
+
+    pub fn from_name(name: &str) -> Option<&'static TypedCommand> {
+        TYPED_COMMAND_LIST
+            .iter()
+            .find(|c| c.name == name || c.aliases.contains(&name))
+    }
+}
+
+/// splits the prompt input into the command and its arguments and runs it
+pub fn execute(cx: &mut Context, input: &str, event: PromptEvent) -> anyhow::Result<()> {
+    let mut parts = input.split_whitespace();
+    let name = parts.next();
+    let command = name.and_then(TypedCommand::from_name);
+    // the preview goes once the input no longer names `theme`, whatever happens next
+    if command.is_none_or(|c| c.name != "theme") {
+        cx.editor.unset_theme_preview();
+    }
+    let Some(name) = name else {
+        return Ok(());
+    };
+    let args: Vec<_> = parts.collect();
+    if let Some(command) = command {
+        return command.exec(cx, &args, event);
+    }
+    if event != PromptEvent::Validate {
+        return Ok(());
+    }
+    // bindable commands and the ones of `[commands]` run by name, without arguments
+    match cx.editor.command(name) {
+        Some(_) if !args.is_empty() => bail!("'{}' takes no arguments", name),
+        Some(command) => command.exec(cx),
+        None => bail!("no such command: '{}'", name),
+    }
+}
+
+/// what the first word of the prompt `input` completes to, sorted
+pub fn completions(editor: &KEditor, input: &str) -> Vec<&'static str> {
+    if input.contains(char::is_whitespace) {
+        return Vec::new();
+    }
+    let typed = TYPED_COMMAND_LIST
+        .iter()
+        .flat_map(|c| std::iter::once(c.name).chain(c.aliases.iter().copied()));
+    let mut names: Vec<_> = typed
+        .chain(editor.commands().map(|c| c.name))
+        .filter(|name| name.starts_with(input))
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+fn quit(cx: &mut Context, _args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    match event {
+        PromptEvent::Validate => cx.editor.quit(false),
+        _ => Ok(()),
+    }
+}
+
+/// quits even with modified documents, their changes stay in the swap files
+fn force_quit(cx: &mut Context, _args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    match event {
+        PromptEvent::Validate => cx.editor.quit(true),
+        _ => Ok(()),
+    }
+}
+
+/// previews the theme while typing, restores the previous one on abort
+fn theme(cx: &mut Context, args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    match event {
+        PromptEvent::Abort => cx.editor.unset_theme_preview(),
+        PromptEvent::Update => {
+            if let Some(theme) = args
+                .first()
+                .and_then(|n| cx.editor.theme_loader.load(n).ok())
+            {
+                cx.editor.set_theme_preview(theme);
+            } else {
+                cx.editor.unset_theme_preview();
+            }
+        }
+        PromptEvent::Validate => {
+            let Some(name) = args.first() else {
+                let current = format!("theme: {}", cx.editor.theme.name());
+                cx.editor.set_status(current);
+                return Ok(());
+            };
+            let theme = match cx.editor.theme_loader.load(name) {
+                Ok(theme) => theme,
+                Err(e) => {
+                    cx.editor.unset_theme_preview();
+                    return Err(e);
+                }
+            };
+            cx.editor.set_theme(theme);
+        }
+    }
+    Ok(())
+}
+
+fn rename(cx: &mut Context, args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    if event != PromptEvent::Validate {
+        return Ok(());
+    }
+    match args {
+        [new_name] => super::lsp::rename_to(cx, new_name),
+        _ => bail!("usage: rename <new-name>"),
+    }
+}
+
+/// writes the current document to its file
+fn write(cx: &mut Context, args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    if event != PromptEvent::Validate {
+        return Ok(());
+    }
+    if !args.is_empty() {
+        bail!("usage: write");
+    }
+    let path = cx.editor.write_document(cx.editor.view.doc)?;
+    cx.editor.set_status(format!("written {}", path.display()));
+    Ok(())
+}
+
+fn recover(cx: &mut Context, args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    if event != PromptEvent::Validate {
+        return Ok(());
+    }
+    if !args.is_empty() {
+        bail!("usage: recover");
+    }
+    cx.editor.recover()
+}
+
+fn session(cx: &mut Context, args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    if event != PromptEvent::Validate {
+        return Ok(());
+    }
+    let (action, path) = match args {
+        [action] => (*action, session::project_path(&std::env::current_dir()?)),
+        [action, name] => (*action, session::named_path(name)?),
+        _ => bail!("usage: session save|load [name]"),
+    };
+    match action {
+        "save" => {
+            Session::capture(cx.editor)?.save(&path)?;
+            cx.editor.set_status(format!("saved {}", path.display()));
+        }
+        "load" => Session::load(&path)?.restore(cx.editor)?,
+        _ => bail!("usage: session save|load [name]"),
+    }
+    Ok(())
+}
+
+/// the keymap in use as config, in a new buffer
+fn keymap(cx: &mut Context, _args: &[&str], event: PromptEvent) -> anyhow::Result<()> {
+    if event != PromptEvent::Validate {
+        return Ok(());
+    }
+    let trees = cx.editor.keymap.trees();
+    let dump = crate::config::dump_keys(trees.iter().map(|(mode, tree)| (mode, tree.as_ref())));
+    cx.editor.open_scratch(Rope::from(dump), Some(Language::Toml));
+    Ok(())
+}
+
+pub const TYPED_COMMAND_LIST: &[TypedCommand] = &[
+    TypedCommand {
+        name: "quit",
+        aliases: &["q"],
+        doc: "Quit the editor",
+        fun: quit,
+    },
+    TypedCommand {
+        name: "quit!",
+        aliases: &["q!"],
+        doc: "Quit the editor, also with modified documents",
+        fun: force_quit,
+    },
+    TypedCommand {
+        name: "write",
+        aliases: &["w"],
+        doc: "Write the document to its file",
+        fun: write,
+    },
+    TypedCommand {
+        name: "recover",
+        aliases: &[],
+        doc: "Recover, diff or delete the swap file of the document",
+        fun: recover,
+    },
+    TypedCommand {
+        name: "session",
+        aliases: &[],
+        doc: "Save or load a session, the one of the working directory without a name",
+        fun: session,
+    },
+    TypedCommand {
+        name: "theme",
+        aliases: &[],
+        doc: "Change the theme, previews it while typing",
+        fun: theme,
+    },
+    TypedCommand {
+        name: "rename",
+        aliases: &[],
+        doc: "Rename the symbol under the cursor",
+        fun: rename,
+    },
+    TypedCommand {
+        name: "keymap",
+        aliases: &["show-keymap"],
+        doc: "Show the keymap in use as config",
+        fun: keymap,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::Harness;
+
+    #[tokio::test]
+    async fn quit_with_modified_documents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("a.txt");
+        std::fs::write(&file, "a\n").unwrap();
+        let mut h = Harness::new("");
+        h.editor.open(&file).unwrap();
+        h.keys("i x esc : q ret").await;
+        assert_eq!(h.editor.exit_code(), None);
+        let (status, _) = h.editor.status.clone().unwrap();
+        let refused = "a.txt is modified, :quit! quits anyway";
+        assert!(status.ends_with(refused), "{}", status);
+        h.keys(": q ! ret").await;
+        assert_eq!(h.editor.exit_code(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn theme_preview_is_dropped() {
+        let mut h = Harness::new("");
+        assert_eq!(h.editor.theme.name(), "default");
+        h.keys(": t h e m e space d e f a u l t _ l i g h t").await;
+        assert_eq!(h.editor.theme.name(), "default_light");
+        // into the name, `them default_light` is no command
+        h.keys("home right right right right right backspace").await;
+        assert_eq!(h.editor.theme.name(), "default");
+        h.keys("e").await;
+        assert_eq!(h.editor.theme.name(), "default_light");
+        h.keys("backspace esc").await;
+        assert_eq!(h.editor.theme.name(), "default");
+
+        h.keys(": t h e m e space d e f a u l t _ l i g h t esc").await;
+        assert_eq!(h.editor.theme.name(), "default");
+    }
+}
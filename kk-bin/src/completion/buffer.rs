@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+
+use futures_util::FutureExt;
+use ropey::Rope;
+
+use crate::editor::KEditor;
+
+use super::{word_start, CompletionContext, CompletionFuture, CompletionItem, CompletionSource};
+
+/// words shorter than this are faster typed than completed
+const MIN_WORD_LEN: usize = 3;
+
+/// words of all open documents
+pub struct BufferWords;
+
+fn words(text: &Rope, words: &mut BTreeSet<String>) {
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if word.chars().count() >= MIN_WORD_LEN {
+            words.insert(std::mem::take(&mut word));
+        }
+        word.clear();
+    }
+}
+
+impl CompletionSource for BufferWords {
+    fn name(&self) -> &'static str {
+        "buffer"
+    }
+
+    fn complete(&self, editor: &KEditor, cx: &CompletionContext) -> CompletionFuture {
+        let start = word_start(&cx.text, cx.pos);
+        let mut all = BTreeSet::new();
+        for doc in editor.documents.values() {
+            words(doc.text(), &mut all);
+        }
+        // the word being typed is not a suggestion
+        let mut end = cx.pos;
+        while end < cx.text.len_chars() && {
+            let c = cx.text.char(end);
+            c.is_alphanumeric() || c == '_'
+        } {
+            end += 1;
+        }
+        all.remove(&cx.text.slice(start..end).to_string());
+
+        let items = all
+            .into_iter()
+            .map(|word| CompletionItem::new(word, start))
+            .collect();
+        futures_util::future::ready(Ok(items)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use ropey::Rope;
+
+    #[test]
+    fn words_of_text() {
+        let mut all = BTreeSet::new();
+        super::words(&Rope::from("let foo_bar = baz(a1, x);\nföö"), &mut all);
+        let all: Vec<_> = all.into_iter().collect();
+        assert_eq!(all, vec!["baz", "foo_bar", "föö", "let"]);
+    }
+}
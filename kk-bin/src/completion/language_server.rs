@@ -0,0 +1,77 @@
+use futures_util::FutureExt;
+use kk_lsp::{lsp, util};
+
+use crate::{editor::KEditor, lsp::doc_url};
+
+use super::{
+    snippet::expand, word_start, CompletionContext, CompletionFuture, CompletionItem,
+    CompletionSource,
+};
+
+/// completions of the language server of the document
+pub struct LanguageServer;
+
+fn documentation(documentation: lsp::Documentation) -> String {
+    match documentation {
+        lsp::Documentation::String(s) => s,
+        lsp::Documentation::MarkupContent(content) => content.value,
+    }
+}
+
+impl CompletionSource for LanguageServer {
+    fn name(&self) -> &'static str {
+        "lsp"
+    }
+
+    fn complete(&self, editor: &KEditor, cx: &CompletionContext) -> CompletionFuture {
+        let doc = &editor.documents[&cx.doc];
+        let (Some(client), Some(url)) = (editor.language_server(doc), doc_url(doc)) else {
+            return futures_util::future::ready(Ok(Vec::new())).boxed();
+        };
+        let encoding = client.offset_encoding();
+        let position = util::pos_to_lsp_pos(&cx.text, cx.pos, encoding);
+        let request = client.completion(url, position);
+        let text = cx.text.clone();
+        let word_start = word_start(&text, cx.pos);
+        async move {
+            let items = match request.await? {
+                Some(lsp::CompletionResponse::Array(items)) => items,
+                Some(lsp::CompletionResponse::List(list)) => list.items,
+                None => Vec::new(),
+            };
+            let items = items
+                .into_iter()
+                .map(|item| {
+                    let (new_text, start) = match item.text_edit {
+                        Some(lsp::CompletionTextEdit::Edit(edit)) => {
+                            (edit.new_text, Some(edit.range.start))
+                        }
+                        Some(lsp::CompletionTextEdit::InsertAndReplace(edit)) => {
+                            (edit.new_text, Some(edit.replace.start))
+                        }
+                        None => (item.insert_text.unwrap_or_else(|| item.label.clone()), None),
+                    };
+                    let start = start
+                        .and_then(|start| util::lsp_pos_to_pos(&text, start, encoding))
+                        .unwrap_or(word_start);
+                    let (new_text, cursor) =
+                        match item.insert_text_format == Some(lsp::InsertTextFormat::SNIPPET) {
+                            true => expand(&new_text),
+                            false => (new_text, None),
+                        };
+                    CompletionItem {
+                        label: item.label,
+                        detail: item.detail,
+                        documentation: item.documentation.map(documentation),
+                        text: new_text,
+                        start,
+                        cursor,
+                        filter_text: item.filter_text,
+                    }
+                })
+                .collect();
+            Ok(items)
+        }
+        .boxed()
+    }
+}
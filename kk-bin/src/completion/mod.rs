@@ -0,0 +1,426 @@
+mod buffer;
+mod language_server;
+mod path;
+mod snippet;
+
+use std::path::{Path, PathBuf};
+
+use futures_util::{future::BoxFuture, FutureExt};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use kk_core::{
+    selection::Range,
+    syntax::Language,
+    transaction::{Change, ChangeSet},
+    DocumentMode,
+};
+use log::warn;
+use ropey::Rope;
+
+use crate::{
+    editor::{DocumentId, KEditor},
+    job::Callback,
+};
+
+pub use buffer::BufferWords;
+pub use language_server::LanguageServer;
+pub use path::Paths;
+pub use snippet::Snippets;
+
+/// items of a source, sources that are done right away return a ready future
+pub type CompletionFuture = BoxFuture<'static, anyhow::Result<Vec<CompletionItem>>>;
+
+/// Something that suggests text to insert at the cursor. The editor asks all its sources
+/// and merges their items into one menu.
+pub trait CompletionSource: Send + Sync {
+    /// shown next to items without a detail
+    fn name(&self) -> &'static str;
+
+    fn complete(&self, editor: &KEditor, cx: &CompletionContext) -> CompletionFuture;
+}
+
+impl std::fmt::Debug for dyn CompletionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// where completion was requested
+#[derive(Debug, Clone)]
+pub struct CompletionContext {
+    pub doc: DocumentId,
+    pub text: Rope,
+    pub path: Option<PathBuf>,
+    pub language: Option<Language>,
+    /// the primary cursor
+    pub pos: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionItem {
+    pub label: String,
+    /// shown next to the label, e.g. the type of a symbol
+    pub detail: Option<String>,
+    /// shown in a panel next to the menu while the item is selected
+    pub documentation: Option<String>,
+    /// replaces the text from `start` to the cursor
+    pub text: String,
+    /// where the replaced text starts at the primary cursor
+    pub start: usize,
+    /// the cursor ends up this many chars into `text`, after it if `None`
+    pub cursor: Option<usize>,
+    /// what the typed text is matched against, the label if `None`
+    pub filter_text: Option<String>,
+}
+
+impl CompletionItem {
+    pub fn new(label: impl Into<String>, start: usize) -> Self {
+        let label = label.into();
+        Self {
+            text: label.clone(),
+            label,
+            detail: None,
+            documentation: None,
+            start,
+            cursor: None,
+            filter_text: None,
+        }
+    }
+
+    fn filter_text(&self) -> &str {
+        self.filter_text.as_deref().unwrap_or(&self.label)
+    }
+
+    /// Replaces the typed text at every cursor. Other cursors replace as many chars as
+    /// the primary one, but not past the cursor before them, cursors at the same place
+    /// complete once.
+    pub fn changes(&self, text: &Rope, cursors: &[Range], primary: usize) -> ChangeSet {
+        let typed = primary.saturating_sub(self.start);
+        let mut heads: Vec<_> = cursors
+            .iter()
+            .map(|r| r.head.min(text.len_chars()))
+            .collect();
+        heads.sort_unstable();
+        heads.dedup();
+        let mut prev = 0;
+        ChangeSet::new(heads.into_iter().map(|head| {
+            let from = head.saturating_sub(typed).max(prev);
+            prev = head;
+            Change::replace(from, head, self.text.clone())
+        }))
+    }
+}
+
+/// start of the word that ends at `pos`
+pub fn word_start(text: &Rope, pos: usize) -> usize {
+    let mut start = pos;
+    while start > 0 {
+        let c = text.char(start - 1);
+        if !(c.is_alphanumeric() || c == '_') {
+            break;
+        }
+        start -= 1;
+    }
+    start
+}
+
+/// the open completion menu
+pub struct Completion {
+    id: usize,
+    doc: DocumentId,
+    /// first char that may be replaced, typing whitespace after it closes the menu
+    start: usize,
+    /// sources that did not answer yet
+    pending: usize,
+    items: Vec<(&'static str, CompletionItem)>,
+    /// indices into `items`, best match first
+    matches: Vec<usize>,
+    selected: usize,
+    matcher: SkimMatcherV2,
+}
+
+impl std::fmt::Debug for Completion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Completion")
+            .field("id", &self.id)
+            .field("doc", &self.doc)
+            .field("pending", &self.pending)
+            .field("items", &self.items.len())
+            .field("matches", &self.matches.len())
+            .finish()
+    }
+}
+
+impl Completion {
+    pub fn new(id: usize, doc: DocumentId, start: usize, pending: usize) -> Self {
+        Self {
+            id,
+            doc,
+            start,
+            pending,
+            items: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            matcher: SkimMatcherV2::default(),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn doc(&self) -> DocumentId {
+        self.doc
+    }
+
+    /// items of a source that answered, the same text is only offered once
+    pub fn add(&mut self, source: &'static str, items: Vec<CompletionItem>) {
+        self.pending = self.pending.saturating_sub(1);
+        for item in items {
+            if !self.items.iter().any(|(_, i)| i.text == item.text) {
+                self.start = self.start.min(item.start);
+                self.items.push((source, item));
+            }
+        }
+    }
+
+    /// a source failed or had nothing to offer
+    pub fn skip(&mut self) {
+        self.pending = self.pending.saturating_sub(1);
+    }
+
+    /// Filters the items by what was typed before the cursor. Returns false once the menu
+    /// should close, i.e. the cursor left the completed word or nothing matches anymore.
+    pub fn update(&mut self, text: &Rope, pos: usize) -> bool {
+        if pos < self.start || pos > text.len_chars() {
+            return false;
+        }
+        if text.slice(self.start..pos).chars().any(char::is_whitespace) {
+            return false;
+        }
+        let selected = self.matches.get(self.selected).copied();
+        let mut matches: Vec<_> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, item))| item.start <= pos)
+            .filter_map(|(i, (_, item))| {
+                let typed = text.slice(item.start..pos).to_string();
+                if typed.is_empty() {
+                    return Some((0, i));
+                }
+                let score = self.matcher.fuzzy_match(item.filter_text(), &typed)?;
+                Some((score, i))
+            })
+            .collect();
+        // sources keep their order among equally good matches
+        matches.sort_by_key(|(score, i)| (std::cmp::Reverse(*score), *i));
+        self.matches = matches.into_iter().map(|(_, i)| i).collect();
+        self.selected = selected
+            .and_then(|s| self.matches.iter().position(|i| *i == s))
+            .unwrap_or(0);
+        self.pending > 0 || !self.matches.is_empty()
+    }
+
+    /// matching items and the name of their source
+    pub fn matches(&self) -> impl ExactSizeIterator<Item = (&'static str, &CompletionItem)> {
+        self.matches.iter().map(|i| {
+            let (source, item) = &self.items[*i];
+            (*source, item)
+        })
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> Option<&CompletionItem> {
+        self.matches.get(self.selected).map(|i| &self.items[*i].1)
+    }
+
+    pub fn move_selection(&mut self, forward: bool) {
+        let len = self.matches.len();
+        if len == 0 {
+            return;
+        }
+        self.selected = match forward {
+            true => (self.selected + 1) % len,
+            false => (self.selected + len - 1) % len,
+        };
+    }
+}
+
+impl KEditor {
+    /// asks every source for items at the primary cursor and opens the menu
+    pub fn trigger_completion(&mut self) {
+        let (view, doc) = self.current_ref();
+        let cx = CompletionContext {
+            doc: view.doc,
+            text: doc.text().clone(),
+            path: doc.path().map(Path::to_path_buf),
+            language: doc.language(),
+            pos: view.selection.primary().head.min(doc.text().len_chars()),
+        };
+        let requests: Vec<_> = self
+            .completion_sources
+            .iter()
+            .map(|source| (source.name(), source.complete(self, &cx)))
+            .collect();
+
+        self.next_completion_id += 1;
+        let id = self.next_completion_id;
+        let start = word_start(&cx.text, cx.pos);
+        self.completion = Some(Completion::new(id, cx.doc, start, requests.len()));
+        for (source, mut request) in requests {
+            match (&mut request).now_or_never() {
+                Some(result) => self.complete(id, source, result),
+                None => self.jobs.callback(async move {
+                    let result = request.await;
+                    let callback = move |editor: &mut KEditor| {
+                        editor.complete(id, source, result);
+                        Ok(())
+                    };
+                    Ok(Box::new(callback) as Callback)
+                }),
+            }
+        }
+    }
+
+    /// answer of a source, dropped if the menu it was meant for is gone
+    fn complete(
+        &mut self,
+        id: usize,
+        source: &'static str,
+        result: anyhow::Result<Vec<CompletionItem>>,
+    ) {
+        let Some(completion) = self.completion.as_mut().filter(|c| c.id() == id) else {
+            return;
+        };
+        match result {
+            Ok(items) => completion.add(source, items),
+            Err(e) => {
+                warn!("{} completion failed: {:#}", source, e);
+                completion.skip();
+            }
+        }
+        self.update_completion();
+        if self.completion.is_none() {
+            self.set_status("no completions");
+        }
+    }
+
+    /// refilters the menu after the text or the cursor changed, closes it if it is done
+    pub fn update_completion(&mut self) {
+        let Some(completion) = &mut self.completion else {
+            return;
+        };
+        let doc = &self.documents[&self.view.doc];
+        let open = self.mode == DocumentMode::Insert
+            && self.view.doc == completion.doc()
+            && completion.update(doc.text(), self.view.selection.primary().head);
+        if !open {
+            self.completion = None;
+        }
+    }
+
+    /// applies the selected item at every cursor, false if there was nothing to accept
+    pub fn accept_completion(&mut self) -> bool {
+        let Some(item) = self.completion.take().and_then(|c| c.selected().cloned()) else {
+            return false;
+        };
+        let (view, doc) = self.current_ref();
+        let changes = item.changes(
+            doc.text(),
+            view.selection.ranges(),
+            view.selection.primary().head,
+        );
+        self.apply(&changes);
+        if let Some(cursor) = item.cursor {
+            let back = item.text.chars().count().saturating_sub(cursor);
+            let (view, _) = self.current();
+            view.selection = view
+                .selection
+                .transform(|r| Range::point(r.head.saturating_sub(back)));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::{
+        selection::{Range, Selection},
+        transaction::{Change, ChangeSet},
+        DocumentMode,
+    };
+    use ropey::Rope;
+
+    use crate::{
+        config::Config,
+        editor::{DocumentId, KEditor},
+    };
+
+    use super::{Completion, CompletionItem};
+
+    fn item(label: &str, start: usize) -> CompletionItem {
+        CompletionItem::new(label, start)
+    }
+
+    #[test]
+    fn fuzzy_filter_and_close() {
+        let text = Rope::from("let fb = fo");
+        let mut completion = Completion::new(0, DocumentId::default(), 9, 2);
+        completion.add("a", vec![item("foo_bar", 9), item("fizz", 9)]);
+        assert!(completion.update(&text, 11));
+        let labels: Vec<_> = completion.matches().map(|(_, i)| &i.label).collect();
+        assert_eq!(labels, vec!["foo_bar"]);
+        // duplicates of other sources are dropped
+        completion.add("b", vec![item("foo_bar", 9), item("fob", 9)]);
+        assert!(completion.update(&text, 11));
+        assert_eq!(completion.matches().len(), 2);
+        completion.move_selection(false);
+        assert_eq!(completion.selected().unwrap().label, "fob");
+        completion.move_selection(true);
+        assert_eq!(completion.selected().unwrap().label, "foo_bar");
+
+        assert!(!completion.update(&Rope::from("let fb = fo x"), 13));
+        assert!(!completion.update(&text, 8));
+        assert!(!completion.update(&Rope::from("let fb = fx"), 11));
+    }
+
+    #[test]
+    fn changes_apply_to_every_cursor() {
+        let text = Rope::from("fo\nfo\n");
+        let mut item = item("foo_bar", 0);
+        item.text = "foo()".to_string();
+        let changes = item.changes(&text, &[Range::point(2), Range::point(5)], 2);
+        let mut text = text;
+        changes.apply(&mut text);
+        assert_eq!(text.to_string(), "foo()\nfoo()\n");
+    }
+
+    #[test]
+    fn accept_in_editor() {
+        let mut editor = KEditor::hermetic(Config::load_with("").unwrap());
+        editor.apply(&ChangeSet::new([Change::insert(0, "foo_bar\nfo\nfo")]));
+        editor.view.selection = Selection::new(vec![Range::point(10), Range::point(13)], 0);
+        editor.set_mode(DocumentMode::Insert);
+        editor.trigger_completion();
+        let completion = editor.completion.as_ref().unwrap();
+        assert_eq!(completion.selected().unwrap().label, "foo_bar");
+        assert!(editor.accept_completion());
+        let (view, doc) = editor.current_ref();
+        assert_eq!(doc.text().to_string(), "foo_bar\nfoo_bar\nfoo_bar");
+        assert_eq!(view.selection.primary().head, 15);
+        assert!(!editor.accept_completion());
+    }
+
+    #[test]
+    fn close_cursors_do_not_overlap() {
+        let text = Rope::from("ab ab");
+        let cursors = [Range::point(1), Range::point(2), Range::point(2), Range::point(5)];
+        let changes = item("abc", 3).changes(&text, &cursors, 5);
+        let mut text = text;
+        changes.apply(&mut text);
+        assert_eq!(text.to_string(), "abcabc abc");
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use futures_util::FutureExt;
+use ropey::Rope;
+
+use crate::editor::KEditor;
+
+use super::{CompletionContext, CompletionFuture, CompletionItem, CompletionSource};
+
+/// entries of the directory typed before the cursor, e.g. `./src/` or `~/.config/`
+pub struct Paths;
+
+/// the path before `pos` and where it starts, paths are delimited by whitespace and quotes
+fn typed_path(text: &Rope, pos: usize) -> (usize, String) {
+    let mut start = pos;
+    while start > 0 {
+        let c = text.char(start - 1);
+        if c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '(' | ')' | '<' | '>' | '=' | ',') {
+            break;
+        }
+        start -= 1;
+    }
+    (start, text.slice(start..pos).to_string())
+}
+
+/// directory to list, relative paths are relative to the document
+fn resolve(dir: &str, doc_path: Option<&Path>) -> Option<PathBuf> {
+    if let Some(rest) = dir.strip_prefix("~/") {
+        return std::env::var_os("HOME").map(|home| PathBuf::from(home).join(rest));
+    }
+    let dir = Path::new(dir);
+    if dir.is_absolute() {
+        return Some(dir.to_path_buf());
+    }
+    let base = doc_path
+        .and_then(|p| std::path::absolute(p).ok())
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+        .or_else(|| std::env::current_dir().ok())?;
+    Some(base.join(dir))
+}
+
+impl CompletionSource for Paths {
+    fn name(&self) -> &'static str {
+        "path"
+    }
+
+    fn complete(&self, _editor: &KEditor, cx: &CompletionContext) -> CompletionFuture {
+        let (start, path) = typed_path(&cx.text, cx.pos);
+        let Some(slash) = path.rfind('/') else {
+            return futures_util::future::ready(Ok(Vec::new())).boxed();
+        };
+        let dir = &path[..=slash];
+        // items replace the file name, the directory stays
+        let start = start + dir.chars().count();
+        let Some(dir) = resolve(dir, cx.path.as_deref()) else {
+            return futures_util::future::ready(Ok(Vec::new())).boxed();
+        };
+        async move {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            let mut items = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+                let mut item = CompletionItem::new(name, start);
+                if is_dir {
+                    item.text.push('/');
+                    item.detail = Some("dir".to_string());
+                }
+                items.push(item);
+            }
+            items.sort_by(|a, b| a.label.cmp(&b.label));
+            Ok(items)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use ropey::Rope;
+
+    #[test]
+    fn typed_paths() {
+        let text = Rope::from("include(\"./src/ma");
+        assert_eq!(super::typed_path(&text, 17), (9, "./src/ma".to_string()));
+        let text = Rope::from("cat /etc");
+        assert_eq!(super::typed_path(&text, 8), (4, "/etc".to_string()));
+        assert_eq!(
+            super::resolve("./src/", Some(Path::new("/tmp/kk/main.rs"))),
+            Some(PathBuf::from("/tmp/kk/./src/"))
+        );
+        assert_eq!(super::resolve("/etc/", None), Some(PathBuf::from("/etc/")));
+    }
+}
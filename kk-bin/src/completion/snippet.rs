@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use futures_util::FutureExt;
+use kk_core::syntax::Language;
+
+use crate::editor::KEditor;
+
+use super::{word_start, CompletionContext, CompletionFuture, CompletionItem, CompletionSource};
+
+/// snippets from `[lang.<name>.snippets]`, keyed by the word they expand
+pub struct Snippets {
+    snippets: HashMap<Language, Vec<(String, String)>>,
+}
+
+impl Snippets {
+    pub fn new(snippets: HashMap<Language, Vec<(String, String)>>) -> Self {
+        Self { snippets }
+    }
+}
+
+/// Text of a snippet body and where the cursor goes. Tabstops `$1` and `${1:default}`
+/// become their default text, the cursor goes to the first one or to `$0`.
+pub(super) fn expand(body: &str) -> (String, Option<usize>) {
+    let mut text = String::new();
+    let mut stops: Vec<(usize, usize)> = Vec::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('$' | '\\' | '}')) => text.push(chars.next().expect("peeked")),
+            ('$', Some(c)) if c.is_ascii_digit() => {
+                let mut n = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    n.push(*c);
+                    chars.next();
+                }
+                stops.push((n.parse().unwrap_or(0), text.chars().count()));
+            }
+            ('$', Some('{')) => {
+                chars.next();
+                let mut n = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    n.push(*c);
+                    chars.next();
+                }
+                stops.push((n.parse().unwrap_or(0), text.chars().count()));
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                }
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    text.push(c);
+                }
+            }
+            (c, _) => text.push(c),
+        }
+    }
+    let cursor = stops
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .min()
+        .or_else(|| stops.iter().find(|(n, _)| *n == 0))
+        .map(|(_, pos)| *pos);
+    (text, cursor)
+}
+
+impl CompletionSource for Snippets {
+    fn name(&self) -> &'static str {
+        "snippet"
+    }
+
+    fn complete(&self, _editor: &KEditor, cx: &CompletionContext) -> CompletionFuture {
+        let start = word_start(&cx.text, cx.pos);
+        let items = cx
+            .language
+            .and_then(|language| self.snippets.get(&language))
+            .into_iter()
+            .flatten()
+            .map(|(trigger, body)| {
+                let (text, cursor) = expand(body);
+                let mut item = CompletionItem::new(trigger.clone(), start);
+                item.detail = Some("snippet".to_string());
+                item.documentation = Some(text.clone());
+                item.text = text;
+                item.cursor = cursor;
+                item
+            })
+            .collect();
+        futures_util::future::ready(Ok(items)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+
+    #[test]
+    fn expand_tabstops() {
+        assert_eq!(
+            expand("fn ${1:name}($2) {\n    $0\n}"),
+            ("fn name() {\n    \n}".to_string(), Some(3))
+        );
+        assert_eq!(expand("dbg!($0)"), ("dbg!()".to_string(), Some(5)));
+        assert_eq!(expand("costs \\$5"), ("costs $5".to_string(), None));
+    }
+}
@@ -1,16 +1,1188 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
 
-use kk_core::DocumentMode;
-use log::info;
+use anyhow::{anyhow, bail, Context};
+use kk_core::{pairs::DEFAULT_PAIRS, syntax::Language, DocumentMode};
+use log::{info, warn};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    time::Instant,
+};
+use toml::Value;
 
+use crate::{
+    commands::KCommand,
+    keymap::{
+        check::{self, KeymapWarning, WarningKind},
+        input::KeyInput,
+        map::DEFAULT_TIMEOUT,
+        tree::{KeyInputTypes, KeymapTree},
+    },
+};
+
+/// config shipped with the binary, the user config is merged on top of it
+pub const BASE_CONFIG: &str = include_str!("../../.config/based.toml");
+
+/// key that matches every key not bound otherwise
+const ANY_KEY: &str = "any";
+
+/// names the prefix of the table it is in, e.g. `[keys.normal.space] label = "leader"`
+const LABEL_KEY: &str = "label";
+
+/// binds the prefix of the table it is in on its own, it then waits for `key-timeout`
+const COMMAND_KEY: &str = "command";
+
+const DEFAULT_WHICH_KEY_DELAY: Duration = Duration::from_millis(400);
+
+#[derive(Debug)]
 pub struct Config {
-    pub keys: HashMap<DocumentMode, String>,
+    pub theme: Option<String>,
+    pub keys: HashMap<DocumentMode, KeymapTree>,
+    /// how long a started key sequence waits before its continuations are shown
+    pub which_key_delay: Duration,
+    /// how long a key bound on its own and as a prefix waits for the rest of a sequence
+    pub key_timeout: Duration,
+    /// clicks, drags and scrolling, off leaves the mouse to the terminal
+    pub mouse: bool,
+    /// save the session of the working directory on quit and restore it when started without
+    /// files
+    pub auto_session: bool,
+    pub settings: Settings,
+    /// settings from `[lang.<name>]`
+    pub languages: HashMap<Language, LanguageConfig>,
+    /// `[commands]`, named sequences of commands that can be bound like the static ones
+    pub commands: Vec<&'static KCommand>,
+    /// keymap problems that did not stop it from loading, see `kk --check-config`
+    pub warnings: Vec<KeymapWarning>,
+    /// keys of the project config that only the user config may set, they were left out
+    pub ignored: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LanguageConfig {
+    pub language_server: Option<LanguageServerConfig>,
+    /// `[lang.<name>.snippets]`, the word to complete and the snippet body
+    pub snippets: Vec<(String, String)>,
+    /// the global settings with the ones set for the language
+    pub settings: Settings,
+    /// the trees of `[keys]` with `[lang.<name>.keys]` merged in, empty if the language has no
+    /// bindings of its own
+    pub keys: HashMap<DocumentMode, KeymapTree>,
+}
+
+/// editor options, set at the top of the config and overridden in `[lang.<name>]`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// long lines continue on the next row instead of being cut off
+    pub soft_wrap: bool,
+    /// typing the first char of a pair in insert mode inserts the second one too
+    pub auto_pairs: Vec<(char, char)>,
+}
+
+impl Settings {
+    /// `defaults` with the options set in `table`
+    fn parse(table: &Value, defaults: &Settings) -> anyhow::Result<Self> {
+        let soft_wrap = match table.get("soft-wrap") {
+            Some(Value::Boolean(soft_wrap)) => *soft_wrap,
+            Some(_) => bail!("'soft-wrap' has to be true or false"),
+            None => defaults.soft_wrap,
+        };
+        let auto_pairs = match table.get("auto-pairs") {
+            Some(Value::Boolean(true)) => DEFAULT_PAIRS.to_vec(),
+            Some(Value::Boolean(false)) => Vec::new(),
+            Some(Value::Table(pairs)) => pairs
+                .iter()
+                .map(|(open, close)| {
+                    let close = close
+                        .as_str()
+                        .ok_or_else(|| anyhow!("auto-pairs have to be strings"))?;
+                    Ok((single_char(open)?, single_char(close)?))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Some(_) => bail!("'auto-pairs' has to be true, false or a table of pairs"),
+            None => defaults.auto_pairs.clone(),
+        };
+        Ok(Self {
+            soft_wrap,
+            auto_pairs,
+        })
+    }
+}
+
+fn single_char(s: &str) -> anyhow::Result<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => bail!("auto-pairs are pairs of single chars, not '{}'", s),
+    }
+}
+
+/// `language-server = { command = "rust-analyzer", args = [] }`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
 }
 
 impl Config {
-    pub fn load(global_config: &str) -> anyhow::Result<()> {
-        let global_config = toml::from_str::<toml::Value>(global_config)?;  
-        info!("{:#?}", global_config);
+    pub fn load(global_config: &str) -> anyhow::Result<Self> {
+        let global_config = toml::from_str::<Value>(global_config)?;
+        Self::from_value(global_config)
+    }
+
+    /// base config with `user` merged on top like a user config file
+    #[cfg(test)]
+    pub fn load_with(user: &str) -> anyhow::Result<Self> {
+        let base = toml::from_str::<Value>(BASE_CONFIG)?;
+        Self::from_layers(base, vec![toml::from_str::<Value>(user)?])
+    }
+
+    /// base config with the user config and then the project config merged on top, if there
+    /// are any
+    pub fn load_user() -> anyhow::Result<Self> {
+        Self::load_files(&config_file(), &project_config_file())
+    }
+
+    /// like `load_user` with the given files, the project config is restricted, a repository
+    /// that was just cloned should not be able to start programs
+    pub fn load_files(user: &Path, project: &Path) -> anyhow::Result<Self> {
+        let base = toml::from_str::<Value>(BASE_CONFIG)?;
+        let mut layers = Vec::new();
+        let mut ignored = Vec::new();
+        for path in [user, project] {
+            let layer = match std::fs::read_to_string(path) {
+                Ok(layer) => layer,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to read {}", path.display()))
+                }
+            };
+            info!("loading config from {}", path.display());
+            let mut layer = toml::from_str::<Value>(&layer)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            if path == project {
+                ignored = restrict_project_layer(&mut layer);
+            }
+            layers.push(layer);
+        }
+        let mut config = Self::from_layers(base, layers)?;
+        config.ignored = ignored;
+        Ok(config)
+    }
+
+    /// `layers` merged on top of `base` in order, warns about bindings that clash with the
+    /// layers below them
+    fn from_layers(base: Value, layers: Vec<Value>) -> anyhow::Result<Self> {
+        // a layer may bind commands defined in a layer above it
+        let commands = layers.iter().fold(base.clone(), |merged, layer| {
+            merge_toml_values(merged, layer.clone())
+        });
+        let commands = parse_command_table(&commands)?;
+        let mut merged = base;
+        let mut warnings = Vec::new();
+        for layer in layers {
+            let below = parse_keys(&merged, &commands, &mut Vec::new())?;
+            let keys = parse_keys(&layer, &commands, &mut Vec::new())?;
+            for (mode, tree) in &keys {
+                if let Some(below) = below.get(mode) {
+                    warnings.extend(check::check_layers(mode, below, tree));
+                }
+            }
+            merged = merge_toml_values(merged, layer);
+        }
+        let mut config = Self::from_value(merged)?;
+        config.warnings.extend(warnings);
+        config.warnings.sort_by_cached_key(|w| w.to_string());
+        Ok(config)
+    }
+
+    fn from_value(value: Value) -> anyhow::Result<Self> {
+        let theme = match value.get("theme") {
+            Some(Value::String(theme)) => Some(theme.clone()),
+            Some(_) => bail!("'theme' has to be a string"),
+            None => None,
+        };
+        let which_key_delay = parse_millis(&value, "which-key-delay")?
+            .unwrap_or(DEFAULT_WHICH_KEY_DELAY);
+        let key_timeout = parse_millis(&value, "key-timeout")?.unwrap_or(DEFAULT_TIMEOUT);
+        let mouse = parse_bool(&value, "mouse")?.unwrap_or(true);
+        let auto_session = parse_bool(&value, "auto-session")?.unwrap_or(false);
+        let settings = Settings::parse(&value, &Settings::default())?;
+        let commands = parse_command_table(&value)?;
+
+        let mut warnings = Vec::new();
+        let keys = parse_keys(&value, &commands, &mut warnings)?;
+        for (mode, tree) in &keys {
+            warnings.extend(check::check_tree(mode, tree));
+        }
+
+        let mut languages = HashMap::new();
+        if let Some(langs) = value.get("lang") {
+            let langs = langs
+                .as_table()
+                .ok_or_else(|| anyhow!("'lang' has to be a table"))?;
+            for (name, lang) in langs {
+                let language = Language::from_name(name)
+                    .ok_or_else(|| anyhow!("unknown language '{}'", name))?;
+                let config = parse_language_config(lang, &value, &settings, &commands)
+                    .with_context(|| format!("invalid settings for '{}'", name))?;
+                // only what the language binds itself, the rest is linted above
+                let mut lang_warnings = Vec::new();
+                for (mode, tree) in &parse_keys(lang, &commands, &mut lang_warnings)? {
+                    lang_warnings.extend(check::check_tree(mode, tree));
+                    if let Some(global) = keys.get(mode) {
+                        lang_warnings.extend(check::check_layers(mode, global, tree));
+                    }
+                }
+                warnings.extend(lang_warnings.into_iter().map(|w| w.in_language(language)));
+                languages.insert(language, config);
+            }
+        }
+        warnings.sort_by_cached_key(|w| w.to_string());
+        Ok(Self {
+            theme,
+            keys,
+            which_key_delay,
+            key_timeout,
+            mouse,
+            auto_session,
+            settings,
+            languages,
+            commands,
+            warnings,
+            ignored: Vec::new(),
+        })
+    }
+}
+
+/// drops what the project config may not set from `layer` and returns the dotted keys of it
+fn restrict_project_layer(layer: &mut Value) -> Vec<String> {
+    let mut ignored = Vec::new();
+    if let Some(langs) = layer.get_mut("lang").and_then(Value::as_table_mut) {
+        for (name, lang) in langs {
+            let Some(lang) = lang.as_table_mut() else {
+                continue;
+            };
+            if lang.remove("language-server").is_some() {
+                ignored.push(format!("lang.{}.language-server", name));
+            }
+        }
+    }
+    ignored
+}
+
+fn parse_keys(
+    value: &Value,
+    commands: &[&'static KCommand],
+    warnings: &mut Vec<KeymapWarning>,
+) -> anyhow::Result<HashMap<DocumentMode, KeymapTree>> {
+    let mut keys = HashMap::new();
+    if let Some(modes) = value.get("keys") {
+        let modes = modes
+            .as_table()
+            .ok_or_else(|| anyhow!("'keys' has to be a table"))?;
+        for (mode, bindings) in modes {
+            let mode = parse_mode(mode)?;
+            let mut tree = KeymapTree::new();
+            insert_bindings(
+                &mode,
+                &mut tree,
+                &mut Vec::new(),
+                bindings,
+                commands,
+                warnings,
+            )?;
+            keys.insert(mode, tree);
+        }
+    }
+    Ok(keys)
+}
+
+/// `kk --check-config`, prints what is off with the keymap and fails unless it is only notes
+pub fn check_config() -> anyhow::Result<i32> {
+    let config = Config::load_user()?;
+    for key in &config.ignored {
+        println!("warning: `{}` is ignored in {}", key, project_config_file().display());
+    }
+    for warning in &config.warnings {
+        match warning.is_note() {
+            true => println!("note: {}", warning),
+            false => println!("warning: {}", warning),
+        }
+    }
+    let failed = config.warnings.iter().filter(|w| !w.is_note()).count() + config.ignored.len();
+    match failed {
+        0 => {
+            println!("{} is fine", config_file().display());
+            Ok(0)
+        }
+        n => {
+            println!("{} warnings", n);
+            Ok(1)
+        }
+    }
+}
+
+fn parse_millis(value: &Value, key: &str) -> anyhow::Result<Option<Duration>> {
+    match value.get(key) {
+        Some(Value::Integer(ms)) if *ms >= 0 => Ok(Some(Duration::from_millis(*ms as u64))),
+        Some(_) => bail!("'{}' has to be a number of milliseconds", key),
+        None => Ok(None),
+    }
+}
+
+fn parse_bool(value: &Value, key: &str) -> anyhow::Result<Option<bool>> {
+    match value.get(key) {
+        Some(Value::Boolean(b)) => Ok(Some(*b)),
+        Some(_) => bail!("'{}' has to be true or false", key),
+        None => Ok(None),
+    }
+}
+
+/// `global` is the whole config the language settings are part of
+fn parse_language_config(
+    settings: &Value,
+    global: &Value,
+    global_settings: &Settings,
+    commands: &[&'static KCommand],
+) -> anyhow::Result<LanguageConfig> {
+    let table = settings
+        .as_table()
+        .ok_or_else(|| anyhow!("language settings have to be a table"))?;
+    let language_server = table
+        .get("language-server")
+        .map(|server| server.clone().try_into::<LanguageServerConfig>())
+        .transpose()?;
+    let snippets = match table.get("snippets") {
+        Some(Value::Table(snippets)) => snippets
+            .iter()
+            .map(|(trigger, body)| match body {
+                Value::String(body) => Ok((trigger.clone(), body.clone())),
+                _ => Err(anyhow!("snippet '{}' has to be a string", trigger)),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        Some(_) => bail!("'snippets' has to be a table"),
+        None => Vec::new(),
+    };
+    let keys = match table.get("keys") {
+        Some(lang_keys) => {
+            let global_keys = global
+                .get("keys")
+                .cloned()
+                .unwrap_or_else(|| Value::Table(Default::default()));
+            let mut merged = toml::map::Map::new();
+            merged.insert(
+                "keys".to_string(),
+                merge_toml_values(global_keys, lang_keys.clone()),
+            );
+            parse_keys(&Value::Table(merged), commands, &mut Vec::new())?
+        }
+        None => HashMap::new(),
+    };
+    Ok(LanguageConfig {
+        language_server,
+        snippets,
+        settings: Settings::parse(settings, global_settings)?,
+        keys,
+    })
+}
+
+fn parse_mode(mode: &str) -> anyhow::Result<DocumentMode> {
+    match mode {
+        "normal" => Ok(DocumentMode::Normal),
+        "insert" => Ok(DocumentMode::Insert),
+        invalid => bail!("invalid mode '{}'", invalid),
+    }
+}
+
+/// nested tables are key sequences: `[keys.normal.space] w = "..."` binds `space w`
+fn insert_bindings(
+    mode: &DocumentMode,
+    tree: &mut KeymapTree,
+    sequence: &mut Vec<KeyInputTypes>,
+    bindings: &Value,
+    commands: &[&'static KCommand],
+    warnings: &mut Vec<KeymapWarning>,
+) -> anyhow::Result<()> {
+    let table = bindings
+        .as_table()
+        .ok_or_else(|| anyhow!("key bindings have to be a table"))?;
+    for (key, value) in table {
+        if key == LABEL_KEY && !sequence.is_empty() {
+            let label = value
+                .as_str()
+                .ok_or_else(|| anyhow!("'{}' has to be a string", LABEL_KEY))?;
+            tree.set_label(sequence, label.to_string());
+            continue;
+        }
+        if key == COMMAND_KEY && !sequence.is_empty() {
+            let value = parse_commands(value, commands)
+                .with_context(|| format!("invalid '{}'", COMMAND_KEY))?;
+            bind(mode, tree, sequence, value, warnings);
+            continue;
+        }
+        let key = match key.as_str() {
+            ANY_KEY => KeyInputTypes::MATCH_ALL,
+            key => KeyInputTypes::MATCH(KeyInput::from_str(key)?),
+        };
+        sequence.push(key.clone());
+        match value {
+            Value::Table(_) => insert_bindings(mode, tree, sequence, value, commands, warnings)?,
+            _ => {
+                let value = parse_commands(value, commands)
+                    .with_context(|| format!("invalid binding for '{}'", key))?;
+                bind(mode, tree, sequence, value, warnings);
+            }
+        }
+        sequence.pop();
+    }
+    Ok(())
+}
+
+fn bind(
+    mode: &DocumentMode,
+    tree: &mut KeymapTree,
+    sequence: &[KeyInputTypes],
+    commands: Vec<&'static KCommand>,
+    warnings: &mut Vec<KeymapWarning>,
+) {
+    // the same key spelled differently, e.g. `S-a` and `A`
+    match tree.get_chain(sequence) {
+        Some((replaced, _)) if !replaced.is_empty() => {
+            let kind = WarningKind::Duplicate {
+                replaced: check::names(&replaced),
+                by: check::names(&commands),
+            };
+            warnings.push(KeymapWarning::new(mode.clone(), sequence.to_vec(), kind));
+        }
+        _ => {}
+    }
+    tree.insert_chain(sequence.to_vec(), commands);
+}
+
+/// a command name or an array of them
+fn command_names(value: &Value) -> anyhow::Result<Vec<&str>> {
+    match value {
+        Value::String(name) => Ok(vec![name]),
+        Value::Array(names) if !names.is_empty() => names
+            .iter()
+            .map(|name| {
+                name.as_str()
+                    .ok_or_else(|| anyhow!("commands have to be strings"))
+            })
+            .collect(),
+        _ => bail!("has to be a command or an array of commands"),
+    }
+}
+
+/// a binding, the commands run in order. `:w` runs a typed command, `commands` are the ones
+/// of `[commands]`
+fn parse_commands(
+    value: &Value,
+    commands: &[&'static KCommand],
+) -> anyhow::Result<Vec<&'static KCommand>> {
+    command_names(value)?
+        .into_iter()
+        .map(|name| {
+            if name.starts_with(':') {
+                return KCommand::typed(name);
+            }
+            commands
+                .iter()
+                .copied()
+                .find(|c| c.name == name)
+                .or_else(|| KCommand::from_name(name))
+                .ok_or_else(|| anyhow!("unknown command '{}'", name))
+        })
+        .collect()
+}
+
+/// `[commands] save-quit = [":w", ":q"]`, with a doc for which-key and listings:
+/// `save-quit = { command = [":w", ":q"], doc = "Write and quit" }`
+fn parse_command_table(value: &Value) -> anyhow::Result<Vec<&'static KCommand>> {
+    let Some(table) = value.get("commands") else {
+        return Ok(Vec::new());
+    };
+    let table = table
+        .as_table()
+        .ok_or_else(|| anyhow!("'commands' has to be a table"))?;
+    let mut defined = Vec::new();
+    for name in table.keys() {
+        define_command(name, table, &mut defined, &mut Vec::new())
+            .with_context(|| format!("invalid command '{}'", name))?;
+    }
+    Ok(defined)
+}
+
+/// commands of the table may use each other, they are defined before the ones using them
+fn define_command(
+    name: &str,
+    table: &toml::map::Map<String, Value>,
+    defined: &mut Vec<&'static KCommand>,
+    defining: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if defined.iter().any(|c| c.name == name) {
+        return Ok(());
+    }
+    if defining.iter().any(|n| n == name) {
+        bail!("'{}' uses itself", name);
+    }
+    if KCommand::from_name(name).is_some() || name.starts_with(':') {
+        bail!("'{}' is taken by a built-in command", name);
+    }
+    let (value, doc) = match &table[name] {
+        Value::Table(command) => {
+            let value = command
+                .get(COMMAND_KEY)
+                .ok_or_else(|| anyhow!("'{}' is missing", COMMAND_KEY))?;
+            let doc = match command.get("doc") {
+                Some(Value::String(doc)) => Some(doc.clone()),
+                Some(_) => bail!("'doc' has to be a string"),
+                None => None,
+            };
+            (value, doc)
+        }
+        value => (value, None),
+    };
+    let names = command_names(value)?;
+    defining.push(name.to_string());
+    for name in &names {
+        if table.contains_key(*name) {
+            define_command(name, table, defined, defining)?;
+        }
+    }
+    defining.pop();
+    let commands = parse_commands(value, defined)?;
+    let doc = doc.unwrap_or_else(|| names.join(", "));
+    defined.push(KCommand::sequence(name, &doc, commands));
+    Ok(())
+}
+
+/// the keymap as `[keys.<mode>]` tables, `Config` reads it back to the same trees
+pub fn dump_keys<'a>(
+    trees: impl IntoIterator<Item = (&'a DocumentMode, &'a KeymapTree)>,
+) -> String {
+    let modes = trees
+        .into_iter()
+        .map(|(mode, tree)| (mode.name().to_string(), Value::Table(bindings_to_toml(tree))))
+        .collect();
+    let mut root = toml::map::Map::new();
+    root.insert("keys".to_string(), Value::Table(modes));
+    toml::to_string(&Value::Table(root)).expect("key tables serialize")
+}
+
+/// the inverse of `insert_bindings`
+fn bindings_to_toml(tree: &KeymapTree) -> toml::map::Map<String, Value> {
+    let mut table = toml::map::Map::new();
+    for (node, subtree) in &tree.nodes {
+        let key = match node.key() {
+            KeyInputTypes::MATCH(key) => key.to_string(),
+            KeyInputTypes::MATCH_ALL => ANY_KEY.to_string(),
+            KeyInputTypes::MATCH_NONE => continue,
+        };
+        let commands = match node.get_cmds().as_slice() {
+            [] => None,
+            [command] => Some(Value::String(command.name.to_string())),
+            commands => Some(Value::Array(
+                commands
+                    .iter()
+                    .map(|c| Value::String(c.name.to_string()))
+                    .collect(),
+            )),
+        };
+        let value = match (subtree, commands) {
+            (Some(subtree), commands) => {
+                let mut bindings = bindings_to_toml(subtree);
+                if let Some(label) = &subtree.label {
+                    bindings.insert(LABEL_KEY.to_string(), Value::String(label.clone()));
+                }
+                if let Some(commands) = commands {
+                    bindings.insert(COMMAND_KEY.to_string(), commands);
+                }
+                Value::Table(bindings)
+            }
+            (None, Some(commands)) => commands,
+            (None, None) => continue,
+        };
+        table.insert(key, value);
+    }
+    table
+}
+
+/// `$XDG_CONFIG_HOME/kk`, defaults to `~/.config/kk`
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default()
+        .join("kk")
+}
+
+/// `kk --config <file>`, read instead of `config.toml` of the config directory
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// only the first call counts, meant to be called before the config is loaded
+pub fn set_config_file(path: PathBuf) {
+    let _ = CONFIG_FILE.set(path);
+}
+
+/// `~/.local/state/kk` unless `$XDG_STATE_HOME` is set, for swap files and sessions
+pub fn state_dir() -> PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .unwrap_or_default()
+        .join("kk")
+}
+
+/// `/home/a/b.rs` is `%2Fhome%2Fa%2Fb.rs`, a file name that is unique for absolute paths.
+/// `%` is escaped as well, `/a%/b` and `/a/%b` must not get the same name.
+pub fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '/' => escaped.push_str("%2F"),
+            '\\' => escaped.push_str("%5C"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn config_file() -> PathBuf {
+    match CONFIG_FILE.get() {
+        Some(path) => path.clone(),
+        None => config_dir().join("config.toml"),
+    }
+}
+
+/// `.kk/config.toml` in the working directory, for settings shared in a project
+pub fn project_config_file() -> PathBuf {
+    std::env::current_dir()
+        .unwrap_or_default()
+        .join(".kk")
+        .join("config.toml")
+}
+
+/// the user and the project config, `Config::load_user` reads them in order
+pub fn config_files() -> [PathBuf; 2] {
+    [config_file(), project_config_file()]
+}
+
+/// themes live next to the config in `themes/<name>.toml`
+pub fn theme_dir() -> PathBuf {
+    config_dir().join("themes")
+}
+
+/// Tells when one of the config files changed on disk. The directories are watched instead of
+/// the files, editors often save by replacing the file and it may not exist yet. Where a
+/// directory is missing as well, the closest one that exists is watched until it shows up.
+#[derive(Debug, Default)]
+pub struct ConfigWatcher {
+    watcher: Option<RecommendedWatcher>,
+    changes: Option<UnboundedReceiver<()>>,
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+    /// when the events seen so far count as a change, unless more come in before
+    deadline: Option<Instant>,
+}
+
+/// a save usually comes as a burst of events, they count as one change this long after the last
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+impl ConfigWatcher {
+    pub fn watch(&mut self, files: Vec<PathBuf>) -> notify::Result<()> {
+        let (tx, rx) = unbounded_channel();
+        let watched = files.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            match event {
+                // a directory on the way to a file counts, it may be where the file goes
+                Ok(event)
+                    if event
+                        .paths
+                        .iter()
+                        .any(|p| watched.iter().any(|f| f.starts_with(p))) =>
+                {
+                    let _ = tx.send(());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("config watcher failed: {}", e),
+            }
+        })?;
+        let dirs = watched_dirs(&files);
+        for dir in &dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        self.watcher = Some(watcher);
+        self.changes = Some(rx);
+        self.files = files;
+        self.dirs = dirs;
         Ok(())
     }
+
+    /// Waits for the next change, pending forever when nothing is watched. The event loop
+    /// selects on it, so what was seen before it is dropped stays in `self`.
+    pub async fn changed(&mut self) {
+        let Some(changes) = &mut self.changes else {
+            return std::future::pending().await;
+        };
+        loop {
+            match self.deadline {
+                None => match changes.recv().await {
+                    Some(()) => self.deadline = Some(Instant::now() + SETTLE_TIME),
+                    None => return std::future::pending().await,
+                },
+                Some(deadline) => tokio::select! {
+                    Some(()) = changes.recv() => {
+                        self.deadline = Some(Instant::now() + SETTLE_TIME);
+                    }
+                    _ = tokio::time::sleep_until(deadline) => break,
+                },
+            }
+        }
+        self.deadline = None;
+
+        // a missing directory was created or an existing one removed
+        if watched_dirs(&self.files) != self.dirs {
+            if let Err(e) = self.watch(self.files.clone()) {
+                warn!("config changes are not picked up: {}", e);
+            }
+        }
+    }
+}
+
+/// the closest existing directory of each file
+fn watched_dirs(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs: Vec<_> = files
+        .iter()
+        .filter_map(|f| f.ancestors().skip(1).find(|dir| dir.is_dir()))
+        .map(Path::to_path_buf)
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// merges `right` into `left`, tables are merged recursively and everything else is replaced
+pub fn merge_toml_values(left: Value, right: Value) -> Value {
+    match (left, right) {
+        (Value::Table(mut left), Value::Table(right)) => {
+            for (key, right_value) in right {
+                let merged = match left.remove(&key) {
+                    Some(left_value) => merge_toml_values(left_value, right_value),
+                    None => right_value,
+                };
+                left.insert(key, merged);
+            }
+            Value::Table(left)
+        }
+        (_, right) => right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, str::FromStr, time::Duration};
+
+    use kk_core::{syntax::Language, DocumentMode};
+    use proptest::prelude::*;
+
+    use crate::{
+        commands::KCommand,
+        keymap::{
+            input::KeyInput,
+            tree::{KeyInputTypes, KeymapNode, KeymapTree},
+        },
+    };
+
+    use super::{
+        dump_keys, escape_path, merge_toml_values, watched_dirs, Config, ConfigWatcher,
+        BASE_CONFIG, SETTLE_TIME,
+    };
+
+    #[test]
+    fn base_config_is_valid() {
+        let config = Config::load(BASE_CONFIG).unwrap();
+        assert!(config.keys.contains_key(&DocumentMode::Normal));
+        assert!(config.keys.contains_key(&DocumentMode::Insert));
+        assert!(config.mouse);
+        assert!(!config.auto_session);
+    }
+
+    #[test]
+    fn nested_tables_are_sequences() {
+        let config = Config::load(
+            r#"
+            theme = "default_light"
+            [keys.normal]
+            q = "quit"
+            [keys.normal.space]
+            i = "insert_mode"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.theme.as_deref(), Some("default_light"));
+
+        let normal = &config.keys[&DocumentMode::Normal];
+        let space = KeymapNode::new(KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap()));
+        let i = KeymapNode::new(KeyInputTypes::MATCH(KeyInput::from_str("i").unwrap()));
+        let (cmds, subtree) = normal.get_fun(&space).unwrap();
+        assert!(cmds.is_empty());
+        let (cmds, _) = subtree.unwrap().get_fun(&i).unwrap();
+        assert_eq!(cmds[0].name, "insert_mode");
+    }
+
+    #[test]
+    fn prefix_labels() {
+        let config = Config::load(
+            r#"
+            which-key-delay = 100
+            key-timeout = 250
+            [keys.normal.space]
+            label = "leader"
+            i = "insert_mode"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.which_key_delay.as_millis(), 100);
+        assert_eq!(config.key_timeout.as_millis(), 250);
+        let normal = &config.keys[&DocumentMode::Normal];
+        let space = KeymapNode::new(KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap()));
+        let (_, subtree) = normal.get_fun(&space).unwrap();
+        assert_eq!(subtree.unwrap().label.as_deref(), Some("leader"));
+
+        assert!(Config::load("[keys.normal.space]\nlabel = 1").is_err());
+        assert!(Config::load("which-key-delay = \"long\"").is_err());
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(Config::load("[keys.normal]\nq = \"doesnotexist\"").is_err());
+        assert!(Config::load("[keys.visual]\nq = \"quit\"").is_err());
+        assert!(Config::load("[keys.normal]\nnotakey = \"quit\"").is_err());
+        assert!(Config::load("[lang.cobol]\nlanguage-server = { command = \"x\" }").is_err());
+        assert!(Config::load("[lang.rust]\nlanguage-server = { cmd = \"x\" }").is_err());
+        assert!(Config::load("mouse = \"off\"").is_err());
+        assert!(Config::load("auto-session = 1").is_err());
+    }
+
+    #[test]
+    fn keymap_warnings() {
+        let config = Config::load(BASE_CONFIG).unwrap();
+        assert_eq!(config.warnings, vec![]);
+
+        let config = Config::load(
+            r#"
+            [keys.normal]
+            " " = "nop"
+            space = "quit"
+            "#,
+        )
+        .unwrap();
+        let warnings: Vec<_> = config.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec!["normal: `space` is bound twice, `quit` replaces `nop`"]
+        );
+
+        let base = toml::from_str(BASE_CONFIG).unwrap();
+        let user = toml::from_str(
+            r#"
+            [keys.normal]
+            q = "quit"
+            i = "nop"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_layers(base, vec![user]).unwrap();
+        let warnings: Vec<_> = config.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "normal: `i` overrides `insert_mode` of the base config",
+                "normal: `q` is bound to `quit` in the base config already",
+            ]
+        );
+    }
+
+    #[test]
+    fn language_keys_and_settings() {
+        let config = Config::load(
+            r#"
+            [keys.normal.space]
+            f = "format"
+            [lang.rust.keys.normal.space]
+            t = "hover"
+            [lang.markdown]
+            soft-wrap = true
+            [lang.rust]
+            auto-pairs = { "(" = ")", "<" = ">" }
+            "#,
+        )
+        .unwrap();
+        assert!(!config.settings.soft_wrap);
+        assert!(config.languages[&Language::Markdown].settings.soft_wrap);
+        assert_eq!(
+            config.languages[&Language::Rust].settings.auto_pairs,
+            vec![('(', ')'), ('<', '>')]
+        );
+        assert!(config.languages[&Language::Markdown].keys.is_empty());
+
+        let keys = |k: &str| {
+            k.split(' ')
+                .map(|k| KeyInputTypes::MATCH(KeyInput::from_str(k).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let rust = &config.languages[&Language::Rust].keys[&DocumentMode::Normal];
+        assert_eq!(rust.get_chain(&keys("space t")).unwrap().0[0].name, "hover");
+        assert_eq!(rust.get_chain(&keys("space f")).unwrap().0[0].name, "format");
+        let normal = &config.keys[&DocumentMode::Normal];
+        assert!(normal.get_chain(&keys("space t")).is_none());
+
+        assert!(Config::load("[lang.rust]\nsoft-wrap = 1").is_err());
+        assert!(Config::load("auto-pairs = { \"(\" = \"))\" }").is_err());
+        assert!(Config::load("auto-pairs = 1").is_err());
+        assert!(Config::load("[lang.rust.keys.normal]\nq = \"doesnotexist\"").is_err());
+    }
+
+    #[test]
+    fn command_sequences_and_aliases() {
+        let config = Config::load(
+            r#"
+            [commands]
+            leave = { command = ["save", ":q"], doc = "Write and quit" }
+            save = ":w"
+            [keys.normal]
+            x = ["normal_mode", "insert_mode"]
+            Z = "leave"
+            [keys.normal.space]
+            command = ":theme default"
+            t = "nop"
+            "#,
+        )
+        .unwrap();
+        let names = |cmds: &[&KCommand]| cmds.iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names(&config.commands), vec!["save", "leave"]);
+        assert_eq!(config.commands[1].doc, "Write and quit");
+        assert_eq!(config.commands[0].doc, ":w");
+
+        let keys = |k: &str| vec![KeyInputTypes::MATCH(KeyInput::from_str(k).unwrap())];
+        let normal = &config.keys[&DocumentMode::Normal];
+        let (x, _) = normal.get_chain(&keys("x")).unwrap();
+        assert_eq!(names(&x), vec!["normal_mode", "insert_mode"]);
+        let (z, _) = normal.get_chain(&keys("Z")).unwrap();
+        assert!(std::ptr::eq(z[0], config.commands[1]));
+        let (space, _) = normal.get_chain(&keys("space")).unwrap();
+        assert_eq!(names(&space), vec![":theme default"]);
+        assert_eq!(space[0].doc, ":theme default");
+
+        // reloading the same config reuses the commands
+        let reloaded = Config::load("[commands]\nsave = \":w\"").unwrap();
+        assert!(std::ptr::eq(reloaded.commands[0], config.commands[0]));
+
+        assert!(Config::load("[keys.normal]\nx = []").is_err());
+        assert!(Config::load("[keys.normal]\nx = \":doesnotexist\"").is_err());
+        assert!(Config::load("[commands]\na = \"b\"\nb = [\"a\"]").is_err());
+        assert!(Config::load("[commands]\nquit = \":q\"").is_err());
+        assert!(Config::load("[commands]\na = { doc = \"no command\" }").is_err());
+    }
+
+    #[test]
+    fn language_servers() {
+        let config = Config::load(
+            r#"
+            [lang.python]
+            language-server = { command = "pylsp", args = ["-v"] }
+            "#,
+        )
+        .unwrap();
+        let server = config.languages[&Language::Python]
+            .language_server
+            .as_ref()
+            .unwrap();
+        assert_eq!(server.command, "pylsp");
+        assert_eq!(server.args, vec!["-v"]);
+    }
+
+    #[test]
+    fn escaped_paths_are_unique() {
+        assert_eq!(escape_path(Path::new("/home/a/b.rs")), "%2Fhome%2Fa%2Fb.rs");
+        assert_ne!(escape_path(Path::new("/a%/b")), escape_path(Path::new("/a/%b")));
+        assert_ne!(escape_path(Path::new("/a/25")), escape_path(Path::new("/a%25")));
+    }
+
+    #[tokio::test]
+    async fn watcher_survives_cancellation() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = ConfigWatcher {
+            changes: Some(rx),
+            ..ConfigWatcher::default()
+        };
+        tx.send(()).unwrap();
+        // dropped while the burst settles, like when a key wins the select of the event loop
+        let wait = Duration::from_millis(10);
+        assert!(tokio::time::timeout(wait, watcher.changed()).await.is_err());
+        let wait = Duration::from_secs(1);
+        assert!(tokio::time::timeout(wait, watcher.changed()).await.is_ok());
+        assert!(tokio::time::timeout(SETTLE_TIME * 2, watcher.changed()).await.is_err());
+    }
+
+    #[test]
+    fn missing_dirs_are_watched_from_above() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("user")).unwrap();
+        let files = [
+            dir.join("user").join("config.toml"),
+            dir.join("project").join(".kk").join("config.toml"),
+        ];
+        let before = watched_dirs(&files);
+        std::fs::create_dir_all(dir.join("project")).unwrap();
+        let after = watched_dirs(&files);
+
+        assert_eq!(before, vec![dir.to_path_buf(), dir.join("user")]);
+        assert_eq!(after, vec![dir.join("project"), dir.join("user")]);
+    }
+
+    #[test]
+    fn project_config_cannot_start_servers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let user = dir.join("user.toml");
+        let project = dir.join("project.toml");
+        std::fs::write(
+            &user,
+            "[lang.python]\nlanguage-server = { command = \"pylsp\" }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &project,
+            r#"
+            [lang.rust]
+            language-server = { command = "sh", args = ["-c", "evil"] }
+            soft-wrap = true
+            [lang.python]
+            language-server = { command = "evil" }
+            "#,
+        )
+        .unwrap();
+        let config = Config::load_files(&user, &project).unwrap();
+
+        assert_eq!(
+            config.ignored,
+            vec!["lang.python.language-server", "lang.rust.language-server"]
+        );
+        let python = &config.languages[&Language::Python];
+        assert_eq!(python.language_server.as_ref().unwrap().command, "pylsp");
+        // the base config still has its say
+        let rust = &config.languages[&Language::Rust];
+        assert_eq!(rust.language_server.as_ref().unwrap().command, "rust-analyzer");
+        assert!(rust.settings.soft_wrap);
+    }
+
+    #[test]
+    fn snippets() {
+        let config = Config::load(
+            r#"
+            [lang.rust.snippets]
+            fn = "fn $1() {}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.languages[&Language::Rust].snippets,
+            vec![("fn".to_string(), "fn $1() {}".to_string())]
+        );
+        assert!(Config::load("[lang.rust]\nsnippets = { fn = 1 }").is_err());
+    }
+
+    #[test]
+    fn dump_base_keys() {
+        let config = Config::load(BASE_CONFIG).unwrap();
+        let dump = dump_keys(&config.keys);
+        assert!(dump.contains("[keys.normal.g]\n\",\" = \"goto_newer_change\""));
+        assert!(dump.contains("\nd = \"goto_definition\"\nlabel = \"goto\"\n"));
+        assert_eq!(Config::load(&dump).unwrap().keys, config.keys);
+
+        let config = Config::load("[keys.normal.g]\ncommand = \"quit\"\ng = \"nop\"").unwrap();
+        let g = KeymapNode::new(KeyInputTypes::MATCH(KeyInput::from_str("g").unwrap()));
+        let (cmds, subtree) = config.keys[&DocumentMode::Normal].get_fun(&g).unwrap();
+        assert_eq!(cmds[0].name, "quit");
+        assert!(subtree.is_some());
+        assert_eq!(Config::load(&dump_keys(&config.keys)).unwrap().keys, config.keys);
+    }
+
+    fn keymap_tree() -> impl Strategy<Value = KeymapTree> {
+        let keys = vec!["a", "b", "C-x", "space", "minus", "ret", "F5", "esc", "lt", "any"];
+        let key = proptest::sample::select(keys).prop_map(|key| match key {
+            "any" => KeyInputTypes::MATCH_ALL,
+            key => KeyInputTypes::MATCH(KeyInput::from_str(key).unwrap()),
+        });
+        let commands = KCommand::STATIC_COMMAND_LIST;
+        let command = (0..commands.len()).prop_map(|i| &commands[i]);
+        let bindings = prop::collection::vec(
+            (prop::collection::vec(key.clone(), 1..4), command),
+            0..20,
+        );
+        let labels = prop::collection::vec((prop::collection::vec(key, 1..3), "[a-z ]{1,8}"), 0..4);
+        (bindings, labels).prop_map(|(bindings, labels)| {
+            let mut tree = KeymapTree::new();
+            for (keys, label) in labels {
+                tree.set_label(&keys, label);
+            }
+            for (keys, command) in bindings {
+                tree.insert_chain(keys, vec![command]);
+            }
+            tree
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn dump_round_trip(normal in keymap_tree(), insert in keymap_tree()) {
+            let keys = [(DocumentMode::Normal, normal), (DocumentMode::Insert, insert)];
+            let dump = dump_keys(keys.iter().map(|(mode, tree)| (mode, tree)));
+            let config = Config::load(&dump).unwrap();
+            prop_assert_eq!(&config.keys[&DocumentMode::Normal], &keys[0].1);
+            prop_assert_eq!(&config.keys[&DocumentMode::Insert], &keys[1].1);
+        }
+    }
+
+    #[test]
+    fn merge_tables() {
+        let base = toml::from_str(
+            r#"
+            theme = "default"
+            [keys.normal]
+            q = "quit"
+            i = "insert_mode"
+            "#,
+        )
+        .unwrap();
+        let user = toml::from_str(
+            r#"
+            [keys.normal]
+            q = "nop"
+            "#,
+        )
+        .unwrap();
+        let merged = merge_toml_values(base, user);
+        assert_eq!(merged["theme"].as_str(), Some("default"));
+        assert_eq!(merged["keys"]["normal"]["q"].as_str(), Some("nop"));
+        assert_eq!(merged["keys"]["normal"]["i"].as_str(), Some("insert_mode"));
+    }
 }
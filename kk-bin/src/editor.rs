@@ -1,41 +1,1150 @@
-use crossterm::event::{Event, KeyCode};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind,
+};
 use futures_util::Stream;
-use log::error;
+use kk_core::{
+    diagnostic::{Diagnostic, Diagnostics},
+    document::Document,
+    syntax::{Language, Loader},
+    selection::Selection,
+    transaction::{Change, ChangeSet},
+    DocumentMode,
+};
+use anyhow::Context as _;
+use log::{error, warn};
+use ropey::Rope;
+use tui::{backend::Backend, layout::Rect, Terminal};
+
+use crate::{
+    commands::{typed, Context, KCommand},
+    completion::{self, Completion, CompletionSource},
+    config::{config_files, theme_dir, Config, ConfigWatcher, Settings},
+    job::{Callback, Jobs},
+    history::{ChangeList, Jump, JumpList, Marks, UndoHistory},
+    keymap::{
+        input::KeyInput,
+        map::{Keymap, KeymapTrees},
+    },
+    lsp::LanguageServers,
+    swap::{recover_picker, SwapFile, Swaps},
+    theme::{ColorDepth, Theme, ThemeLoader},
+    ui::{
+        self, document_area, enter_ui, exit_ui,
+        picker::{Picker, PickerAction},
+        popup::Popup,
+        prompt::{Prompt, PromptEvent},
+        which_key::WhichKey,
+        Signal, Signals,
+    },
+    view::View,
+};
+
+/// lines a turn of the mouse wheel scrolls
+const SCROLL_LINES: isize = 3;
+/// how often the text of modified documents is written to their swap files
+const SWAP_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct DocumentId(pub(crate) usize);
 
-use crate::{ui::{enter_ui, exit_ui}, config::Config};
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Info,
+    Error,
+}
 
 #[derive(Debug)]
-pub struct KEditor {}
+pub struct KEditor {
+    pub mode: DocumentMode,
+    pub keymap: Keymap,
+    /// when the pending key sequence was started
+    pending_since: Option<Instant>,
+    which_key_delay: Duration,
+    pub documents: BTreeMap<DocumentId, Document>,
+    next_document_id: usize,
+    /// the focused view
+    pub view: View,
+    /// the other views on screen left to right, `view` is drawn before `splits[focus]`
+    pub splits: Vec<View>,
+    focus: usize,
+    /// the views of the documents that are not shown, they get them back when shown again
+    hidden_views: HashMap<DocumentId, View>,
+    /// of the view, it moves between documents
+    pub jumps: JumpList,
+    pub changelists: HashMap<DocumentId, ChangeList>,
+    histories: HashMap<DocumentId, UndoHistory>,
+    pub marks: Marks,
+    pub theme: Theme,
+    /// theme to restore when a preview is aborted
+    last_theme: Option<Theme>,
+    pub theme_loader: ThemeLoader,
+    pub syn_loader: Arc<Loader>,
+    pub prompt: Option<Prompt>,
+    pub picker: Option<Picker>,
+    pub popup: Option<Popup>,
+    pub completion: Option<Completion>,
+    /// asked in order, earlier sources win among equally good matches
+    pub completion_sources: Vec<Box<dyn CompletionSource>>,
+    pub(crate) next_completion_id: usize,
+    pub status: Option<(String, Severity)>,
+    pub jobs: Jobs,
+    pub language_servers: LanguageServers,
+    /// diagnostics of files that are not open, keyed by absolute path. They move into the
+    /// document once it is opened.
+    pub diagnostics: BTreeMap<PathBuf, Diagnostics>,
+    config_watcher: ConfigWatcher,
+    /// the user and the project config, reloaded when they change
+    pub config_files: [PathBuf; 2],
+    settings: Settings,
+    /// for languages with settings of their own
+    language_settings: HashMap<Language, Settings>,
+    /// from `[commands]` of the config
+    config_commands: Vec<&'static KCommand>,
+    mouse: bool,
+    /// the left button went down on the text of the focused view, moving selects from there
+    dragging: bool,
+    /// headless runs write the modified documents once they quit
+    write_on_quit: bool,
+    auto_session: bool,
+    /// the terminal, mouse events are hit-tested against its layout
+    area: Rect,
+    exit_code: Option<i32>,
+    /// the event loop suspends before waiting for the next event
+    suspend: bool,
+    pub(crate) swaps: Swaps,
+}
 
 impl KEditor {
     pub fn new() -> Self {
-        let _config = Config::load(include_str!("../../.config/based.toml")).unwrap();
-        Self {}
+        match Config::load_user() {
+            Ok(config) => Self::with_config(config),
+            Err(e) => {
+                let config =
+                    Config::load(crate::config::BASE_CONFIG).expect("base config is valid");
+                let mut editor = Self::with_config(config);
+                editor.set_error(format!("{:#}", e));
+                editor
+            }
+        }
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self::with_themes(config, ThemeLoader::new(theme_dir(), ColorDepth::detect()))
+    }
+
+    /// `with_config` with the builtin themes, nothing of the user is read
+    #[cfg(test)]
+    pub fn hermetic(config: Config) -> Self {
+        Self::with_themes(config, ThemeLoader::builtin(ColorDepth::TrueColor))
+    }
+
+    fn with_themes(config: Config, theme_loader: ThemeLoader) -> Self {
+        let (theme, theme_error) = theme_loader.default_theme();
+        let syn_loader = Arc::new(Loader::new(theme.scopes().to_vec()));
+
+        let mut documents = BTreeMap::new();
+        let scratch = DocumentId(0);
+        documents.insert(scratch, Document::new(Rope::new()));
+
+        let mut editor = Self {
+            mode: DocumentMode::Normal,
+            keymap: Keymap::new(),
+            pending_since: None,
+            which_key_delay: Duration::default(),
+            documents,
+            next_document_id: 1,
+            view: View::new(scratch),
+            splits: Vec::new(),
+            focus: 0,
+            hidden_views: HashMap::new(),
+            jumps: JumpList::default(),
+            changelists: HashMap::new(),
+            histories: HashMap::new(),
+            marks: Marks::default(),
+            theme,
+            last_theme: None,
+            theme_loader,
+            syn_loader,
+            prompt: None,
+            picker: None,
+            popup: None,
+            completion: None,
+            completion_sources: Vec::new(),
+            next_completion_id: 0,
+            status: None,
+            jobs: Jobs::default(),
+            language_servers: LanguageServers::new(HashMap::new()),
+            diagnostics: BTreeMap::new(),
+            config_watcher: ConfigWatcher::default(),
+            config_files: config_files(),
+            settings: Settings::default(),
+            language_settings: HashMap::new(),
+            config_commands: Vec::new(),
+            mouse: true,
+            dragging: false,
+            write_on_quit: false,
+            auto_session: false,
+            area: Rect::default(),
+            exit_code: None,
+            suspend: false,
+            swaps: Swaps::default(),
+        };
+        if let Some(e) = theme_error {
+            warn!("{:#}", e);
+            editor.set_error(format!("{:#}", e));
+        }
+        editor.apply_config(config);
+        editor
+    }
+
+    /// Takes over the settings of `config`. Servers that are running keep their settings, a
+    /// started key sequence is dropped.
+    fn apply_config(&mut self, mut config: Config) {
+        for warning in &config.warnings {
+            warn!("{}", warning);
+        }
+        let keymap_warnings = config.warnings.iter().filter(|w| !w.is_note()).count();
+
+        self.keymap.set_timeout(config.key_timeout);
+        let languages = config
+            .languages
+            .iter_mut()
+            .filter(|(_, config)| !config.keys.is_empty())
+            .map(|(language, config)| {
+                let keys = std::mem::take(&mut config.keys);
+                let trees = keys.into_iter().map(|(mode, tree)| (mode, Arc::new(tree)));
+                (*language, trees.collect())
+            })
+            .collect();
+        self.keymap.replace_trees(KeymapTrees {
+            modes: config
+                .keys
+                .into_iter()
+                .map(|(mode, tree)| (mode, Arc::new(tree)))
+                .collect(),
+            languages,
+        });
+        self.pending_since = None;
+        self.which_key_delay = config.which_key_delay;
+        self.config_commands = config.commands;
+        self.mouse = config.mouse;
+        self.auto_session = config.auto_session;
+
+        if let Some(name) = config.theme.as_deref().filter(|n| *n != self.theme.name()) {
+            match self.theme_loader.load(name) {
+                Ok(theme) => self.set_theme(theme),
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+
+        let server_configs = config
+            .languages
+            .iter()
+            .filter_map(|(language, config)| Some((*language, config.language_server.clone()?)))
+            .collect();
+        self.language_servers.set_configs(server_configs);
+        self.settings = config.settings;
+        self.language_settings = config
+            .languages
+            .iter()
+            .map(|(language, config)| (*language, config.settings.clone()))
+            .collect();
+        let snippets = config
+            .languages
+            .into_iter()
+            .map(|(language, config)| (language, config.snippets))
+            .collect();
+        self.completion_sources = vec![
+            Box::new(completion::LanguageServer),
+            Box::new(completion::Snippets::new(snippets)),
+            Box::new(completion::Paths),
+            Box::new(completion::BufferWords),
+        ];
+
+        if keymap_warnings > 0 {
+            self.set_error(format!(
+                "{} keymap warnings, see `kk --check-config`",
+                keymap_warnings
+            ));
+        }
+        if !config.ignored.is_empty() {
+            for key in &config.ignored {
+                warn!("`{}` is ignored in the project config", key);
+            }
+            self.set_error(format!(
+                "the project config may not set {}",
+                config.ignored.join(", ")
+            ));
+        }
+    }
+
+    /// the settings for the language of `doc`
+    pub fn settings(&self, doc: &Document) -> &Settings {
+        doc.language()
+            .and_then(|language| self.language_settings.get(&language))
+            .unwrap_or(&self.settings)
+    }
+
+    /// the commands that can be bound, the static ones and then the ones of the config
+    pub fn commands(&self) -> impl Iterator<Item = &'static KCommand> + '_ {
+        KCommand::STATIC_COMMAND_LIST
+            .iter()
+            .chain(self.config_commands.iter().copied())
+    }
+
+    pub fn command(&self, name: &str) -> Option<&'static KCommand> {
+        self.commands().find(|c| c.name == name)
+    }
+
+    /// reads the config again in the background, the old one stays if the new one is broken
+    pub fn reload_config(&mut self) {
+        let [user, project] = self.config_files.clone();
+        self.jobs.callback(async move {
+            let config = tokio::task::spawn_blocking(move || Config::load_files(&user, &project))
+                .await?
+                .context("config not reloaded")?;
+            let callback: Callback = Box::new(|editor: &mut KEditor| {
+                editor.set_status("config reloaded");
+                editor.apply_config(config);
+                Ok(())
+            });
+            Ok(callback)
+        });
+    }
+
+    /// Opens the file in the view, replacing the scratch buffer if it is still empty. Where the
+    /// view was goes to the jumplist.
+    pub fn open(&mut self, path: &Path) -> anyhow::Result<DocumentId> {
+        let id = self.open_document(path)?;
+        if id != self.view.doc {
+            self.push_jump();
+        }
+        self.show(id);
+        Ok(id)
+    }
+
+    /// remembers where the view is before a large jump
+    pub fn push_jump(&mut self) {
+        self.jumps.push(Jump {
+            doc: self.view.doc,
+            selection: self.view.selection.clone(),
+        });
+    }
+
+    /// shows the document of `jump` with its selection, the jumplist is left alone
+    pub fn go_to(&mut self, jump: &Jump) {
+        self.show(jump.doc);
+        self.view.selection = jump.selection.clamp(self.documents[&jump.doc].text());
+    }
+
+    /// shows `text` in a new buffer without a file
+    pub fn open_scratch(&mut self, text: Rope, language: Option<Language>) -> DocumentId {
+        let mut doc = Document::new(text);
+        doc.set_language(language, &self.syn_loader);
+        let id = DocumentId(self.next_document_id);
+        self.next_document_id += 1;
+        self.documents.insert(id, doc);
+        self.show(id);
+        id
+    }
+
+    /// replaces the view, the empty scratch buffer is dropped
+    fn show(&mut self, id: DocumentId) {
+        let old = self.view.doc;
+        if old == id {
+            return;
+        }
+        let view = self.hidden_views.remove(&id);
+        let view = view.or_else(|| self.splits.iter().find(|v| v.doc == id).cloned());
+        let old_view = std::mem::replace(&mut self.view, view.unwrap_or_else(|| View::new(id)));
+        let empty =
+            self.documents[&old].path().is_none() && self.documents[&old].text().len_chars() == 0;
+        if empty && !self.splits.iter().any(|v| v.doc == old) {
+            self.close_document(old);
+        } else {
+            self.hidden_views.insert(old, old_view);
+        }
+    }
+
+    /// drops the document and what refers to it, its view is gone already
+    fn close_document(&mut self, id: DocumentId) {
+        self.close_language_server_document(id);
+        self.documents.remove(&id);
+        self.jumps.remove(id);
+        self.changelists.remove(&id);
+        self.histories.remove(&id);
+        self.marks.remove(id);
+    }
+
+    /// the view of the document, whether it is shown or not
+    pub fn view_of(&self, id: DocumentId) -> Option<&View> {
+        let mut shown = std::iter::once(&self.view).chain(&self.splits);
+        shown
+            .find(|v| v.doc == id)
+            .or_else(|| self.hidden_views.get(&id))
+    }
+
+    /// the views on screen left to right
+    pub fn views(&self) -> impl Iterator<Item = &View> {
+        let (left, right) = self.splits.split_at(self.focus);
+        left.iter().chain(std::iter::once(&self.view)).chain(right)
+    }
+
+    /// the place of the focused view among `views`
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    /// shows the document of the focused view again right of it, the new view gets the focus
+    pub fn vsplit(&mut self) {
+        self.splits.insert(self.focus, self.view.clone());
+        self.focus += 1;
+    }
+
+    /// focuses the view at `index` of `views`
+    pub fn focus_split(&mut self, index: usize) {
+        if index == self.focus || index > self.splits.len() {
+            return;
+        }
+        let left = index < self.focus;
+        let view = self.splits.remove(if left { index } else { index - 1 });
+        let old = std::mem::replace(&mut self.view, view);
+        let at = if left { self.focus - 1 } else { self.focus };
+        self.splits.insert(at, old);
+        self.focus = index;
+    }
+
+    /// closes the focused view, the one right of it gets the focus. False for the last view.
+    pub fn close_split(&mut self) -> bool {
+        if self.splits.is_empty() {
+            return false;
+        }
+        let index = self.focus.min(self.splits.len() - 1);
+        let old = std::mem::replace(&mut self.view, self.splits.remove(index));
+        self.focus = index;
+        if !self.views().any(|v| v.doc == old.doc) {
+            self.hidden_views.insert(old.doc, old);
+        }
+        true
+    }
+
+    /// replaces the views beside the focused one, e.g. when a session is restored
+    pub fn set_splits(&mut self, splits: Vec<View>, focus: usize) {
+        self.focus = focus.min(splits.len());
+        self.splits = splits;
+    }
+
+    /// loads the file without showing it, documents are only opened once
+    pub fn open_document(&mut self, path: &Path) -> anyhow::Result<DocumentId> {
+        if let Some(id) = self.document_by_path(path) {
+            return Ok(id);
+        }
+
+        let mut doc = Document::open(path, &self.syn_loader)?;
+        if let Some(diagnostics) = self.diagnostics.remove(&std::path::absolute(path)?) {
+            doc.set_diagnostics(diagnostics);
+        }
+        let id = DocumentId(self.next_document_id);
+        self.next_document_id += 1;
+        self.documents.insert(id, doc);
+        self.launch_language_server(id);
+        self.check_swap(id);
+        Ok(id)
+    }
+
+    pub fn document_by_path(&self, path: &Path) -> Option<DocumentId> {
+        let absolute = std::path::absolute(path).ok()?;
+        self.documents
+            .iter()
+            .find(|(_, doc)| {
+                doc.path()
+                    .and_then(|p| std::path::absolute(p).ok())
+                    .is_some_and(|p| p == absolute)
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// writes the document to its file and returns the path
+    pub fn write_document(&mut self, id: DocumentId) -> anyhow::Result<PathBuf> {
+        let doc = self.documents.get_mut(&id).expect("document is open");
+        let path = doc
+            .path()
+            .ok_or_else(|| anyhow::anyhow!("the document has no file"))?
+            .to_path_buf();
+        if doc.readonly() {
+            anyhow::bail!("{} is read-only", path.display());
+        }
+        write_file(&path, doc.text())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        doc.mark_saved();
+        self.swaps.remove(&path);
+        Ok(path)
+    }
+
+    /// swap files are written for modified documents from now on
+    pub fn enable_swap(&mut self, dir: PathBuf) {
+        self.swaps.enable(dir);
+    }
+
+    /// Offers to recover the swap file another kk left for the document, unless one is
+    /// running still. One with the same text as the file is removed without asking.
+    fn check_swap(&mut self, id: DocumentId) {
+        let Some(path) = self.documents[&id].path().map(Path::to_path_buf) else {
+            return;
+        };
+        let Some(swap_path) = self.swaps.swap_path(&path) else {
+            return;
+        };
+        let swap = match SwapFile::read(&swap_path) {
+            Ok(Some(swap)) => swap,
+            Ok(None) => return,
+            Err(e) => {
+                self.set_error(format!("failed to read {}: {}", swap_path.display(), e));
+                return;
+            }
+        };
+        if !swap.is_for(&path) {
+            self.swaps.leave_alone(&path);
+            self.set_error(format!(
+                "{} is the swap file of {}, no swap file is kept for {}",
+                swap_path.display(),
+                swap.file.display(),
+                path.display()
+            ));
+        } else if swap.in_use() {
+            self.swaps.leave_alone(&path);
+            self.set_error(format!(
+                "{} is being edited by another kk (pid {}), no swap file is kept for it",
+                path.display(),
+                swap.pid
+            ));
+        } else if swap.text == *self.documents[&id].text() {
+            self.swaps.remove(&path);
+        } else if self.picker.is_none() {
+            self.picker = Some(recover_picker(id, swap_path, swap));
+        } else {
+            self.set_status(format!(
+                "{} has a swap file, :recover to decide about it",
+                path.display()
+            ));
+        }
+    }
+
+    /// the picker for the swap file of the current document
+    pub fn recover(&mut self) -> anyhow::Result<()> {
+        let id = self.view.doc;
+        let path = self.documents[&id]
+            .path()
+            .ok_or_else(|| anyhow::anyhow!("the document has no file"))?;
+        let swap_path = self
+            .swaps
+            .swap_path(path)
+            .ok_or_else(|| anyhow::anyhow!("swap files are off"))?;
+        match SwapFile::read(&swap_path)? {
+            Some(swap) if !swap.is_for(path) => {
+                anyhow::bail!("{} is the swap file of {}", swap_path.display(), swap.file.display())
+            }
+            Some(swap) => self.picker = Some(recover_picker(id, swap_path, swap)),
+            None => anyhow::bail!("{} has no swap file", path.display()),
+        }
+        Ok(())
+    }
+
+
+    /// replaces the diagnostics `provider` reported for the file, it does not have to be open
+    pub fn set_diagnostics(&mut self, path: &Path, provider: &str, diagnostics: Vec<Diagnostic>) {
+        if let Some(id) = self.document_by_path(path) {
+            let doc = self.documents.get_mut(&id).expect("document is open");
+            doc.diagnostics_mut().set(provider, diagnostics);
+            return;
+        }
+        let Ok(path) = std::path::absolute(path) else {
+            return;
+        };
+        let entry = self.diagnostics.entry(path.clone()).or_default();
+        entry.set(provider, diagnostics);
+        if entry.is_empty() {
+            self.diagnostics.remove(&path);
+        }
+    }
+
+    pub fn current(&mut self) -> (&mut View, &mut Document) {
+        let doc = self
+            .documents
+            .get_mut(&self.view.doc)
+            .expect("view points to an open document");
+        (&mut self.view, doc)
+    }
+
+    pub fn current_ref(&self) -> (&View, &Document) {
+        (&self.view, &self.documents[&self.view.doc])
+    }
+
+    /// applies the changes to the current document and maps the selection through them
+    pub fn apply(&mut self, changes: &ChangeSet) {
+        self.apply_to(self.view.doc, changes);
+    }
+
+    /// Changes the document and records it for undo. Outside of insert mode each change is a
+    /// revision of its own.
+    pub fn apply_to(&mut self, id: DocumentId, changes: &ChangeSet) {
+        let selection = match self.view.doc == id {
+            true => Some(self.view.selection.clone()),
+            false => self.hidden_views.get(&id).map(|v| v.selection.clone()),
+        };
+        let Some(old_text) = self.change_document(id, changes) else {
+            return;
+        };
+        let history = self.histories.entry(id).or_default();
+        history.record(changes, &old_text, selection.as_ref());
+        if self.mode != DocumentMode::Insert {
+            history.commit();
+        }
+    }
+
+    /// applies `changes` and maps what points into the document, returns the text before
+    fn change_document(&mut self, id: DocumentId, changes: &ChangeSet) -> Option<Rope> {
+        if changes.is_empty() {
+            return None;
+        }
+        let doc = self.documents.get_mut(&id).expect("document is open");
+        if doc.readonly() {
+            self.set_error("the document is read-only");
+            return None;
+        }
+        let old_text = doc.text().clone();
+        doc.apply(changes);
+        // the event loop writes the swap files periodically
+        if let Some(path) = doc.path() {
+            self.swaps.record(path, changes, doc.version(), doc.text());
+        }
+        let shown = std::iter::once(&mut self.view).chain(&mut self.splits);
+        for view in shown.chain(self.hidden_views.get_mut(&id)) {
+            if view.doc == id {
+                view.selection = view.selection.map(changes).clamp(doc.text());
+            }
+        }
+        self.jumps.map(id, changes, doc.text());
+        self.marks.map(id, changes, doc.text());
+        let changelist = self.changelists.entry(id).or_default();
+        changelist.record(changes, doc.text());
+        self.notify_language_server(id, &old_text, changes);
+        Some(old_text)
+    }
+
+    /// goes back one revision of the current document, false if there is none
+    pub fn undo(&mut self) -> bool {
+        let id = self.view.doc;
+        let Some((inverses, selection)) = self.histories.entry(id).or_default().undo() else {
+            return false;
+        };
+        for inverse in &inverses {
+            self.change_document(id, inverse);
+        }
+        if let Some(selection) = selection {
+            self.view.selection = selection.clamp(self.documents[&id].text());
+        }
+        true
+    }
+
+    /// applies the revision undone last again, false if there is none
+    pub fn redo(&mut self) -> bool {
+        let id = self.view.doc;
+        let Some(steps) = self.histories.entry(id).or_default().redo() else {
+            return false;
+        };
+        for changes in &steps {
+            self.change_document(id, changes);
+        }
+        true
+    }
+
+    /// leaving insert mode ends its revision
+    pub fn set_mode(&mut self, mode: DocumentMode) {
+        if mode != DocumentMode::Insert {
+            self.histories.values_mut().for_each(UndoHistory::commit);
+        }
+        self.mode = mode.clone();
+        self.keymap.set_mode(mode);
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.last_theme = None;
+        self.apply_theme(theme);
+    }
+
+    /// shows the theme until it is either set or the preview is unset
+    pub fn set_theme_preview(&mut self, theme: Theme) {
+        if self.last_theme.is_none() {
+            self.last_theme = Some(self.theme.clone());
+        }
+        self.apply_theme(theme);
+    }
+
+    pub fn unset_theme_preview(&mut self) {
+        if let Some(theme) = self.last_theme.take() {
+            self.apply_theme(theme);
+        }
+    }
+
+    fn apply_theme(&mut self, theme: Theme) {
+        self.syn_loader.set_scopes(theme.scopes().to_vec());
+        self.theme = theme;
+    }
+
+    pub fn set_status(&mut self, status: impl Into<String>) {
+        self.status = Some((status.into(), Severity::Info));
+    }
+
+    pub fn set_error(&mut self, error: impl Into<String>) {
+        self.status = Some((error.into(), Severity::Error));
+    }
+
+    pub fn exit(&mut self, code: i32) {
+        self.exit_code = Some(code);
+    }
+
+    /// Quits unless documents have changes that were not written, `force` quits anyway. Their
+    /// swap files are kept.
+    pub fn quit(&mut self, force: bool) -> anyhow::Result<()> {
+        let mut modified = self.modified_files();
+        match (modified.next(), modified.count()) {
+            (Some(_), _) if force || self.write_on_quit => {}
+            (Some(path), 0) => anyhow::bail!("{} is modified, :quit! quits anyway", path.display()),
+            (Some(path), n) => anyhow::bail!(
+                "{} and {} more are modified, :quit! quits anyway",
+                path.display(),
+                n
+            ),
+            (None, _) => {}
+        }
+        self.exit(0);
+        Ok(())
+    }
+
+    /// the files of documents with changes that were not written
+    fn modified_files(&self) -> impl Iterator<Item = &Path> {
+        let modified = self.documents.values().filter(|doc| doc.is_modified());
+        modified.filter_map(|doc| doc.path())
+    }
+
+    /// quitting does not ask about modified documents, they are written after
+    pub fn write_on_quit(&mut self) {
+        self.write_on_quit = true;
+    }
+
+    pub fn auto_session(&self) -> bool {
+        self.auto_session
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn suspend(&mut self) {
+        self.suspend = true;
+    }
+
+    pub fn handle_key(&mut self, event: KeyEvent) {
+        if let Some(picker) = &mut self.picker {
+            match picker.handle_key(event) {
+                Some(PickerAction::Close) => self.picker = None,
+                Some(PickerAction::Select) => {
+                    let picker = self.picker.take().expect("picker is open");
+                    if let Err(e) = picker.select(self) {
+                        self.set_error(format!("{:#}", e));
+                    }
+                }
+                None => {}
+            }
+            return;
+        }
+
+        if let Some(prompt) = &mut self.prompt {
+            if event.code == KeyCode::Tab && prompt.prefix() == ":" {
+                self.complete_prompt();
+                return;
+            }
+            let Some(prompt_event) = prompt.handle_key(event) else {
+                return;
+            };
+            let input = prompt.line().to_string();
+            if prompt_event != PromptEvent::Update {
+                self.prompt = None;
+            }
+            let mut cx = Context {
+                editor: self,
+                key: None,
+                count: None,
+            };
+            if let Err(e) = typed::execute(&mut cx, &input, prompt_event) {
+                self.set_error(format!("{:#}", e));
+            }
+            return;
+        }
+
+        // any key closes the popup, esc does nothing else
+        if self.popup.take().is_some() && event.code == crossterm::event::KeyCode::Esc {
+            return;
+        }
+
+        let key = KeyInput::from(event);
+        self.status = None;
+        let language = self.current_ref().1.language();
+        self.keymap.set_language(language);
+        // the key completing a binding resets the count
+        let count = self.keymap.count();
+        let commands = self.keymap.get(key);
+        self.run_commands(commands, Some(key), count);
+    }
+
+    /// completes the command name in the prompt as far as it is unambiguous, lists the
+    /// candidates if there are several
+    fn complete_prompt(&mut self) {
+        let Some(prompt) = &self.prompt else {
+            return;
+        };
+        let names = typed::completions(self, prompt.line());
+        let completed = match names.as_slice() {
+            [] => return,
+            [name] => format!("{} ", name),
+            [first, rest @ ..] => rest.iter().fold(first.to_string(), |prefix, name| {
+                let len = prefix
+                    .chars()
+                    .zip(name.chars())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                prefix.chars().take(len).collect()
+            }),
+        };
+        let mut prompt = Prompt::with_line(":", completed);
+        if names.len() > 1 {
+            prompt.set_hint(names.join(" "));
+        }
+        self.prompt = Some(prompt);
+    }
+
+    /// Inserts the pasted text at every cursor in one change, in any mode. The prompt and the
+    /// pickers get it as a single line.
+    fn handle_paste(&mut self, text: &str) {
+        if let Some(picker) = &mut self.picker {
+            picker.insert(text);
+            return;
+        }
+        if let Some(prompt) = &mut self.prompt {
+            prompt.insert(text);
+            let input = prompt.line().to_string();
+            let mut cx = Context {
+                editor: self,
+                key: None,
+                count: None,
+            };
+            if let Err(e) = typed::execute(&mut cx, &input, PromptEvent::Update) {
+                self.set_error(format!("{:#}", e));
+            }
+            return;
+        }
+
+        self.keymap.cancel();
+        self.pending_since = None;
+        let (view, doc) = self.current_ref();
+        let text = doc.line_ending().normalize(text);
+        let changes = ChangeSet::new(
+            view.selection
+                .iter()
+                .map(|r| Change::insert(r.head, text.clone())),
+        );
+        // one undo step of its own, in insert mode too
+        self.histories.entry(self.view.doc).or_default().commit();
+        self.apply(&changes);
+        self.histories.entry(self.view.doc).or_default().commit();
+        self.update_completion();
+    }
+
+    fn handle_mouse(&mut self, event: MouseEvent) {
+        // the pickers and the prompt are keyboard only
+        if self.picker.is_some() || self.prompt.is_some() {
+            return;
+        }
+        let areas = ui::view_areas(document_area(self.area), self.views().count());
+        let (column, row) = (event.column, event.row);
+        let hit = areas.iter().position(|area| {
+            (area.x..area.x + area.width).contains(&column)
+                && (area.y..area.y + area.height).contains(&row)
+        });
+        match event.kind {
+            // a click into another view focuses it
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.popup = None;
+                self.dragging = false;
+                let Some(index) = hit else {
+                    return;
+                };
+                self.focus_split(index);
+                if let Some(pos) = ui::position_at(self, areas[index], column, row) {
+                    self.keymap.cancel();
+                    self.view.selection = Selection::point(pos);
+                    self.dragging = true;
+                }
+            }
+            // from where the button went down
+            MouseEventKind::Drag(MouseButton::Left) if self.dragging => {
+                if let Some(pos) = ui::position_at(self, areas[self.focus], column, row) {
+                    let anchor = self.view.selection.primary().anchor;
+                    self.view.selection = Selection::single(anchor, pos);
+                }
+            }
+            MouseEventKind::Up(MouseButton::Left) => self.dragging = false,
+            MouseEventKind::Down(MouseButton::Middle) => {
+                let Some(index) = hit else {
+                    return;
+                };
+                let text = self.primary_text();
+                self.focus_split(index);
+                if let Some(pos) = ui::position_at(self, areas[index], column, row) {
+                    self.paste_selection(text, pos);
+                }
+            }
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let Some(index) = hit else {
+                    return;
+                };
+                let lines = match event.kind {
+                    MouseEventKind::ScrollUp => -SCROLL_LINES,
+                    _ => SCROLL_LINES,
+                };
+                self.scroll(index, lines, areas[index].height as usize);
+            }
+            _ => {}
+        }
+    }
+
+    /// the text of the primary selection, nothing for a cursor
+    fn primary_text(&self) -> Option<String> {
+        let (view, doc) = self.current_ref();
+        let range = view.selection.primary();
+        let text = doc.text().slice(range.from()..range.to(doc.text()));
+        (!range.is_point()).then(|| text.to_string())
+    }
+
+    /// inserts the primary selection `text` at `pos` like X11 does on middle-click, the cursor
+    /// ends up after it
+    fn paste_selection(&mut self, text: Option<String>, pos: usize) {
+        let Some(text) = text else {
+            return;
+        };
+        let len = text.chars().count();
+        self.apply(&ChangeSet::new([Change::insert(pos, text)]));
+        self.view.selection = Selection::point(pos + len);
+    }
+
+    /// Moves the view at `index` of `views` by `lines`, the cursor moves along if it would
+    /// leave the `height` rows. The focus stays where it is.
+    fn scroll(&mut self, index: usize, lines: isize, height: usize) {
+        let view = match index.cmp(&self.focus) {
+            Ordering::Less => &mut self.splits[index],
+            Ordering::Equal => &mut self.view,
+            Ordering::Greater => &mut self.splits[index - 1],
+        };
+        let text = self.documents[&view.doc].text();
+        let last_line = text.len_lines().saturating_sub(1);
+        view.offset = view.offset.saturating_add_signed(lines).min(last_line);
+        let head = view.selection.primary().head.min(text.len_chars());
+        let line = text.char_to_line(head);
+        let visible = view.offset..(view.offset + height.max(1)).min(last_line + 1);
+        if !visible.contains(&line) {
+            let line = line.clamp(visible.start, visible.end - 1);
+            view.selection = Selection::point(text.line_to_char(line));
+        }
+    }
+
+    /// runs the binding of a pending sequence nothing continued in time
+    fn handle_key_timeout(&mut self) {
+        let count = self.keymap.count();
+        let commands = self.keymap.check_timeout();
+        self.run_commands(commands, None, count);
+    }
+
+    /// runs what a pending sequence falls back to without waiting for `key-timeout`
+    pub fn finish_pending(&mut self) {
+        let count = self.keymap.count();
+        let commands = self.keymap.take_fallback();
+        self.run_commands(commands, None, count);
+    }
+
+    fn run_commands(
+        &mut self,
+        commands: Vec<&'static KCommand>,
+        key: Option<KeyInput>,
+        count: Option<usize>,
+    ) {
+        for command in commands {
+            let mut cx = Context {
+                editor: self,
+                key,
+                count,
+            };
+            if let Err(e) = command.exec(&mut cx) {
+                self.set_error(format!("{:#}", e));
+                break;
+            }
+        }
+        self.pending_since = match self.keymap.pending() {
+            Some(_) => self.pending_since.or_else(|| Some(Instant::now())),
+            None => None,
+        };
+        self.update_completion();
+    }
+
+    /// when the continuations of the pending key sequence will be shown
+    fn which_key_deadline(&self) -> Option<Instant> {
+        self.pending_since.map(|since| since + self.which_key_delay)
+    }
+
+    /// the continuations of the pending key sequence once it waited long enough
+    pub fn which_key(&self) -> Option<WhichKey> {
+        let deadline = self.which_key_deadline()?;
+        if Instant::now() < deadline {
+            return None;
+        }
+        let (tree, keys) = self.keymap.pending()?;
+        Some(WhichKey::new(tree, keys))
     }
 
     async fn handle_terminal_event(&mut self, event: Result<Event, crossterm::ErrorKind>) {
-        let event = event.unwrap();
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                error!("Failed to read terminal event: {}", e);
+                return;
+            }
+        };
         match event {
-            Event::Key(key) =>  {
-                if let KeyCode::Char('q') = key.code {
-                    exit_ui(); 
-                    std::process::exit(0);
-                }
+            // only presses are bound. Held keys come as more presses, repeats are only reported
+            // with the event types flag which we don't ask for, so they would count twice
+            Event::Key(key) => match key.kind {
+                KeyEventKind::Press => self.handle_key(key),
+                KeyEventKind::Repeat | KeyEventKind::Release => {}
+            },
+            Event::Mouse(event) if self.mouse => self.handle_mouse(event),
+            Event::Mouse(_) => {}
+            Event::Paste(text) => self.handle_paste(&text),
+            Event::Resize(width, height) => self.area = Rect::new(0, 0, width, height),
+            _ => {
+                error!("Unhandled event: {:?}", event);
             }
-            _ => { error!("Unhandled event: {:?}", event); }
         }
+    }
 
+    fn handle_job(&mut self, result: anyhow::Result<Option<Callback>>) {
+        let result = result.and_then(|callback| match callback {
+            Some(callback) => callback(self),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            self.set_error(format!("{:#}", e));
+        }
     }
-    async fn event_loop<S>(&mut self, input_stream: &mut S)
+
+    /// waits for the jobs that are running and applies what they hand back
+    #[cfg(test)]
+    pub async fn finish_jobs(&mut self) {
+        while !self.jobs.is_empty() {
+            let result = self.jobs.next().await;
+            self.handle_job(result);
+        }
+    }
+
+    /// handles events until the input stream ends or the editor exits, then returns the exit
+    /// code. Draws before waiting for each event.
+    pub async fn event_loop<S, B>(
+        &mut self,
+        input_stream: &mut S,
+        terminal: &mut Terminal<B>,
+        signals: &mut Signals,
+    ) -> i32
     where
         S: Stream<Item = crossterm::Result<crossterm::event::Event>> + Unpin,
+        B: Backend,
     {
+        // `enter_ui` captured it
+        let mut mouse_captured = true;
+        let mut swap_interval = tokio::time::interval(SWAP_INTERVAL);
         loop {
+            if self.mouse != mouse_captured {
+                if let Err(e) = ui::set_mouse_capture(self.mouse) {
+                    error!("Failed to set mouse capture: {}", e);
+                }
+                mouse_captured = self.mouse;
+            }
+            if let Err(e) = terminal.draw(|f| ui::render(self, f)) {
+                error!("Failed to draw: {}", e);
+            }
+            if let Some(code) = self.exit_code {
+                return code;
+            }
+            if std::mem::take(&mut self.suspend) {
+                if let Err(e) = ui::suspend() {
+                    self.set_error(format!("{:#}", e));
+                }
+                // set up again like `enter_ui` did, the shell drew over the screen
+                mouse_captured = true;
+                if let Err(e) = terminal.clear() {
+                    error!("Failed to clear: {}", e);
+                }
+                continue;
+            }
+
+            // wake up to draw the which-key popup
+            let which_key_deadline = self
+                .which_key_deadline()
+                .filter(|deadline| *deadline > Instant::now());
+            let which_key_timer = tokio::time::sleep_until(
+                which_key_deadline
+                    .unwrap_or_else(Instant::now)
+                    .into(),
+            );
+
+            let key_deadline = self.keymap.deadline();
+            let key_timer =
+                tokio::time::sleep_until(key_deadline.unwrap_or_else(Instant::now).into());
+
             use futures_util::StreamExt;
             tokio::select! {
-                Some(event) = input_stream.next() => {
-                    self.handle_terminal_event(event).await;
+                event = input_stream.next() => match event {
+                    Some(event) => self.handle_terminal_event(event).await,
+                    None => return 0,
+                },
+                (id, message) = self.language_servers.next_message() => {
+                    self.handle_language_server_message(id, message)
                 }
+                result = self.jobs.next() => self.handle_job(result),
+                _ = which_key_timer, if which_key_deadline.is_some() => {}
+                _ = key_timer, if key_deadline.is_some() => self.handle_key_timeout(),
+                _ = self.config_watcher.changed() => self.reload_config(),
+                _ = swap_interval.tick() => self.swaps.flush(),
+                signal = signals.next() => match signal {
+                    Signal::Terminate => {
+                        self.swaps.keep_all();
+                        return 1;
+                    }
+                    Signal::Continue => {
+                        if let Err(e) = ui::resume() {
+                            error!("Failed to resume: {:#}", e);
+                        }
+                        if let Err(e) = terminal.clear() {
+                            error!("Failed to clear: {}", e);
+                        }
+                    }
+                },
             }
         }
     }
@@ -44,15 +1153,61 @@ impl KEditor {
     where
         S: Stream<Item = crossterm::Result<crossterm::event::Event>> + Unpin,
     {
-        let _terminal = enter_ui()?;
+        if let Err(e) = self.config_watcher.watch(self.config_files.to_vec()) {
+            warn!("config changes are not picked up: {}", e);
+        }
+        let mut terminal = enter_ui()?;
+        self.area = terminal.size()?;
         let hook = std::panic::take_hook();
+        let flush_swaps = self.swaps.panic_flush();
         std::panic::set_hook(Box::new(move |info| {
+            flush_swaps();
             let _ = exit_ui();
             hook(info)
         }));
 
-        self.event_loop(input_stream).await;
+        let code = self
+            .event_loop(input_stream, &mut terminal, &mut Signals::new())
+            .await;
+        // documents left with `:quit!` or when the input ended keep their swap files
+        let modified: Vec<_> = self.modified_files().map(Path::to_path_buf).collect();
+        self.swaps.remove_all_but(&modified);
+        exit_ui()?;
+        Ok(code)
+    }
+}
+
+/// Writes `text` to a file next to `path` and renames it over `path`, a crash or a full disk
+/// leaves the old file as it was. Symlinks are followed, the permissions of the old file kept.
+fn write_file(path: &Path, text: &Rope) -> std::io::Result<()> {
+    let target = match std::fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let Some(name) = target.file_name() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "not a file name",
+        ));
+    };
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(".kk-{}", std::process::id()));
+    let tmp = target.with_file_name(tmp_name);
 
-        Ok(0)
+    let written = (|| {
+        let file = std::fs::File::create(&tmp)?;
+        if let Ok(metadata) = std::fs::metadata(&target) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        let mut out = std::io::BufWriter::new(file);
+        text.write_to(&mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, &target)
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
     }
+    written
 }
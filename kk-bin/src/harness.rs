@@ -0,0 +1,252 @@
+//! Drives `KEditor::event_loop` with scripted keys and draws into a `TestBackend`, so commands
+//! can be tested without a terminal
+
+use std::str::FromStr;
+
+use crossterm::event::{Event, KeyEvent};
+use kk_core::{selection::Selection, DocumentMode};
+use ropey::Rope;
+use tui::{backend::TestBackend, Terminal};
+
+use crate::{config::Config, editor::KEditor, keymap::input::KeyInput, ui::Signals};
+
+pub const WIDTH: u16 = 40;
+pub const HEIGHT: u16 = 10;
+
+pub struct Harness {
+    pub editor: KEditor,
+    terminal: Terminal<TestBackend>,
+}
+
+impl Harness {
+    /// the base config only, what the user configured does not change what keys do
+    pub fn new(text: &str) -> Self {
+        Self::with_config(text, "")
+    }
+
+    /// `config` is merged on top of the base config like a user config
+    pub fn with_config(text: &str, config: &str) -> Self {
+        let config = Config::load_with(config).expect("test config is valid");
+        let mut editor = KEditor::hermetic(config);
+        editor.open_scratch(Rope::from(text), None);
+        let terminal = Terminal::new(TestBackend::new(WIDTH, HEIGHT)).expect("test backend");
+        Self { editor, terminal }
+    }
+
+    /// Types `keys` in the notation of the config, e.g. `i h i esc` or `3 space w`, then
+    /// draws. A sequence that waits for `key-timeout` stays pending.
+    pub async fn keys(&mut self, keys: &str) -> &mut Self {
+        let events = keys
+            .split_whitespace()
+            .map(|key| {
+                let key = KeyInput::from_str(key).expect("valid key");
+                Event::Key(KeyEvent::new(key.code, key.modifiers))
+            })
+            .collect();
+        self.events(events).await
+    }
+
+    /// the events go through the event loop like terminal input, the size comes first
+    pub async fn events(&mut self, events: Vec<Event>) -> &mut Self {
+        let resize = Event::Resize(WIDTH, HEIGHT);
+        let events = std::iter::once(resize).chain(events).map(Ok);
+        let mut stream = futures_util::stream::iter(events);
+        self.editor
+            .event_loop(&mut stream, &mut self.terminal, &mut Signals::default())
+            .await;
+        self
+    }
+
+    pub fn text(&self) -> String {
+        self.editor.current_ref().1.text().to_string()
+    }
+
+    pub fn selection(&self) -> &Selection {
+        &self.editor.current_ref().0.selection
+    }
+
+    pub fn mode(&self) -> &DocumentMode {
+        &self.editor.mode
+    }
+
+    /// the drawn rows without trailing spaces
+    pub fn screen(&self) -> Vec<String> {
+        let buffer = self.terminal.backend().buffer();
+        (0..HEIGHT)
+            .map(|y| {
+                let row: String = (0..WIDTH)
+                    .map(|x| buffer.get(x, y).symbol.as_str())
+                    .collect();
+                row.trim_end().to_string()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{
+        Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
+    use kk_core::{
+        selection::{Range, Selection},
+        DocumentMode,
+    };
+
+    use crate::editor::Severity;
+
+    use super::Harness;
+
+    #[tokio::test]
+    async fn insert_and_render() {
+        let mut h = Harness::new("world");
+        h.keys("i").await;
+        assert_eq!(h.mode(), &DocumentMode::Insert);
+        h.keys("h i space esc").await;
+        assert_eq!(h.text(), "hi world");
+        assert_eq!(h.mode(), &DocumentMode::Normal);
+        assert_eq!(h.selection(), &Selection::point(3));
+        let screen = h.screen();
+        assert!(screen[0].ends_with("hi world"), "{:?}", screen);
+        assert!(screen.iter().any(|row| row.contains("NOR")), "{:?}", screen);
+    }
+
+    #[tokio::test]
+    async fn counts_and_typed_commands() {
+        let mut h = Harness::new("abc\ndef\nghi\n");
+        h.keys("2 j l").await;
+        assert_eq!(h.selection(), &Selection::point(9));
+        h.keys(": q u i t").await;
+        assert!(h.screen()[super::HEIGHT as usize - 1].starts_with(":quit"));
+        assert_eq!(h.editor.exit_code(), None);
+        h.keys("ret").await;
+        assert_eq!(h.editor.exit_code(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn config_bindings() {
+        let mut h = Harness::with_config("a\nb\n", "[keys.normal]\nX = \"move_line_down\"");
+        h.keys("X").await;
+        assert_eq!(h.selection(), &Selection::point(2));
+    }
+
+    #[tokio::test]
+    async fn only_presses_are_bound() {
+        use crossterm::event::KeyEventKind::{Press, Release, Repeat};
+
+        let mut h = Harness::new("a\nb\nc\n");
+        let j = KeyCode::Char('j');
+        let key = |kind| Event::Key(KeyEvent::new_with_kind(j, KeyModifiers::NONE, kind));
+        let kinds = [Press, Repeat, Release];
+        h.events(kinds.into_iter().map(key).collect()).await;
+        assert_eq!(h.selection(), &Selection::point(2));
+    }
+
+    fn mouse(kind: MouseEventKind, column: u16, row: u16) -> Event {
+        let modifiers = KeyModifiers::NONE;
+        Event::Mouse(MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers,
+        })
+    }
+
+    #[tokio::test]
+    async fn clicks_focus_splits() {
+        let mut h = Harness::new("abc\ndef\n");
+        h.keys("C-w v").await;
+        assert_eq!((h.editor.views().count(), h.editor.focus()), (2, 1));
+        assert!(h.screen()[0].contains('│'), "{:?}", h.screen());
+
+        // the text starts after the gutter of 3 columns, the right view after the border
+        let down = MouseEventKind::Down(MouseButton::Left);
+        let drag = MouseEventKind::Drag(MouseButton::Left);
+        h.events(vec![mouse(down, 4, 1)]).await;
+        assert_eq!(h.editor.focus(), 0);
+        assert_eq!(h.selection(), &Selection::point(5));
+        h.events(vec![mouse(down, 24, 0), mouse(drag, 25, 1)]).await;
+        assert_eq!(h.editor.focus(), 1);
+        assert_eq!(h.selection(), &Selection::single(1, 6));
+
+        // only drags from the text select, not from the status line
+        h.events(vec![mouse(down, 24, 8), mouse(drag, 24, 1)]).await;
+        assert_eq!(h.selection(), &Selection::single(1, 6));
+
+        h.keys("C-w q").await;
+        assert_eq!((h.editor.views().count(), h.editor.focus()), (1, 0));
+        assert_eq!(h.selection(), &Selection::point(5));
+    }
+
+    #[tokio::test]
+    async fn paste_is_one_undo_step() {
+        let mut h = Harness::new("a\r\nb\r\n");
+        h.editor.view.selection = Selection::new(vec![Range::point(0), Range::point(3)], 0);
+        h.events(vec![Event::Paste("x\ny\n".to_string())]).await;
+        assert_eq!(h.text(), "x\r\ny\r\na\r\nx\r\ny\r\nb\r\n");
+        assert_eq!(h.mode(), &DocumentMode::Normal);
+        h.keys("u").await;
+        assert_eq!(h.text(), "a\r\nb\r\n");
+        assert_eq!(h.selection().ranges().len(), 2);
+        h.keys("U").await;
+        assert_eq!(h.text(), "x\r\ny\r\na\r\nx\r\ny\r\nb\r\n");
+
+        // apart from what is typed around it in insert mode
+        let mut h = Harness::new("");
+        h.keys("i a").await;
+        h.events(vec![Event::Paste("bc".to_string())]).await;
+        h.keys("d esc").await;
+        assert_eq!(h.text(), "abcd");
+        h.keys("u").await;
+        assert_eq!(h.text(), "abc");
+        h.keys("u").await;
+        assert_eq!(h.text(), "a");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn write_replaces_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "old\n").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.join("link.txt");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        let mut h = Harness::new("");
+        h.editor.open(&link).unwrap();
+        h.keys("i x esc : w ret").await;
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "xold\n");
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn broken_reload_keeps_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let user = dir.join("config.toml");
+        let mut h = Harness::new("a\nb\nc\n");
+        h.editor.config_files = [user.clone(), dir.join("project.toml")];
+
+        std::fs::write(&user, "[keys.normal]\nX = \"move_line_down\"").unwrap();
+        h.editor.reload_config();
+        h.editor.finish_jobs().await;
+        h.keys("X").await;
+        assert_eq!(h.selection(), &Selection::point(2));
+
+        std::fs::write(&user, "[keys.normal]\nX = ").unwrap();
+        h.editor.reload_config();
+        h.editor.finish_jobs().await;
+        let (status, severity) = h.editor.status.clone().unwrap();
+        assert_eq!(severity, Severity::Error);
+        assert!(status.starts_with("config not reloaded"), "{}", status);
+        h.keys("X").await;
+        assert_eq!(h.selection(), &Selection::point(4));
+    }
+}
@@ -0,0 +1,178 @@
+//! `kk --headless -c <script> files...` runs the keymap and the commands without a terminal,
+//! then writes the modified documents
+
+use std::str::FromStr;
+
+use crossterm::event::KeyEvent;
+
+use crate::{
+    commands::{typed, Context},
+    editor::{KEditor, Severity},
+    keymap::input::KeyInput,
+    ui::prompt::PromptEvent,
+};
+
+/// one `-c` of the script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// typed in the current mode like keys from the terminal
+    Keys(Vec<KeyInput>),
+    /// run like it was typed after `:`
+    Command(String),
+}
+
+impl FromStr for Step {
+    type Err = anyhow::Error;
+
+    /// `:w` is a command, anything else keys in the notation of the config, e.g. `g g d`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = s.strip_prefix(':') {
+            return Ok(Self::Command(command.to_string()));
+        }
+        let keys = s
+            .split_whitespace()
+            .map(|key| {
+                KeyInput::from_str(key).map_err(|e| e.context(format!("invalid key '{}'", key)))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self::Keys(keys))
+    }
+}
+
+/// Runs the steps until one fails or one exits, the modified documents are written after.
+/// A sequence still waiting for keys at the end runs what it is bound to. Language servers are
+/// not started for a script. Errors go to stderr and make the exit code 1.
+pub fn run(editor: &mut KEditor, script: &[Step]) -> i32 {
+    editor.write_on_quit();
+    for step in script {
+        match step {
+            Step::Keys(keys) => {
+                for key in keys {
+                    editor.handle_key(KeyEvent::new(key.code, key.modifiers));
+                    if failed(editor) {
+                        return 1;
+                    }
+                }
+            }
+            Step::Command(line) => {
+                let mut cx = Context {
+                    editor,
+                    key: None,
+                    count: None,
+                };
+                if let Err(e) = typed::execute(&mut cx, line, PromptEvent::Validate) {
+                    editor.set_error(format!(":{}: {:#}", line, e));
+                }
+            }
+        }
+        if failed(editor) {
+            return 1;
+        }
+        if editor.exit_code().is_some() {
+            break;
+        }
+    }
+    editor.finish_pending();
+    if failed(editor) {
+        return 1;
+    }
+
+    let changed: Vec<_> = editor
+        .documents
+        .iter()
+        .filter(|(_, doc)| doc.path().is_some() && doc.is_modified())
+        .map(|(id, _)| *id)
+        .collect();
+    let mut code = editor.exit_code().unwrap_or(0);
+    for id in changed {
+        if let Err(e) = editor.write_document(id) {
+            eprintln!("kk: {:#}", e);
+            code = 1;
+        }
+    }
+    code
+}
+
+/// prints the error of the last step, the status is cleared for the next one
+fn failed(editor: &mut KEditor) -> bool {
+    match editor.status.take() {
+        Some((error, Severity::Error)) => {
+            eprintln!("kk: {}", error);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use kk_core::syntax::Language;
+    use ropey::Rope;
+
+    use crate::{config::Config, editor::KEditor, keymap::input::KeyInput};
+
+    use super::{run, Step};
+
+    fn steps(script: &[&str]) -> Vec<Step> {
+        script.iter().map(|s| Step::from_str(s).unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_steps() {
+        let key = |k: &str| KeyInput::from_str(k).unwrap();
+        assert_eq!(
+            steps(&[":theme default", "i a space esc"]),
+            vec![
+                Step::Command("theme default".to_string()),
+                Step::Keys(vec![key("i"), key("a"), key("space"), key("esc")]),
+            ]
+        );
+        assert!(Step::from_str("i nope esc").is_err());
+    }
+
+    #[tokio::test]
+    async fn edit_and_write() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "world\n").unwrap();
+        let untouched = dir.join("b.txt");
+        std::fs::write(&untouched, "b\n").unwrap();
+
+        let mut editor = KEditor::hermetic(Config::load_with("").unwrap());
+        editor.open(&untouched).unwrap();
+        editor.open(&path).unwrap();
+        let code = run(&mut editor, &steps(&["i h i space esc"]));
+        assert_eq!(code, 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hi world\n");
+
+        // the scratch buffer has no file to write
+        editor.open_scratch(Rope::from("x"), None);
+        assert_eq!(run(&mut editor, &steps(&[":w"])), 1);
+        assert_eq!(run(&mut editor, &steps(&[":nope"])), 1);
+        // nothing runs after quitting
+        assert_eq!(run(&mut editor, &steps(&[":q", "i a esc"])), 0);
+        assert_eq!(editor.current_ref().1.text().to_string(), "x");
+        assert_eq!(std::fs::read_to_string(&untouched).unwrap(), "b\n");
+    }
+
+    #[tokio::test]
+    async fn no_language_servers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("a.rs");
+        std::fs::write(&path, "fn main() {}\n").unwrap();
+        let config = "[lang.rust]\nlanguage-server = { command = \"cat\" }";
+
+        let mut editor = KEditor::hermetic(Config::load_with(config).unwrap());
+        editor.open(&path).unwrap();
+        assert!(editor.language_servers.get(Language::Rust).is_some());
+
+        let mut editor = KEditor::hermetic(Config::load_with(config).unwrap());
+        editor.language_servers.disable();
+        editor.open(&path).unwrap();
+        assert!(editor.language_servers.get(Language::Rust).is_none());
+    }
+}
@@ -0,0 +1,380 @@
+//! Where the cursor has been: the jumplist of the view, the changelists of the documents and
+//! the marks. And what the documents looked like, their undo histories.
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use kk_core::{
+    selection::Selection,
+    transaction::{Assoc, ChangeSet},
+};
+use ropey::Rope;
+
+use crate::editor::DocumentId;
+
+/// older entries are dropped beyond this
+const MAX_JUMPS: usize = 100;
+const MAX_CHANGES: usize = 100;
+const MAX_REVISIONS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub doc: DocumentId,
+    pub selection: Selection,
+}
+
+/// The positions before large jumps, like going to a definition or opening a file. Going back
+/// remembers where it started so going forward returns there.
+#[derive(Debug, Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// `jumps.len()` unless going back and forth
+    current: usize,
+}
+
+impl JumpList {
+    /// drops the jumps after the current one, the same jump twice in a row is kept once
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps.truncate(self.current + 1);
+        if self.jumps.last() != Some(&jump) {
+            self.jumps.push(jump);
+        }
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.current = self.jumps.len();
+    }
+
+    /// `count` jumps back from `here`, which is pushed if it is not in the list yet
+    pub fn backward(&mut self, count: usize, here: Jump) -> Option<&Jump> {
+        if self.jumps.is_empty() {
+            return None;
+        }
+        if self.current == self.jumps.len() {
+            self.push(here);
+            self.current = self.jumps.len() - 1;
+        }
+        self.current = self.current.checked_sub(count)?;
+        self.jumps.get(self.current)
+    }
+
+    pub fn forward(&mut self, count: usize) -> Option<&Jump> {
+        let target = self.current + count;
+        if target >= self.jumps.len() {
+            return None;
+        }
+        self.current = target;
+        self.jumps.get(target)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Jump> {
+        self.jumps.iter()
+    }
+
+    /// keeps the selections of `doc` on the same text
+    pub fn map(&mut self, doc: DocumentId, changes: &ChangeSet, text: &Rope) {
+        for jump in self.jumps.iter_mut().filter(|j| j.doc == doc) {
+            jump.selection = jump.selection.map(changes).clamp(text);
+        }
+    }
+
+    /// the document was closed
+    pub fn remove(&mut self, doc: DocumentId) {
+        let before = self.jumps[..self.current]
+            .iter()
+            .filter(|j| j.doc == doc)
+            .count();
+        self.jumps.retain(|j| j.doc != doc);
+        self.current -= before;
+    }
+}
+
+/// Where a document was edited, one entry per line edited in a row
+#[derive(Debug, Default)]
+pub struct ChangeList {
+    positions: Vec<usize>,
+    /// `positions.len()` unless going through them
+    current: usize,
+}
+
+impl ChangeList {
+    /// maps the positions through `changes` and records where they end
+    pub fn record(&mut self, changes: &ChangeSet, text: &Rope) {
+        let Some(last) = changes.changes().last() else {
+            return;
+        };
+        for pos in &mut self.positions {
+            *pos = changes.map_pos(*pos, Assoc::After).min(text.len_chars());
+        }
+        let pos =
+            (changes.map_pos(last.from, Assoc::Before) + last.inserted_len()).min(text.len_chars());
+        if let Some(prev) = self.positions.last_mut() {
+            if text.char_to_line(*prev) == text.char_to_line(pos) {
+                self.positions.pop();
+            }
+        }
+        self.positions.push(pos);
+        if self.positions.len() > MAX_CHANGES {
+            self.positions.remove(0);
+        }
+        self.current = self.positions.len();
+    }
+
+    /// `count` changes older, the first one goes to the last change
+    pub fn older(&mut self, count: usize) -> Option<usize> {
+        self.current = self.current.checked_sub(count)?;
+        self.positions.get(self.current).copied()
+    }
+
+    pub fn newer(&mut self, count: usize) -> Option<usize> {
+        let target = self.current + count;
+        let pos = self.positions.get(target).copied()?;
+        self.current = target;
+        Some(pos)
+    }
+}
+
+/// one undo step, the changesets in the order they were applied with the ones undoing them
+#[derive(Debug, Clone)]
+struct Revision {
+    steps: Vec<(ChangeSet, ChangeSet)>,
+    /// before the first step, where undoing puts the cursors back
+    selection: Option<Selection>,
+}
+
+/// The revisions of a document. Changes are collected until they are committed, an insert
+/// session is one revision.
+#[derive(Debug, Default)]
+pub struct UndoHistory {
+    revisions: Vec<Revision>,
+    /// `revisions.len()` unless some were undone
+    current: usize,
+    pending: Option<Revision>,
+}
+
+impl UndoHistory {
+    /// `changes` was applied to `old_text`, what was undone can not be redone anymore
+    pub fn record(&mut self, changes: &ChangeSet, old_text: &Rope, selection: Option<&Selection>) {
+        let pending = self.pending.get_or_insert_with(|| Revision {
+            steps: Vec::new(),
+            selection: selection.cloned(),
+        });
+        pending.steps.push((changes.clone(), changes.invert(old_text)));
+    }
+
+    /// the changes recorded so far are one revision, the next ones start another
+    pub fn commit(&mut self) {
+        let Some(revision) = self.pending.take() else {
+            return;
+        };
+        self.revisions.truncate(self.current);
+        self.revisions.push(revision);
+        if self.revisions.len() > MAX_REVISIONS {
+            self.revisions.remove(0);
+        }
+        self.current = self.revisions.len();
+    }
+
+    /// the changesets that undo the last revision in order and the selection before it
+    pub fn undo(&mut self) -> Option<(Vec<ChangeSet>, Option<Selection>)> {
+        self.commit();
+        self.current = self.current.checked_sub(1)?;
+        let revision = &self.revisions[self.current];
+        let inverses = revision.steps.iter().rev().map(|(_, inverse)| inverse.clone());
+        Some((inverses.collect(), revision.selection.clone()))
+    }
+
+    /// the changesets of the revision undone last
+    pub fn redo(&mut self) -> Option<Vec<ChangeSet>> {
+        self.commit();
+        let revision = self.revisions.get(self.current)?;
+        self.current += 1;
+        Some(revision.steps.iter().map(|(changes, _)| changes.clone()).collect())
+    }
+}
+
+/// `a` to `z` are local to their document, `A` to `Z` global
+#[derive(Debug, Default)]
+pub struct Marks {
+    local: BTreeMap<(DocumentId, char), usize>,
+    global: BTreeMap<char, (DocumentId, usize)>,
+}
+
+impl Marks {
+    pub fn set(&mut self, name: char, doc: DocumentId, pos: usize) -> anyhow::Result<()> {
+        match name {
+            'a'..='z' => self.local.insert((doc, name), pos).map(|_| ()),
+            'A'..='Z' => self.global.insert(name, (doc, pos)).map(|_| ()),
+            _ => bail!("invalid mark '{}', marks are letters", name),
+        };
+        Ok(())
+    }
+
+    /// where mark `name` is, seen from `doc`
+    pub fn get(&self, name: char, doc: DocumentId) -> Option<(DocumentId, usize)> {
+        match name {
+            'a'..='z' => self.local.get(&(doc, name)).map(|pos| (doc, *pos)),
+            _ => self.global.get(&name).copied(),
+        }
+    }
+
+    /// the local marks of `doc` and then the global ones, by name
+    pub fn iter(&self, doc: DocumentId) -> impl Iterator<Item = (char, DocumentId, usize)> + '_ {
+        let local = self
+            .local
+            .range((doc, 'a')..=(doc, 'z'))
+            .map(|((doc, name), pos)| (*name, *doc, *pos));
+        let global = self
+            .global
+            .iter()
+            .map(|(name, (doc, pos))| (*name, *doc, *pos));
+        local.chain(global)
+    }
+
+    /// keeps the marks of `doc` on the same text
+    pub fn map(&mut self, doc: DocumentId, changes: &ChangeSet, text: &Rope) {
+        let map = |pos: &mut usize| {
+            *pos = changes.map_pos(*pos, Assoc::After).min(text.len_chars());
+        };
+        self.local
+            .range_mut((doc, 'a')..=(doc, 'z'))
+            .for_each(|(_, pos)| map(pos));
+        self.global
+            .values_mut()
+            .filter(|(d, _)| *d == doc)
+            .for_each(|(_, pos)| map(pos));
+    }
+
+    /// the document was closed
+    pub fn remove(&mut self, doc: DocumentId) {
+        self.local.retain(|(d, _), _| *d != doc);
+        self.global.retain(|_, (d, _)| *d != doc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::{
+        selection::Selection,
+        transaction::{Change, ChangeSet},
+    };
+    use ropey::Rope;
+
+    use crate::editor::DocumentId;
+
+    use super::{ChangeList, Jump, JumpList, Marks, UndoHistory};
+
+    fn jump(doc: usize, pos: usize) -> Jump {
+        Jump {
+            doc: DocumentId(doc),
+            selection: Selection::point(pos),
+        }
+    }
+
+    #[test]
+    fn jumplist() {
+        let mut jumps = JumpList::default();
+        assert_eq!(jumps.backward(1, jump(0, 0)), None);
+        jumps.push(jump(0, 1));
+        jumps.push(jump(0, 1));
+        jumps.push(jump(1, 2));
+        assert_eq!(jumps.backward(1, jump(1, 3)), Some(&jump(1, 2)));
+        assert_eq!(jumps.backward(1, jump(1, 2)), Some(&jump(0, 1)));
+        assert_eq!(jumps.backward(1, jump(0, 1)), None);
+        assert_eq!(jumps.forward(2), Some(&jump(1, 3)));
+        assert_eq!(jumps.forward(1), None);
+
+        jumps.backward(2, jump(1, 3));
+        jumps.push(jump(0, 5));
+        let all: Vec<_> = jumps.iter().cloned().collect();
+        assert_eq!(all, vec![jump(0, 1), jump(0, 5)]);
+
+        let text = Rope::from("xx0123456");
+        jumps.map(
+            DocumentId(0),
+            &ChangeSet::new([Change::insert(0, "xx")]),
+            &text,
+        );
+        jumps.remove(DocumentId(1));
+        assert_eq!(jumps.backward(1, jump(1, 0)), Some(&jump(0, 7)));
+        jumps.remove(DocumentId(0));
+        assert_eq!(jumps.iter().count(), 1);
+        assert_eq!(jumps.forward(1), None);
+    }
+
+    #[test]
+    fn changelist() {
+        let mut changes = ChangeList::default();
+        let mut text = Rope::from("a\nb\nc\n");
+        let mut edit = |changes: &mut ChangeList, change: Change| {
+            let change = ChangeSet::new([change]);
+            change.apply(&mut text);
+            changes.record(&change, &text);
+        };
+        edit(&mut changes, Change::insert(4, "x"));
+        edit(&mut changes, Change::insert(5, "y"));
+        edit(&mut changes, Change::insert(0, "z"));
+        // "za\nb\nxyc\n", the edits of the third line are one entry
+        assert_eq!(changes.older(1), Some(1));
+        assert_eq!(changes.older(1), Some(7));
+        assert_eq!(changes.older(1), None);
+        assert_eq!(changes.newer(1), Some(1));
+        assert_eq!(changes.newer(1), None);
+    }
+
+    #[test]
+    fn marks() {
+        let (a, b) = (DocumentId(0), DocumentId(1));
+        let mut marks = Marks::default();
+        marks.set('m', a, 3).unwrap();
+        marks.set('M', a, 4).unwrap();
+        marks.set('m', b, 5).unwrap();
+        assert!(marks.set('1', a, 0).is_err());
+        assert_eq!(marks.get('m', a), Some((a, 3)));
+        assert_eq!(marks.get('M', b), Some((a, 4)));
+        assert_eq!(marks.get('x', a), None);
+
+        let text = Rope::from("0134");
+        marks.map(a, &ChangeSet::new([Change::delete(2, 3)]), &text);
+        assert_eq!(marks.get('m', a), Some((a, 2)));
+        assert_eq!(marks.get('M', a), Some((a, 3)));
+        assert_eq!(marks.get('m', b), Some((b, 5)));
+        let listed: Vec<_> = marks.iter(a).collect();
+        assert_eq!(listed, vec![('m', a, 2), ('M', a, 3)]);
+        marks.remove(a);
+        assert_eq!(marks.get('M', b), None);
+    }
+
+    #[test]
+    fn undo_history() {
+        fn change(history: &mut UndoHistory, text: &mut Rope, changes: ChangeSet) {
+            history.record(&changes, text, Some(&Selection::point(text.len_chars())));
+            changes.apply(text);
+        }
+        let mut history = UndoHistory::default();
+        let mut text = Rope::from("a");
+        // one revision of two steps
+        change(&mut history, &mut text, ChangeSet::new([Change::insert(1, "b")]));
+        change(&mut history, &mut text, ChangeSet::new([Change::insert(2, "c")]));
+        history.commit();
+        change(&mut history, &mut text, ChangeSet::new([Change::delete(0, 1)]));
+        assert_eq!(text, "bc");
+
+        let (inverses, selection) = history.undo().unwrap();
+        inverses.iter().for_each(|c| c.apply(&mut text));
+        assert_eq!(text, "abc");
+        assert_eq!(selection, Some(Selection::point(3)));
+        let (inverses, selection) = history.undo().unwrap();
+        inverses.iter().for_each(|c| c.apply(&mut text));
+        assert_eq!(text, "a");
+        assert_eq!(selection, Some(Selection::point(1)));
+        assert!(history.undo().is_none());
+
+        history.redo().unwrap().iter().for_each(|c| c.apply(&mut text));
+        assert_eq!(text, "abc");
+        // the delete is gone once something else changes
+        change(&mut history, &mut text, ChangeSet::new([Change::insert(0, "x")]));
+        assert!(history.redo().is_none());
+        assert_eq!(history.undo().unwrap().0.len(), 1);
+    }
+}
@@ -0,0 +1,54 @@
+use std::future::Future;
+
+use futures_util::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+
+use crate::editor::KEditor;
+
+/// runs on the editor once the job that returned it is done
+pub type Callback = Box<dyn FnOnce(&mut KEditor) -> anyhow::Result<()> + Send>;
+
+type JobFuture = BoxFuture<'static, anyhow::Result<Option<Callback>>>;
+
+/// Background work like language server requests. Jobs run concurrently with the event loop
+/// and may hand back a callback to update the editor with their result.
+#[derive(Default)]
+pub struct Jobs {
+    futures: FuturesUnordered<JobFuture>,
+}
+
+impl std::fmt::Debug for Jobs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jobs")
+            .field("running", &self.futures.len())
+            .finish()
+    }
+}
+
+impl Jobs {
+    pub fn spawn<F>(&mut self, f: F)
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.futures.push(f.map(|r| r.map(|()| None)).boxed());
+    }
+
+    pub fn callback<F>(&mut self, f: F)
+    where
+        F: Future<Output = anyhow::Result<Callback>> + Send + 'static,
+    {
+        self.futures.push(f.map(|r| r.map(Some)).boxed());
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.futures.is_empty()
+    }
+
+    /// next finished job, pending forever while there are none
+    pub async fn next(&mut self) -> anyhow::Result<Option<Callback>> {
+        match self.futures.next().await {
+            Some(result) => result,
+            None => std::future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use crossterm::event::KeyCode;
+use kk_core::{syntax::Language, DocumentMode};
+
+use crate::commands::KCommand;
+
+use super::tree::{KeyInputTypes, KeymapTree};
+
+/// something in a keymap that loads fine but probably does not do what was meant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapWarning {
+    /// set for the bindings of `[lang.<name>.keys]`
+    pub language: Option<Language>,
+    pub mode: DocumentMode,
+    pub keys: Vec<KeyInputTypes>,
+    pub kind: WarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// bound twice in one config under different spellings, e.g. `S-a` and `A`
+    Duplicate {
+        replaced: Vec<&'static str>,
+        by: Vec<&'static str>,
+    },
+    /// bound on its own and as a prefix, the commands only run after `key-timeout`
+    Shadowed { commands: Vec<&'static str> },
+    /// never reached when typing
+    Unreachable { reason: &'static str },
+    /// the user config binds what the base config already binds
+    Redundant { commands: Vec<&'static str> },
+    /// the user config rebinds a binding of the base config
+    Overridden { base: Vec<&'static str> },
+    /// the user config binds a prefix of the base config directly, dropping its bindings
+    Replaced { bindings: usize },
+}
+
+impl KeymapWarning {
+    pub fn new(mode: DocumentMode, keys: Vec<KeyInputTypes>, kind: WarningKind) -> Self {
+        Self {
+            language: None,
+            mode,
+            keys,
+            kind,
+        }
+    }
+
+    pub fn in_language(self, language: Language) -> Self {
+        Self {
+            language: Some(language),
+            ..self
+        }
+    }
+
+    /// overriding the base config is what the user config is for, it is only worth a note
+    pub fn is_note(&self) -> bool {
+        matches!(self.kind, WarningKind::Overridden { .. })
+    }
+}
+
+impl std::fmt::Display for KeymapWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys: Vec<_> = self.keys.iter().map(|k| k.to_string()).collect();
+        if let Some(language) = self.language {
+            write!(f, "{} ", language.name())?;
+        }
+        write!(f, "{}: `{}` ", self.mode.name(), keys.join(" "))?;
+        match &self.kind {
+            WarningKind::Duplicate { replaced, by } => write!(
+                f,
+                "is bound twice, `{}` replaces `{}`",
+                by.join(", "),
+                replaced.join(", ")
+            ),
+            WarningKind::Shadowed { commands } => write!(
+                f,
+                "also starts longer sequences, `{}` only runs after key-timeout",
+                commands.join(", ")
+            ),
+            WarningKind::Unreachable { reason } => write!(f, "can never be typed, {}", reason),
+            WarningKind::Redundant { commands } => write!(
+                f,
+                "is bound to `{}` in the base config already",
+                commands.join(", ")
+            ),
+            WarningKind::Overridden { base } => {
+                write!(f, "overrides `{}` of the base config", base.join(", "))
+            }
+            WarningKind::Replaced { bindings } => write!(
+                f,
+                "is a prefix in the base config, binding it drops {} sequences",
+                bindings
+            ),
+        }
+    }
+}
+
+pub fn names(commands: &[&'static KCommand]) -> Vec<&'static str> {
+    commands.iter().map(|c| c.name).collect()
+}
+
+/// calls `f` for every node below `tree` with the keys leading to it, in key order
+fn walk(
+    tree: &KeymapTree,
+    keys: &mut Vec<KeyInputTypes>,
+    f: &mut impl FnMut(&[KeyInputTypes], &[&'static KCommand], Option<&KeymapTree>),
+) {
+    let mut nodes: Vec<_> = tree.nodes.iter().collect();
+    nodes.sort_by_key(|(node, _)| node.key().to_string());
+    for (node, subtree) in nodes {
+        keys.push(node.key().clone());
+        f(keys, &node.get_cmds(), subtree.as_deref());
+        if let Some(subtree) = subtree {
+            walk(subtree, keys, f);
+        }
+        keys.pop();
+    }
+}
+
+/// number of bindings with commands below `tree`
+fn count_bindings(tree: &KeymapTree) -> usize {
+    let mut count = 0;
+    walk(tree, &mut Vec::new(), &mut |_, cmds, _| {
+        count += !cmds.is_empty() as usize
+    });
+    count
+}
+
+/// shadowed and unreachable bindings of a loaded tree
+pub fn check_tree(mode: &DocumentMode, tree: &KeymapTree) -> Vec<KeymapWarning> {
+    let mut warnings = Vec::new();
+    walk(tree, &mut Vec::new(), &mut |keys, cmds, subtree| {
+        let (last, prefix) = keys.split_last().expect("walk passes at least one key");
+        // only the first key that can not be typed is reported
+        if prefix.iter().enumerate().any(|(i, key)| cancels(i, key))
+            || prefix.iter().rev().skip(1).any(is_wildcard)
+        {
+            return;
+        }
+        let kind = if cancels(prefix.len(), last) {
+            WarningKind::Unreachable {
+                reason: "esc cancels a pending sequence",
+            }
+        } else if prefix.last().is_some_and(is_wildcard) {
+            WarningKind::Unreachable {
+                reason: "the wildcard before it ends the sequence",
+            }
+        } else if !cmds.is_empty() && subtree.is_some_and(|t| count_bindings(t) > 0) {
+            WarningKind::Shadowed {
+                commands: names(cmds),
+            }
+        } else {
+            return;
+        };
+        warnings.push(KeymapWarning::new(mode.clone(), keys.to_vec(), kind));
+    });
+    warnings
+}
+
+/// esc after the first key of a sequence cancels it
+fn cancels(position: usize, key: &KeyInputTypes) -> bool {
+    position > 0 && matches!(key, KeyInputTypes::MATCH(key) if key.code == KeyCode::Esc)
+}
+
+fn is_wildcard(key: &KeyInputTypes) -> bool {
+    matches!(key, KeyInputTypes::MATCH_ALL | KeyInputTypes::MATCH_NONE)
+}
+
+/// bindings of the user config that repeat, override or drop bindings of the base config
+pub fn check_layers(
+    mode: &DocumentMode,
+    base: &KeymapTree,
+    user: &KeymapTree,
+) -> Vec<KeymapWarning> {
+    let mut warnings = Vec::new();
+    walk(user, &mut Vec::new(), &mut |keys, cmds, _| {
+        let Some((base_cmds, base_subtree)) = base.get_chain(keys) else {
+            return;
+        };
+        let commands = names(cmds);
+        let base_names = names(&base_cmds);
+        let kind = if base_names.is_empty() {
+            match base_subtree.map(|t| count_bindings(&t)) {
+                Some(bindings) if bindings > 0 && !commands.is_empty() => {
+                    WarningKind::Replaced { bindings }
+                }
+                _ => return,
+            }
+        } else if base_names == commands {
+            WarningKind::Redundant { commands }
+        } else {
+            WarningKind::Overridden { base: base_names }
+        };
+        warnings.push(KeymapWarning::new(mode.clone(), keys.to_vec(), kind));
+    });
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use kk_core::DocumentMode;
+
+    use crate::{
+        commands::KCommand,
+        keymap::{input::KeyInput, tree::KeymapTree},
+    };
+
+    use super::{check_layers, check_tree, KeyInputTypes, WarningKind};
+
+    fn keys(keys: &str) -> Vec<KeyInputTypes> {
+        keys.split(' ')
+            .map(|k| match k {
+                "any" => KeyInputTypes::MATCH_ALL,
+                k => KeyInputTypes::MATCH(KeyInput::from_str(k).unwrap()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shadowed_and_unreachable() {
+        let mut tree = KeymapTree::new();
+        tree.insert_chain(keys("g"), vec![&KCommand::nop]);
+        tree.insert_chain(keys("g g"), vec![&KCommand::quit]);
+        tree.insert_chain(keys("space esc"), vec![&KCommand::quit]);
+        tree.insert_chain(keys("space esc a"), vec![&KCommand::quit]);
+        tree.insert_chain(keys("any a"), vec![&KCommand::quit]);
+        tree.insert_chain(keys("any a b"), vec![&KCommand::quit]);
+        tree.insert_chain(keys("esc"), vec![&KCommand::normal_mode]);
+
+        let warnings: Vec<_> = check_tree(&DocumentMode::Normal, &tree)
+            .iter()
+            .map(|w| w.to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "normal: `any a` can never be typed, the wildcard before it ends the sequence",
+                "normal: `g` also starts longer sequences, `nop` only runs after key-timeout",
+                "normal: `space esc` can never be typed, esc cancels a pending sequence",
+            ]
+        );
+    }
+
+    #[test]
+    fn layers() {
+        let mut base = KeymapTree::new();
+        base.insert_chain(keys("q"), vec![&KCommand::quit]);
+        base.insert_chain(keys("i"), vec![&KCommand::insert_mode]);
+        base.insert_chain(keys("d"), vec![&KCommand::normal_mode]);
+        base.insert_chain(keys("space a"), vec![&KCommand::nop]);
+        base.insert_chain(keys("space b"), vec![&KCommand::nop]);
+        let mut user = KeymapTree::new();
+        user.insert_chain(keys("q"), vec![&KCommand::quit]);
+        user.insert_chain(keys("i"), vec![&KCommand::nop]);
+        user.insert_chain(keys("space"), vec![&KCommand::nop]);
+        user.insert_chain(keys("x"), vec![&KCommand::nop]);
+        user.insert_chain(keys("d a"), vec![&KCommand::nop]);
+
+        let warnings = check_layers(&DocumentMode::Normal, &base, &user);
+        let kinds: Vec<_> = warnings.iter().map(|w| w.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                WarningKind::Overridden {
+                    base: vec!["normal_mode"]
+                },
+                WarningKind::Overridden {
+                    base: vec!["insert_mode"]
+                },
+                WarningKind::Redundant {
+                    commands: vec!["quit"]
+                },
+                WarningKind::Replaced { bindings: 2 },
+            ]
+        );
+        assert!(warnings[0].is_note());
+        assert!(!warnings[2].is_note());
+    }
+}
@@ -1,17 +1,52 @@
-use crossterm::event::{KeyCode, MediaKeyCode, ModifierKeyCode};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MediaKeyCode, ModifierKeyCode};
 
 use anyhow::anyhow;
 
 
 /// represents key input mappable in Config
-/// todo: add key modifiers
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy, Hash)]
 pub struct KeyInput {
     pub code: KeyCode,
+    /// Only ctrl, alt and shift. Shift is part of the char for printable chars, `S-a` is `A`,
+    /// only whitespace keeps it, e.g. `S-space`.
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyInput {
+    /// moves shift into the char, `C-S-a` and `C-A` are the same key, and backtab is `S-tab`
+    fn normalized(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) && !c.is_whitespace() => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                let mut upper = c.to_uppercase();
+                match (upper.next(), upper.next()) {
+                    (Some(upper), None) => KeyCode::Char(upper),
+                    _ => KeyCode::Char(c),
+                }
+            }
+            // crossterm reports shift-tab as a key of its own
+            KeyCode::BackTab => {
+                modifiers.insert(KeyModifiers::SHIFT);
+                KeyCode::Tab
+            }
+            code => code,
+        };
+        Self { code, modifiers }
+    }
+}
+
+/// Without the kitty keyboard protocol `C-i` comes in as `tab` and `C-m` as `ret`. With it the
+/// terminal sends the shifted symbol, `C-S-1` is `C-!`.
+impl From<KeyEvent> for KeyInput {
+    fn from(event: KeyEvent) -> Self {
+        let modifiers =
+            event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        Self::normalized(event.code, modifiers)
+    }
 }
 
 /// Taken from helix_view::input 
-/// effictively only takes the last key separated by "-" 
+/// the last token separated by "-" is the key, `C-`, `A-` and `S-` before it are modifiers
 impl std::str::FromStr for KeyInput {
     type Err = anyhow::Error;
 
@@ -84,7 +119,98 @@ impl std::str::FromStr for KeyInput {
             invalid => return Err(anyhow!("Invalid key code '{}'", invalid)),
         };
 
-        Ok(KeyInput { code })
+        let mut modifiers = KeyModifiers::NONE;
+        for token in tokens {
+            match token {
+                "C" => modifiers.insert(KeyModifiers::CONTROL),
+                "A" => modifiers.insert(KeyModifiers::ALT),
+                "S" => modifiers.insert(KeyModifiers::SHIFT),
+                invalid => {
+                    return Err(anyhow!(
+                        "Invalid modifier '{}' in '{}', modifiers are C-, A- and S-",
+                        invalid,
+                        s
+                    ))
+                }
+            }
+        }
+        Ok(KeyInput::normalized(code, modifiers))
+    }
+}
+
+/// the inverse of `from_str`, e.g. `C-space`
+impl std::fmt::Display for KeyInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.code == KeyCode::BackTab {
+            return Self::normalized(self.code, self.modifiers).fmt(f);
+        }
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("A-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            f.write_str("S-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str(keys::SPACE),
+            KeyCode::Char('-') => f.write_str(keys::MINUS),
+            KeyCode::Char('<') => f.write_str(keys::LESS_THAN),
+            KeyCode::Char('>') => f.write_str(keys::GREATER_THAN),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::Backspace => f.write_str(keys::BACKSPACE),
+            KeyCode::Enter => f.write_str(keys::ENTER),
+            KeyCode::Left => f.write_str(keys::LEFT),
+            KeyCode::Right => f.write_str(keys::RIGHT),
+            KeyCode::Up => f.write_str(keys::UP),
+            KeyCode::Down => f.write_str(keys::DOWN),
+            KeyCode::Home => f.write_str(keys::HOME),
+            KeyCode::End => f.write_str(keys::END),
+            KeyCode::PageUp => f.write_str(keys::PAGEUP),
+            KeyCode::PageDown => f.write_str(keys::PAGEDOWN),
+            KeyCode::Tab => f.write_str(keys::TAB),
+            KeyCode::Delete => f.write_str(keys::DELETE),
+            KeyCode::Insert => f.write_str(keys::INSERT),
+            KeyCode::Null => f.write_str(keys::NULL),
+            KeyCode::Esc => f.write_str(keys::ESC),
+            KeyCode::CapsLock => f.write_str(keys::CAPS_LOCK),
+            KeyCode::ScrollLock => f.write_str(keys::SCROLL_LOCK),
+            KeyCode::NumLock => f.write_str(keys::NUM_LOCK),
+            KeyCode::PrintScreen => f.write_str(keys::PRINT_SCREEN),
+            KeyCode::Pause => f.write_str(keys::PAUSE),
+            KeyCode::Menu => f.write_str(keys::MENU),
+            KeyCode::KeypadBegin => f.write_str(keys::KEYPAD_BEGIN),
+            KeyCode::Media(MediaKeyCode::Play) => f.write_str(keys::PLAY),
+            KeyCode::Media(MediaKeyCode::Pause) => f.write_str(keys::PAUSE_MEDIA),
+            KeyCode::Media(MediaKeyCode::PlayPause) => f.write_str(keys::PLAY_PAUSE),
+            KeyCode::Media(MediaKeyCode::Stop) => f.write_str(keys::STOP),
+            KeyCode::Media(MediaKeyCode::Reverse) => f.write_str(keys::REVERSE),
+            KeyCode::Media(MediaKeyCode::FastForward) => f.write_str(keys::FAST_FORWARD),
+            KeyCode::Media(MediaKeyCode::Rewind) => f.write_str(keys::REWIND),
+            KeyCode::Media(MediaKeyCode::TrackNext) => f.write_str(keys::TRACK_NEXT),
+            KeyCode::Media(MediaKeyCode::TrackPrevious) => f.write_str(keys::TRACK_PREVIOUS),
+            KeyCode::Media(MediaKeyCode::Record) => f.write_str(keys::RECORD),
+            KeyCode::Media(MediaKeyCode::LowerVolume) => f.write_str(keys::LOWER_VOLUME),
+            KeyCode::Media(MediaKeyCode::RaiseVolume) => f.write_str(keys::RAISE_VOLUME),
+            KeyCode::Media(MediaKeyCode::MuteVolume) => f.write_str(keys::MUTE_VOLUME),
+            KeyCode::Modifier(ModifierKeyCode::LeftShift) => f.write_str(keys::LEFT_SHIFT),
+            KeyCode::Modifier(ModifierKeyCode::LeftControl) => f.write_str(keys::LEFT_CONTROL),
+            KeyCode::Modifier(ModifierKeyCode::LeftAlt) => f.write_str(keys::LEFT_ALT),
+            KeyCode::Modifier(ModifierKeyCode::LeftSuper) => f.write_str(keys::LEFT_SUPER),
+            KeyCode::Modifier(ModifierKeyCode::LeftHyper) => f.write_str(keys::LEFT_HYPER),
+            KeyCode::Modifier(ModifierKeyCode::LeftMeta) => f.write_str(keys::LEFT_META),
+            KeyCode::Modifier(ModifierKeyCode::RightShift) => f.write_str(keys::RIGHT_SHIFT),
+            KeyCode::Modifier(ModifierKeyCode::RightControl) => f.write_str(keys::RIGHT_CONTROL),
+            KeyCode::Modifier(ModifierKeyCode::RightAlt) => f.write_str(keys::RIGHT_ALT),
+            KeyCode::Modifier(ModifierKeyCode::RightSuper) => f.write_str(keys::RIGHT_SUPER),
+            KeyCode::Modifier(ModifierKeyCode::RightHyper) => f.write_str(keys::RIGHT_HYPER),
+            KeyCode::Modifier(ModifierKeyCode::RightMeta) => f.write_str(keys::RIGHT_META),
+            KeyCode::Modifier(ModifierKeyCode::IsoLevel3Shift) => f.write_str(keys::ISO_LEVEL_3_SHIFT),
+            KeyCode::Modifier(ModifierKeyCode::IsoLevel5Shift) => f.write_str(keys::ISO_LEVEL_5_SHIFT),
+            code => write!(f, "{:?}", code),
+        }
     }
 }
 
@@ -148,7 +274,8 @@ pub(crate) mod keys {
 mod tests {
     use std::str::FromStr;
 
-    use crossterm::event::KeyCode;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MediaKeyCode, ModifierKeyCode};
+    use proptest::prelude::*;
 
     use super::KeyInput;
 
@@ -167,10 +294,109 @@ mod tests {
             assert_eq!(key.code, KeyCode::Char(' '))
         }
         {
-            let key = KeyInput::from_str("space-w").unwrap();
-            assert_eq!(key.code, KeyCode::Char('w'))
+            let key = KeyInput::from_str("C-n").unwrap();
+            assert_eq!(key.code, KeyCode::Char('n'));
+            assert_eq!(key.modifiers, KeyModifiers::CONTROL);
+        }
+        {
+            let key = KeyInput::from_str("C-A-ret").unwrap();
+            assert_eq!(key.code, KeyCode::Enter);
+            assert_eq!(key.modifiers, KeyModifiers::CONTROL | KeyModifiers::ALT);
         }
     }
+
+    #[test]
+    fn display_round_trip() {
+        for key in ["a", "space", "C-n", "A-S-ret", "minus", "F5", "tab", "C-lt", "del"] {
+            assert_eq!(KeyInput::from_str(key).unwrap().to_string(), key);
+        }
+    }
+
+    fn key_input() -> impl Strategy<Value = KeyInput> {
+        let named = vec![
+            KeyCode::Backspace,
+            KeyCode::Enter,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::Up,
+            KeyCode::Down,
+            KeyCode::Home,
+            KeyCode::End,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::Tab,
+            KeyCode::Delete,
+            KeyCode::Insert,
+            KeyCode::Null,
+            KeyCode::Esc,
+            KeyCode::CapsLock,
+            KeyCode::Menu,
+            KeyCode::Media(MediaKeyCode::PlayPause),
+            KeyCode::Modifier(ModifierKeyCode::LeftControl),
+        ];
+        let code = prop_oneof![
+            any::<char>().prop_map(KeyCode::Char),
+            (1u8..13).prop_map(KeyCode::F),
+            proptest::sample::select(named),
+        ];
+        (code, any::<(bool, bool, bool)>()).prop_map(|(code, (ctrl, alt, shift))| {
+            let mut modifiers = KeyModifiers::NONE;
+            modifiers.set(KeyModifiers::CONTROL, ctrl);
+            modifiers.set(KeyModifiers::ALT, alt);
+            // shift is part of the char unless it is whitespace
+            let char_shift = matches!(code, KeyCode::Char(c) if !c.is_whitespace());
+            modifiers.set(KeyModifiers::SHIFT, shift && !char_shift);
+            KeyInput { code, modifiers }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn parse_display(key in key_input()) {
+            prop_assert_eq!(KeyInput::from_str(&key.to_string()).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn from_key_event() {
+        let event = KeyEvent::new(KeyCode::Char('N'), KeyModifiers::SHIFT);
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("N").unwrap());
+        let event = KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL);
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("C-n").unwrap());
+        assert_ne!(KeyInput::from(event), KeyInput::from_str("n").unwrap());
+    }
+
+    #[test]
+    fn enhanced_keys() {
+        let ctrl_shift = KeyModifiers::CONTROL | KeyModifiers::SHIFT;
+        let event = KeyEvent::new(KeyCode::Char('a'), ctrl_shift);
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("C-A").unwrap());
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("C-S-a").unwrap());
+        assert_eq!(KeyInput::from(event).to_string(), "C-A");
+        let event = KeyEvent::new(KeyCode::Char('!'), KeyModifiers::SHIFT);
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("!").unwrap());
+
+        let event = KeyEvent::new(KeyCode::Char(' '), KeyModifiers::SHIFT);
+        assert_eq!(KeyInput::from(event).to_string(), "S-space");
+        assert_ne!(KeyInput::from(event), KeyInput::from_str("space").unwrap());
+
+        let event = KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT);
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("S-tab").unwrap());
+        assert_eq!(KeyInput::from(event).to_string(), "S-tab");
+        let event = KeyEvent::new(KeyCode::BackTab, KeyModifiers::NONE);
+        assert_eq!(KeyInput::from(event), KeyInput::from_str("S-tab").unwrap());
+        let backtab = KeyInput {
+            code: KeyCode::BackTab,
+            modifiers: KeyModifiers::CONTROL,
+        };
+        assert_eq!(backtab.to_string(), "C-S-tab");
+
+        // only told apart with the kitty keyboard protocol
+        let event = KeyEvent::new(KeyCode::Char('i'), KeyModifiers::CONTROL);
+        assert_ne!(KeyInput::from(event), KeyInput::from_str("tab").unwrap());
+        let event = KeyEvent::new(KeyCode::Char('m'), KeyModifiers::CONTROL);
+        assert_ne!(KeyInput::from(event), KeyInput::from_str("ret").unwrap());
+    }
     #[test]
     fn parse_test_not_eq(){
         {
@@ -181,5 +407,9 @@ mod tests {
             let key = KeyInput::from_str("doesnotexits");
             assert!(key.is_err())
         }
+        // unknown modifiers are not ignored
+        for key in ["space-w", "Ctrl-s", "c-s", "-a"] {
+            assert!(KeyInput::from_str(key).is_err(), "{}", key);
+        }
     }
 }
@@ -1,8 +1,17 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use kk_core::DocumentMode;
+use arc_swap::ArcSwap;
+use crossterm::event::KeyCode;
+use kk_core::{syntax::Language, DocumentMode};
 
-use crate::commands::KCommand;
+use crate::{
+    clock::{Clock, SystemClock},
+    commands::KCommand,
+};
 
 use super::{
     input::KeyInput,
@@ -12,92 +21,263 @@ use super::{
 #[derive(Debug)]
 pub struct Keymap {
     active_mode: DocumentMode,
+    /// of the current document
+    language: Option<Language>,
     state: Option<Arc<KeymapTree>>,
-    maps: HashMap<DocumentMode, ArcKeymapTree>,
+    /// keys leading to `state`
+    pending_keys: Vec<KeyInput>,
+    /// digits typed before a binding, e.g. the 3 of `3 w`
+    count: Option<usize>,
+    /// commands of a pending node that also has children and when they run on their own
+    fallback: Option<(Vec<&'static KCommand>, Instant)>,
+    timeout: Duration,
+    clock: Arc<dyn Clock>,
+    /// swapped as a whole when the config is reloaded
+    maps: ArcSwap<KeymapTrees>,
+}
+
+/// the trees of every mode, and of the languages with bindings of their own
+#[derive(Debug, Clone, Default)]
+pub struct KeymapTrees {
+    pub modes: HashMap<DocumentMode, ArcKeymapTree>,
+    /// these already contain the bindings of the mode
+    pub languages: HashMap<Language, HashMap<DocumentMode, ArcKeymapTree>>,
+}
+
+impl KeymapTrees {
+    /// the tree of the language if it has one, the tree of the mode otherwise
+    pub fn get(&self, language: Option<Language>, mode: &DocumentMode) -> Option<&ArcKeymapTree> {
+        language
+            .and_then(|language| self.languages.get(&language)?.get(mode))
+            .or_else(|| self.modes.get(mode))
+    }
 }
 
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
 impl Keymap {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             active_mode: DocumentMode::Normal,
+            language: None,
             state: None,
-            maps: HashMap::new(),
+            pending_keys: Vec::new(),
+            count: None,
+            fallback: None,
+            timeout: DEFAULT_TIMEOUT,
+            clock,
+            maps: ArcSwap::from_pointee(KeymapTrees::default()),
+        }
+    }
+
+    /// how long a node with both commands and children waits for the rest of a sequence
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_mode(&mut self, mode: DocumentMode) {
+        self.active_mode = mode;
+        self.cancel();
+    }
+
+    /// a sequence started in a document of another language is dropped
+    pub fn set_language(&mut self, language: Option<Language>) {
+        if self.language != language {
+            self.language = language;
+            self.cancel();
+        }
+    }
+
+    fn root(&self) -> ArcKeymapTree {
+        self.maps
+            .load()
+            .get(self.language, &self.active_mode)
+            .expect("every mode has a tree")
+            .clone()
+    }
+
+    /// drops the pending sequence and count
+    pub fn cancel(&mut self) {
+        self.state = None;
+        self.fallback = None;
+        self.pending_keys.clear();
+        self.count = None;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.state.is_some() || self.count.is_some()
+    }
+
+    /// count for the next commands, read it before the key that completes the binding
+    pub fn count(&self) -> Option<usize> {
+        self.count
+    }
+
+    /// what was typed of the next binding, e.g. `3 space w`
+    pub fn pending_input(&self) -> Option<String> {
+        if !self.is_pending() {
+            return None;
         }
+        let keys = self.pending_keys.iter().map(|k| k.to_string());
+        let input: Vec<_> = self.count.map(|c| c.to_string()).into_iter().chain(keys).collect();
+        Some(input.join(" "))
     }
 
+    /// Digits count unless the mode binds them, a count does not start with 0. Modes with
+    /// an `any` binding like insert take digits literally.
+    fn is_count_digit(&self, key: KeyInput) -> bool {
+        let KeyCode::Char(c) = key.code else {
+            return false;
+        };
+        if !c.is_ascii_digit() || !key.modifiers.is_empty() || self.state.is_some() {
+            return false;
+        }
+        if c == '0' && self.count.is_none() {
+            return false;
+        }
+        let root = self.root();
+        root.get_fun(&KeymapNode::new(KeyInputTypes::MATCH(key))).is_none()
+            && root.get_fun(&KeymapNode::new(KeyInputTypes::MATCH_ALL)).is_none()
+    }
+
+    /// when the fallback of the pending sequence runs unless another key comes first
+    pub fn deadline(&self) -> Option<Instant> {
+        self.fallback.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    /// commands of the pending sequence once it timed out, the sequence is over then
+    pub fn check_timeout(&mut self) -> Vec<&'static KCommand> {
+        match self.deadline() {
+            Some(deadline) if self.clock.now() >= deadline => {
+                let (cmds, _) = self.fallback.take().expect("there is a deadline");
+                self.cancel();
+                cmds
+            }
+            _ => vec![],
+        }
+    }
+
+    /// commands of the pending sequence as if it timed out now, the sequence is over then
+    pub fn take_fallback(&mut self) -> Vec<&'static KCommand> {
+        let cmds = self.fallback.take().map(|(cmds, _)| cmds).unwrap_or_default();
+        self.cancel();
+        cmds
+    }
+
+    /// the subtree of a started sequence and the keys typed so far
+    pub fn pending(&self) -> Option<(&KeymapTree, &[KeyInput])> {
+        self.state
+            .as_deref()
+            .map(|state| (state, self.pending_keys.as_slice()))
+    }
+
+    /// the tree of every mode for the language of the current document
+    pub fn trees(&self) -> HashMap<DocumentMode, ArcKeymapTree> {
+        let maps = self.maps.load();
+        maps.modes
+            .keys()
+            .filter_map(|mode| Some((mode.clone(), maps.get(self.language, mode)?.clone())))
+            .collect()
+    }
+
+    #[cfg(test)]
     pub fn load_keymap_tree(&mut self, doc_mod: DocumentMode, tree: ArcKeymapTree) {
-        self.maps.insert(doc_mod, tree);
+        let mut maps = KeymapTrees::clone(&self.maps.load());
+        maps.modes.insert(doc_mod, tree);
+        self.maps.store(Arc::new(maps));
     }
 
-    pub fn merge(&mut self, _map: HashMap<DocumentMode, ArcKeymapTree>) {
-        todo!()
+    /// replaces the trees of all modes, a started sequence belongs to the old ones and is
+    /// dropped
+    pub fn replace_trees(&mut self, trees: KeymapTrees) {
+        self.maps.store(Arc::new(trees));
+        self.cancel();
     }
 
     pub fn get(&mut self, key: KeyInput) -> Vec<&'static KCommand> {
+        // the timer may not have fired yet
+        let mut cmds = self.check_timeout();
+        if key.code == KeyCode::Esc && self.is_pending() {
+            self.cancel();
+            return cmds;
+        }
+        if self.is_count_digit(key) {
+            let digit = match key.code {
+                KeyCode::Char(c) => c.to_digit(10).unwrap_or(0) as usize,
+                _ => 0,
+            };
+            let count = self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+            self.count = Some(count);
+            return cmds;
+        }
+        cmds.extend(self.lookup(key));
+        match self.state {
+            Some(_) => self.pending_keys.push(key),
+            None => {
+                self.pending_keys.clear();
+                self.count = None;
+            }
+        }
+        cmds
+    }
+
+    /// Follows `key` from the pending subtree or the root of the mode. A node with both
+    /// commands and children waits for the next key, its commands are the fallback that
+    /// runs if the sequence is not continued before the timeout.
+    fn lookup(&mut self, key: KeyInput) -> Vec<&'static KCommand> {
         let key_node = KeymapNode::new(KeyInputTypes::MATCH(key));
         let all_node = KeymapNode::new(KeyInputTypes::MATCH_ALL);
         let none_node = KeymapNode::new(KeyInputTypes::MATCH_NONE);
-        if self.state.is_some() {
-            let state = self.state.as_ref().unwrap().clone();
-            let cmds = match state.get_fun(&key_node) {
-                Some((c, new_state)) => {
-                    match new_state {
-                        Some(s) => self.state = Some(s),
-                        None => self.state = None,
-                    }
-                    c
-                }
-                None => match state.get_fun(&all_node) {
-                    Some((c, _new_state)) => {
-                        self.state = None;
-                        c
-                    }
-                    None => match state.get_fun(&none_node) {
-                        Some((c, _new_state)) => {
-                            self.state = None;
-                            c
-                        }
-                        None => vec![],
-                    },
-                },
-            };
-            cmds
-        } else {
-            let mode_tree = self.maps.get(&self.active_mode).unwrap();
-            let cmds = match mode_tree.get_fun(&key_node) {
-                Some((c, new_state)) => {
-                    match new_state {
-                        Some(s) => self.state = Some(s),
-                        None => self.state = None,
-                    }
-                    c
-                }
-                None => match mode_tree.get_fun(&all_node) {
-                    Some((c, _new_state)) => {
-                        self.state = None;
-                        c
-                    }
-                    None => match mode_tree.get_fun(&none_node) {
-                        Some((c, _new_state)) => {
-                            self.state = None;
-                            c
-                        }
-                        None => vec![],
-                    },
-                },
+        let tree = match self.state.take() {
+            Some(state) => state,
+            None => self.root(),
+        };
+
+        if let Some((cmds, subtree)) = tree.get_fun(&key_node) {
+            self.fallback = None;
+            let Some(subtree) = subtree else {
+                return cmds;
             };
-            cmds
+            self.state = Some(subtree);
+            if !cmds.is_empty() {
+                let deadline = self.clock.now() + self.timeout;
+                self.fallback = Some((cmds, deadline));
+            }
+            return vec![];
+        }
+        // wildcards end a sequence without continuing it
+        if let Some((cmds, _)) = tree
+            .get_fun(&all_node)
+            .or_else(|| tree.get_fun(&none_node))
+        {
+            self.fallback = None;
+            return cmds;
+        }
+        match self.fallback.take() {
+            // the shorter binding wins, the key starts over without the old keys and count
+            Some((mut cmds, _)) => {
+                self.pending_keys.clear();
+                self.count = None;
+                cmds.extend(self.lookup(key));
+                cmds
+            }
+            None => vec![],
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{str::FromStr, sync::Arc};
+    use std::{str::FromStr, sync::Arc, time::Duration};
 
-    use kk_core::DocumentMode;
+    use kk_core::{syntax::Language, DocumentMode};
 
     use crate::{
+        clock::MockClock,
         commands::KCommand,
         keymap::{
             input::KeyInput,
@@ -105,7 +285,7 @@ mod tests {
         },
     };
 
-    use super::Keymap;
+    use super::{Keymap, KeymapTrees, DEFAULT_TIMEOUT};
 
     fn setup() -> KeymapTree {
         let mut k = KeymapTree::new();
@@ -130,20 +310,21 @@ mod tests {
         let space = KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap());
         let a = KeyInputTypes::MATCH(KeyInput::from_str("a").unwrap());
         let c = KeyInputTypes::MATCH(KeyInput::from_str("c").unwrap());
-        let d = KeyInputTypes::MATCH(KeyInput::from_str("d").unwrap());
 
-        k.insert_chain(vec![space.clone()], vec![&KCommand::escape]); // todo: out of order insert
-                                                                      // while chained
         k.insert_chain(vec![space.clone(), a.clone()], vec![&KCommand::escape]);
+        k.insert_chain(vec![space.clone()], vec![&KCommand::normal_mode]);
         k.insert_chain(vec![c.clone()], vec![&KCommand::nop]);
         k.insert_single(KeymapNode::new_with_commands(
             KeyInputTypes::MATCH_ALL,
             vec![&KCommand::error],
         ));
-        println!("{:#?}", k);
         k
     }
 
+    fn names(cmds: Vec<&'static KCommand>) -> Vec<&'static str> {
+        cmds.into_iter().map(|c| c.name).collect()
+    }
+
     #[test]
     fn touch_one_key_success() {
         let mut keymap = Keymap::new();
@@ -168,9 +349,12 @@ mod tests {
         keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(setup()));
         let cmds = keymap.get(KeyInput::from_str("space").unwrap());
         assert_eq!(cmds.len(), 0);
+        let (_, keys) = keymap.pending().unwrap();
+        assert_eq!(keys, [KeyInput::from_str("space").unwrap()]);
         let cmds = keymap.get(KeyInput::from_str("a").unwrap());
         assert_eq!(cmds.len(), 1);
         assert_eq!(cmds[0].name, "escape");
+        assert!(keymap.pending().is_none());
     }
 
     #[test]
@@ -222,14 +406,180 @@ mod tests {
 
     #[test]
     fn touch_key_chain() {
-        let mut keymap = Keymap::new();
+        let clock = Arc::new(MockClock::new());
+        let mut keymap = Keymap::with_clock(clock.clone());
         keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(setup_alternative()));
 
+        // `space` alone and `space a` are bound, the longer sequence wins if it completes
         let cmds = keymap.get(KeyInput::from_str("space").unwrap());
-        assert_eq!(cmds.len(), 1);
-        assert_eq!(cmds[0].name, "escape"); // note: only works because of order 09-05-2023
+        assert!(cmds.is_empty());
+        assert!(keymap.deadline().is_some());
+        clock.advance(DEFAULT_TIMEOUT / 2);
+        assert!(keymap.check_timeout().is_empty());
         let cmds = keymap.get(KeyInput::from_str("a").unwrap());
-        assert_eq!(cmds.len(), 1);
-        assert_eq!(cmds[0].name, "escape");
+        assert_eq!(names(cmds), vec!["escape"]);
+        assert!(keymap.deadline().is_none());
+        assert!(keymap.pending().is_none());
+    }
+
+    #[test]
+    fn ambiguous_prefix_times_out() {
+        let clock = Arc::new(MockClock::new());
+        let mut keymap = Keymap::with_clock(clock.clone());
+        keymap.set_timeout(Duration::from_millis(300));
+        keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(setup_alternative()));
+
+        assert!(keymap.get(KeyInput::from_str("space").unwrap()).is_empty());
+        clock.advance(Duration::from_millis(299));
+        assert!(keymap.check_timeout().is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(names(keymap.check_timeout()), vec!["normal_mode"]);
+        assert!(keymap.pending().is_none());
+        assert!(keymap.check_timeout().is_empty());
+
+        // `a` after the timeout starts over
+        let cmds = keymap.get(KeyInput::from_str("a").unwrap());
+        assert_eq!(names(cmds), vec!["error"]);
+    }
+
+    #[test]
+    fn late_key_runs_timed_out_binding_first() {
+        let clock = Arc::new(MockClock::new());
+        let mut keymap = Keymap::with_clock(clock.clone());
+        keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(setup_alternative()));
+
+        // the timer did not fire before the next key arrived
+        keymap.get(KeyInput::from_str("space").unwrap());
+        clock.advance(DEFAULT_TIMEOUT);
+        let cmds = keymap.get(KeyInput::from_str("a").unwrap());
+        assert_eq!(names(cmds), vec!["normal_mode", "error"]);
+    }
+
+    #[test]
+    fn other_key_falls_back_to_shorter_binding() {
+        let clock = Arc::new(MockClock::new());
+        let mut keymap = Keymap::with_clock(clock.clone());
+        let mut tree = setup_alternative();
+        let space = KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap());
+        let b = KeyInputTypes::MATCH(KeyInput::from_str("b").unwrap());
+        tree.insert_chain(vec![space, b.clone(), b], vec![&KCommand::quit]);
+        keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(tree));
+
+        // `c` does not continue `space`, so `space` runs and `c` is looked up on its own
+        keymap.get(KeyInput::from_str("space").unwrap());
+        let cmds = keymap.get(KeyInput::from_str("c").unwrap());
+        assert_eq!(names(cmds), vec!["normal_mode", "nop"]);
+        assert!(keymap.pending().is_none());
+
+        // without a fallback an unknown continuation just ends the sequence
+        keymap.get(KeyInput::from_str("space").unwrap());
+        assert!(keymap.get(KeyInput::from_str("b").unwrap()).is_empty());
+        assert!(keymap.deadline().is_none());
+        assert!(keymap.get(KeyInput::from_str("c").unwrap()).is_empty());
+        assert!(keymap.pending().is_none());
+        let cmds = keymap.get(KeyInput::from_str("c").unwrap());
+        assert_eq!(names(cmds), vec!["nop"]);
+    }
+
+    #[test]
+    fn fallback_then_prefix_starts_over() {
+        let mut keymap = Keymap::new();
+        keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(setup_alternative()));
+
+        // the second `space` does not continue the first one, it starts a new sequence
+        keymap.get(KeyInput::from_str("space").unwrap());
+        let cmds = keymap.get(KeyInput::from_str("space").unwrap());
+        assert_eq!(names(cmds), vec!["normal_mode"]);
+        assert_eq!(keymap.pending_input().as_deref(), Some("space"));
+        let (_, keys) = keymap.pending().unwrap();
+        assert_eq!(keys, &[KeyInput::from_str("space").unwrap()]);
+        assert_eq!(names(keymap.get(KeyInput::from_str("a").unwrap())), vec!["escape"]);
+        assert!(keymap.pending().is_none());
+    }
+
+    #[test]
+    fn counts_and_pending_input() {
+        let mut keymap = Keymap::new();
+        let mut normal = setup();
+        normal.nodes.remove(&KeymapNode::new(KeyInputTypes::MATCH_ALL));
+        keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(normal));
+        let mut insert = KeymapTree::new();
+        insert.insert_single(KeymapNode::new_with_commands(
+            KeyInputTypes::MATCH_ALL,
+            vec![&KCommand::nop],
+        ));
+        keymap.load_keymap_tree(DocumentMode::Insert, Arc::new(insert));
+
+        // a count does not start with 0
+        assert!(keymap.get(KeyInput::from_str("0").unwrap()).is_empty());
+        assert_eq!(keymap.count(), None);
+        assert!(keymap.get(KeyInput::from_str("1").unwrap()).is_empty());
+        assert!(keymap.get(KeyInput::from_str("0").unwrap()).is_empty());
+        assert!(keymap.get(KeyInput::from_str("space").unwrap()).is_empty());
+        assert_eq!(keymap.count(), Some(10));
+        assert_eq!(keymap.pending_input().as_deref(), Some("10 space"));
+        assert_eq!(names(keymap.get(KeyInput::from_str("a").unwrap())), vec!["escape"]);
+        assert_eq!(keymap.count(), None);
+        assert_eq!(keymap.pending_input(), None);
+
+        // esc cancels instead of running
+        keymap.get(KeyInput::from_str("3").unwrap());
+        keymap.get(KeyInput::from_str("space").unwrap());
+        assert!(keymap.get(KeyInput::from_str("esc").unwrap()).is_empty());
+        assert!(!keymap.is_pending());
+        assert_eq!(names(keymap.get(KeyInput::from_str("c").unwrap())), vec!["nop"]);
+
+        // modes binding `any` take digits literally
+        keymap.set_mode(DocumentMode::Insert);
+        assert_eq!(names(keymap.get(KeyInput::from_str("3").unwrap())), vec!["nop"]);
+        assert_eq!(keymap.count(), None);
+    }
+
+    #[test]
+    fn replace_trees_drops_pending() {
+        let mut keymap = Keymap::new();
+        keymap.load_keymap_tree(DocumentMode::Normal, Arc::new(setup()));
+        let snapshot = keymap.trees();
+        assert!(keymap.get(KeyInput::from_str("space").unwrap()).is_empty());
+        assert!(keymap.is_pending());
+
+        let mut tree = KeymapTree::new();
+        let a = KeyInputTypes::MATCH(KeyInput::from_str("a").unwrap());
+        tree.insert_chain(vec![a], vec![&KCommand::quit]);
+        keymap.replace_trees(KeymapTrees {
+            modes: [(DocumentMode::Normal, Arc::new(tree))].into(),
+            ..Default::default()
+        });
+        assert!(!keymap.is_pending());
+        assert_eq!(names(keymap.get(KeyInput::from_str("a").unwrap())), vec!["quit"]);
+        // readers of the old trees keep them
+        assert_eq!(snapshot[&DocumentMode::Normal].nodes.len(), 4);
+    }
+
+    #[test]
+    fn language_trees() {
+        let mut keymap = Keymap::new();
+        let mut rust = setup();
+        let space = KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap());
+        let t = KeyInputTypes::MATCH(KeyInput::from_str("t").unwrap());
+        rust.insert_chain(vec![space, t], vec![&KCommand::quit]);
+        keymap.replace_trees(KeymapTrees {
+            modes: [(DocumentMode::Normal, Arc::new(setup()))].into(),
+            languages: [(
+                Language::Rust,
+                [(DocumentMode::Normal, Arc::new(rust))].into(),
+            )]
+            .into(),
+        });
+
+        keymap.get(KeyInput::from_str("space").unwrap());
+        assert!(keymap.get(KeyInput::from_str("t").unwrap()).is_empty());
+        keymap.set_language(Some(Language::Rust));
+        keymap.get(KeyInput::from_str("space").unwrap());
+        assert_eq!(names(keymap.get(KeyInput::from_str("t").unwrap())), vec!["quit"]);
+        // other languages use the tree of the mode
+        keymap.set_language(Some(Language::Python));
+        keymap.get(KeyInput::from_str("space").unwrap());
+        assert!(keymap.get(KeyInput::from_str("t").unwrap()).is_empty());
     }
 }
@@ -1,10 +1,4 @@
-mod input;
-mod map;
-mod tree;
-
-pub enum KeyMode {
-    Command,
-    Normal,
-    Insert,
-}
-
+pub mod check;
+pub mod input;
+pub mod map;
+pub mod tree;
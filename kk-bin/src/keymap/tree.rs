@@ -1,12 +1,10 @@
 use std::{collections::HashMap, hash::Hash, sync::Arc};
 
-use anyhow::{bail, Ok};
-
 use crate::commands::KCommand;
 
 use super::input::KeyInput;
 
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum KeyInputTypes {
     MATCH_NONE,      // does not match any key, used for root node, should match last
@@ -14,6 +12,17 @@ pub enum KeyInputTypes {
     MATCH_ALL,       // matches all keys, should match second
 }
 
+/// the key as written in the config
+impl std::fmt::Display for KeyInputTypes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyInputTypes::MATCH_NONE => f.write_str("none"),
+            KeyInputTypes::MATCH(key) => write!(f, "{}", key),
+            KeyInputTypes::MATCH_ALL => f.write_str("any"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct KeymapNode {
     key: KeyInputTypes,
@@ -46,27 +55,12 @@ impl KeymapNode {
             commands: command,
         }
     }
-    pub fn get_cmds(&self) -> Vec<&'static KCommand> {
-        self.commands.to_owned()
+    pub fn key(&self) -> &KeyInputTypes {
+        &self.key
     }
 
-    pub fn merge(self, other: Self) -> anyhow::Result<Self> {
-        if self.key != other.key {
-            bail!(
-                "Cannot merge two nodes with different keys: {:?} and {:?}",
-                self,
-                other
-            );
-        }
-
-        Ok(Self {
-            key: self.key,
-            commands: self
-                .commands
-                .into_iter()
-                .chain(other.commands.into_iter())
-                .collect(),
-        })
+    pub fn get_cmds(&self) -> Vec<&'static KCommand> {
+        self.commands.to_owned()
     }
 }
 
@@ -74,24 +68,87 @@ pub type ArcKeymapTree = Arc<KeymapTree>;
 #[derive(Debug, Clone)]
 pub struct KeymapTree {
     pub nodes: HashMap<KeymapNode, Option<ArcKeymapTree>>,
+    /// name of the prefix leading here, shown while the rest of a sequence is pending
+    pub label: Option<String>,
 }
 
 impl KeymapTree {
     pub fn new() -> Self {
         Self {
             nodes: HashMap::new(),
+            label: None,
         }
     }
 
+    /// labels the subtree at the end of `keys`, creating it if needed
+    pub fn set_label(&mut self, keys: &[KeyInputTypes], label: String) {
+        let Some((key, rest)) = keys.split_first() else {
+            self.label = Some(label);
+            return;
+        };
+        let node = KeymapNode::new(key.clone());
+        if !self.nodes.contains_key(&node) {
+            self.insert_single(node.clone());
+        }
+        let subtree = self
+            .nodes
+            .get_mut(&node)
+            .expect("node was inserted")
+            .get_or_insert_with(|| Arc::new(KeymapTree::new()));
+        Arc::make_mut(subtree).set_label(rest, label);
+    }
+
     /// looks into the keys in that node, will find the match and return the commads as well as the
     /// next subtree as Option with None indicating its a leaf
     pub fn get_fun(
         &self,
         node: &KeymapNode,
     ) -> Option<(Vec<&'static KCommand>, Option<ArcKeymapTree>)> {
-        match self.nodes.get_key_value(node) {
-            Some((key, v)) => Some((key.get_cmds(), v.clone())),
-            None => None,
+        self.nodes
+            .get_key_value(node)
+            .map(|(key, v)| (key.get_cmds(), v.clone()))
+    }
+
+    /// follows `keys` down the tree, like `get_fun` for a whole sequence
+    pub fn get_chain(
+        &self,
+        keys: &[KeyInputTypes],
+    ) -> Option<(Vec<&'static KCommand>, Option<ArcKeymapTree>)> {
+        let (last, rest) = keys.split_last()?;
+        let mut tree = self;
+        for key in rest {
+            let (_, subtree) = tree.nodes.get_key_value(&KeymapNode::new(key.clone()))?;
+            tree = subtree.as_deref()?;
+        }
+        tree.get_fun(&KeymapNode::new(last.clone()))
+    }
+
+    /// the sequences that run just the command named `name`, shortest first
+    pub fn keys_for(&self, name: &str) -> Vec<Vec<KeyInputTypes>> {
+        let mut found = Vec::new();
+        self.collect_keys(name, &mut Vec::new(), &mut found);
+        found.sort_by_cached_key(|keys| {
+            let keys: Vec<_> = keys.iter().map(|k| k.to_string()).collect();
+            (keys.len(), keys)
+        });
+        found
+    }
+
+    fn collect_keys(
+        &self,
+        name: &str,
+        keys: &mut Vec<KeyInputTypes>,
+        found: &mut Vec<Vec<KeyInputTypes>>,
+    ) {
+        for (node, subtree) in &self.nodes {
+            keys.push(node.key.clone());
+            if matches!(node.commands.as_slice(), [command] if command.name == name) {
+                found.push(keys.clone());
+            }
+            if let Some(subtree) = subtree {
+                subtree.collect_keys(name, keys, found);
+            }
+            keys.pop();
         }
     }
 
@@ -99,7 +156,7 @@ impl KeymapTree {
         self.nodes.insert(node, None);
     }
 
-    /// Inserst with commands inserted on the last node
+    /// Inserst with commands inserted on the last node, replacing the commands it had
     /// todo: performance due to make_mut??
     pub fn insert_chain(&mut self, keys: Vec<KeyInputTypes>, commands: Vec<&'static KCommand>) {
         let last_item = keys.len() - 1;
@@ -113,6 +170,12 @@ impl KeymapTree {
             };
             // check whether node alrea exists
             match search_tree.nodes.contains_key(&node) {
+                true if i == last_item => {
+                    // rebinding keeps the longer sequences starting here
+                    let subtree = search_tree.nodes.remove(&node).unwrap();
+                    search_tree.nodes.insert(node, subtree);
+                    break;
+                }
                 true => {
                     // exists, go down the tree
                     let tree = search_tree.nodes.get_mut(&node).unwrap();
@@ -122,12 +185,8 @@ impl KeymapTree {
                             search_tree = Arc::make_mut(subtree);
                         }
                         None => {
-                            if i != last_item {
-                                tree.replace(Arc::new(KeymapTree::new()));
-                                search_tree = Arc::make_mut(tree.as_mut().unwrap());
-                            } else {
-                                break;
-                            }
+                            tree.replace(Arc::new(KeymapTree::new()));
+                            search_tree = Arc::make_mut(tree.as_mut().unwrap());
                         }
                     }
                 }
@@ -147,11 +206,31 @@ impl KeymapTree {
     }
 }
 
+/// same keys, commands and labels all the way down
+impl PartialEq for KeymapTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label
+            && self.nodes.len() == other.nodes.len()
+            && self.nodes.iter().all(|(node, subtree)| {
+                other
+                    .nodes
+                    .get_key_value(node)
+                    .is_some_and(|(other_node, other_subtree)| {
+                        let names = |n: &KeymapNode| {
+                            n.commands.iter().map(|c| c.name).collect::<Vec<_>>()
+                        };
+                        names(node) == names(other_node) && subtree == other_subtree
+                    })
+            })
+    }
+}
+impl Eq for KeymapTree {}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use crate::keymap::input::KeyInput;
+    use crate::{commands::KCommand, keymap::input::KeyInput};
 
     use super::{KeyInputTypes, KeymapNode, KeymapTree};
 
@@ -252,4 +331,49 @@ mod tests {
         assert_eq!(ad_keys.len(), 1);
         assert!(ad_keys.contains(&KeymapNode::new(b.clone())));
     }
+
+    #[test]
+    fn insert_prefix_after_sequence() {
+        let mut k = KeymapTree::new();
+        let space = KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap());
+        let a = KeyInputTypes::MATCH(KeyInput::from_str("a").unwrap());
+        k.insert_chain(vec![space.clone(), a.clone()], vec![]);
+        k.insert_chain(vec![space.clone()], vec![&KCommand::nop]);
+
+        let (cmds, subtree) = k.get_fun(&KeymapNode::new(space)).unwrap();
+        assert_eq!(cmds[0].name, "nop");
+        assert!(subtree.unwrap().nodes.contains_key(&KeymapNode::new(a)));
+    }
+
+    #[test]
+    fn label_prefix() {
+        let mut k = KeymapTree::new();
+        let space = KeyInputTypes::MATCH(KeyInput::from_str("space").unwrap());
+        let a = KeyInputTypes::MATCH(KeyInput::from_str("a").unwrap());
+        k.set_label(std::slice::from_ref(&space), "leader".to_string());
+        k.insert_chain(vec![space.clone(), a.clone()], vec![]);
+
+        let (_, spaced) = k.get_fun(&KeymapNode::new(space)).unwrap();
+        let spaced = spaced.unwrap();
+        assert_eq!(spaced.label.as_deref(), Some("leader"));
+        assert!(spaced.nodes.contains_key(&KeymapNode::new(a)));
+    }
+
+    #[test]
+    fn keys_for_command() {
+        let mut k = KeymapTree::new();
+        let key = |k: &str| KeyInputTypes::MATCH(KeyInput::from_str(k).unwrap());
+        k.insert_chain(vec![key("space"), key("q")], vec![&KCommand::quit]);
+        k.insert_chain(vec![key("q")], vec![&KCommand::quit]);
+        k.insert_chain(vec![key("Q")], vec![&KCommand::quit]);
+        k.insert_chain(vec![key("x")], vec![&KCommand::nop, &KCommand::quit]);
+
+        let found: Vec<_> = k
+            .keys_for("quit")
+            .iter()
+            .map(|keys| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(found, vec![vec!["Q"], vec!["q"], vec!["space", "q"]]);
+        assert!(k.keys_for("hover").is_empty());
+    }
 }
@@ -0,0 +1,458 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use kk_core::{
+    diagnostic::{Diagnostic, Severity},
+    document::Document,
+    syntax::Language,
+    transaction::ChangeSet,
+};
+use kk_lsp::{
+    jsonrpc,
+    lsp::{self, Url},
+    util, Client, OffsetEncoding, ServerMessage,
+};
+use log::{error, info, warn};
+use ropey::Rope;
+use serde_json::Value;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    config::LanguageServerConfig,
+    editor::{DocumentId, KEditor},
+    job::Callback,
+};
+
+/// how long quitting waits for the servers to exit
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// the running language servers, at most one per language, started on demand
+#[derive(Debug)]
+pub struct LanguageServers {
+    configs: HashMap<Language, LanguageServerConfig>,
+    clients: HashMap<Language, Arc<Client>>,
+    next_id: usize,
+    incoming_tx: UnboundedSender<(usize, ServerMessage)>,
+    incoming: UnboundedReceiver<(usize, ServerMessage)>,
+    /// off for `--headless`, the script does not wait for servers
+    enabled: bool,
+}
+
+impl LanguageServers {
+    pub fn new(configs: HashMap<Language, LanguageServerConfig>) -> Self {
+        let (incoming_tx, incoming) = unbounded_channel();
+        Self {
+            configs,
+            clients: HashMap::new(),
+            next_id: 0,
+            incoming_tx,
+            incoming,
+            enabled: true,
+        }
+    }
+
+    /// no servers are started from now on
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    /// settings for servers started from now on
+    pub fn set_configs(&mut self, configs: HashMap<Language, LanguageServerConfig>) {
+        self.configs = configs;
+    }
+
+    /// the server of the language, it may still be initializing
+    pub fn get(&self, language: Language) -> Option<&Arc<Client>> {
+        self.clients.get(&language)
+    }
+
+    pub fn by_id(&self, id: usize) -> Option<&Arc<Client>> {
+        self.clients.values().find(|c| c.id() == id)
+    }
+
+    /// starts the server for the language unless it is already running, `None` if there is
+    /// no server configured
+    fn start(&mut self, language: Language) -> Option<anyhow::Result<Arc<Client>>> {
+        if !self.enabled {
+            return None;
+        }
+        let config = self.configs.get(&language)?;
+        let root = std::env::current_dir().ok();
+        let id = self.next_id;
+        self.next_id += 1;
+        let client = Client::start(
+            &config.command,
+            &config.args,
+            root.as_deref(),
+            id,
+            self.incoming_tx.clone(),
+        )
+        .map(Arc::new)
+        .map_err(|e| anyhow!("failed to start {}: {}", config.command, e));
+        if let Ok(client) = &client {
+            self.clients.insert(language, client.clone());
+        }
+        Some(client)
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Arc<Client>> {
+        let language = *self.clients.iter().find(|(_, c)| c.id() == id)?.0;
+        self.clients.remove(&language)
+    }
+
+    /// Asks the initialized servers to shut down and exit and waits until they closed their
+    /// output, at most `SHUTDOWN_TIMEOUT`. The rest is killed when the clients are dropped.
+    pub async fn shutdown(&mut self) {
+        let clients: Vec<_> = self.clients.drain().map(|(_, client)| client).collect();
+        let shutdown = async {
+            let requests = clients.iter().filter(|c| c.is_initialized()).map(|c| async move {
+                match c.shutdown_and_exit().await {
+                    Ok(()) => Some(c.id()),
+                    Err(e) => {
+                        warn!("{}: failed to shut down: {}", c.name(), e);
+                        None
+                    }
+                }
+            });
+            let mut running: HashSet<_> = futures_util::future::join_all(requests)
+                .await
+                .into_iter()
+                .flatten()
+                .collect();
+            while !running.is_empty() {
+                if let (id, ServerMessage::Exited) = self.next_message().await {
+                    running.remove(&id);
+                }
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown).await.is_err() {
+            warn!("language servers did not exit in time");
+        }
+    }
+
+    /// never ends, the sender is kept around
+    pub async fn next_message(&mut self) -> (usize, ServerMessage) {
+        self.incoming
+            .recv()
+            .await
+            .expect("language servers sender is alive")
+    }
+}
+
+pub fn path_to_url(path: &std::path::Path) -> Option<Url> {
+    let path = std::path::absolute(path).ok()?;
+    Url::from_file_path(path).ok()
+}
+
+pub fn doc_url(doc: &Document) -> Option<Url> {
+    doc.path().and_then(path_to_url)
+}
+
+fn language_id(language: Language) -> &'static str {
+    language.name()
+}
+
+fn diagnostic_from_lsp(
+    text: &Rope,
+    diagnostic: lsp::Diagnostic,
+    encoding: OffsetEncoding,
+) -> Option<Diagnostic> {
+    let (from, to) = util::lsp_range_to_range(text, diagnostic.range, encoding)?;
+    let severity = match diagnostic.severity {
+        Some(lsp::DiagnosticSeverity::ERROR) => Severity::Error,
+        Some(lsp::DiagnosticSeverity::WARNING) => Severity::Warning,
+        Some(lsp::DiagnosticSeverity::HINT) => Severity::Hint,
+        _ => Severity::Info,
+    };
+    let code = diagnostic.code.map(|code| match code {
+        lsp::NumberOrString::Number(n) => n.to_string(),
+        lsp::NumberOrString::String(s) => s,
+    });
+    Some(Diagnostic {
+        from,
+        to,
+        severity,
+        message: diagnostic.message,
+        source: diagnostic.source,
+        code,
+    })
+}
+
+/// for the context of code action requests
+pub fn diagnostic_to_lsp(
+    text: &Rope,
+    diagnostic: &Diagnostic,
+    encoding: OffsetEncoding,
+) -> lsp::Diagnostic {
+    let severity = match diagnostic.severity {
+        Severity::Error => lsp::DiagnosticSeverity::ERROR,
+        Severity::Warning => lsp::DiagnosticSeverity::WARNING,
+        Severity::Info => lsp::DiagnosticSeverity::INFORMATION,
+        Severity::Hint => lsp::DiagnosticSeverity::HINT,
+    };
+    lsp::Diagnostic {
+        range: util::range_to_lsp_range(text, diagnostic.from, diagnostic.to, encoding),
+        severity: Some(severity),
+        code: diagnostic.code.clone().map(lsp::NumberOrString::String),
+        source: diagnostic.source.clone(),
+        message: diagnostic.message.clone(),
+        ..Default::default()
+    }
+}
+
+impl KEditor {
+    /// initialized server of the document
+    pub fn language_server(&self, doc: &Document) -> Option<Arc<Client>> {
+        self.language_servers
+            .get(doc.language()?)
+            .filter(|c| c.is_initialized())
+            .cloned()
+    }
+
+    /// tells the server of the document about it, starting the server if needed
+    pub fn launch_language_server(&mut self, id: DocumentId) {
+        let doc = &self.documents[&id];
+        let (Some(language), Some(url)) = (doc.language(), doc_url(doc)) else {
+            return;
+        };
+        if let Some(client) = self.language_servers.get(language) {
+            // still initializing, the document is opened once that is done
+            if client.is_initialized() {
+                if let Err(e) =
+                    client.did_open(url, doc.version(), doc.text(), language_id(language))
+                {
+                    error!("{}", e);
+                }
+            }
+            return;
+        }
+
+        let client = match self.language_servers.start(language) {
+            Some(Ok(client)) => client,
+            Some(Err(e)) => {
+                warn!("{:#}", e);
+                return;
+            }
+            None => return,
+        };
+        self.jobs.callback(async move {
+            client.initialize().await?;
+            info!("{} initialized", client.name());
+            let callback = move |editor: &mut KEditor| {
+                for doc in editor.documents.values() {
+                    let Some(url) = doc_url(doc) else { continue };
+                    if doc.language() == Some(language) {
+                        client.did_open(url, doc.version(), doc.text(), language_id(language))?;
+                    }
+                }
+                Ok(())
+            };
+            Ok(Box::new(callback) as Callback)
+        });
+    }
+
+    /// tells the server of the document it is closed
+    pub fn close_language_server_document(&self, id: DocumentId) {
+        let doc = &self.documents[&id];
+        let (Some(client), Some(url)) = (self.language_server(doc), doc_url(doc)) else {
+            return;
+        };
+        if let Err(e) = client.did_close(url) {
+            error!("{}", e);
+        }
+    }
+
+    /// before quitting
+    pub async fn shutdown_language_servers(&mut self) {
+        self.language_servers.shutdown().await;
+    }
+
+    /// `old_text` is the text of the document before the changes
+    pub fn notify_language_server(&self, id: DocumentId, old_text: &Rope, changes: &ChangeSet) {
+        let doc = &self.documents[&id];
+        let (Some(client), Some(url)) = (self.language_server(doc), doc_url(doc)) else {
+            return;
+        };
+        if let Err(e) = client.did_change(url, doc.version(), old_text, doc.text(), changes) {
+            error!("{}", e);
+        }
+    }
+
+    pub fn document_by_url(&self, url: &Url) -> Option<DocumentId> {
+        self.documents
+            .iter()
+            .find(|(_, doc)| doc_url(doc).as_ref() == Some(url))
+            .map(|(id, _)| *id)
+    }
+
+    /// applies the edits to the documents, opening the ones that are not open yet
+    pub fn apply_workspace_edit(
+        &mut self,
+        edit: lsp::WorkspaceEdit,
+        encoding: OffsetEncoding,
+    ) -> anyhow::Result<()> {
+        let mut edits: Vec<(Url, Vec<lsp::TextEdit>)> = Vec::new();
+        if let Some(changes) = edit.document_changes {
+            let documents = match changes {
+                lsp::DocumentChanges::Edits(edits) => edits,
+                lsp::DocumentChanges::Operations(operations) => operations
+                    .into_iter()
+                    .map(|op| match op {
+                        lsp::DocumentChangeOperation::Edit(edit) => Ok(edit),
+                        lsp::DocumentChangeOperation::Op(_) => {
+                            bail!("file operations are not supported")
+                        }
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            };
+            for document in documents {
+                let text_edits = document
+                    .edits
+                    .into_iter()
+                    .map(|edit| match edit {
+                        lsp::OneOf::Left(edit) => edit,
+                        lsp::OneOf::Right(annotated) => annotated.text_edit,
+                    })
+                    .collect();
+                edits.push((document.text_document.uri, text_edits));
+            }
+        } else if let Some(changes) = edit.changes {
+            edits.extend(changes);
+        }
+
+        for (url, text_edits) in edits {
+            let id = match self.document_by_url(&url) {
+                Some(id) => id,
+                None => {
+                    let path = url
+                        .to_file_path()
+                        .map_err(|()| anyhow!("not a file: {}", url))?;
+                    self.open_document(&path)?
+                }
+            };
+            let changes = util::edits_to_changes(self.documents[&id].text(), text_edits, encoding);
+            self.apply_to(id, &changes);
+        }
+        Ok(())
+    }
+
+    pub fn handle_language_server_message(&mut self, id: usize, message: ServerMessage) {
+        let Some(client) = self.language_servers.by_id(id).cloned() else {
+            return;
+        };
+        match message {
+            ServerMessage::Notification(notification) => {
+                if let Err(e) = self.handle_notification(&client, notification) {
+                    error!("{}: {:#}", client.name(), e);
+                }
+            }
+            ServerMessage::Request(request) => {
+                let result = self.handle_request(&client, &request.method, request.params);
+                if let Err(e) = client.reply(request.id, result) {
+                    error!("{}: failed to reply: {}", client.name(), e);
+                }
+            }
+            ServerMessage::Exited => {
+                self.language_servers.remove(id);
+                for doc in self.documents.values_mut() {
+                    doc.diagnostics_mut().clear(client.name());
+                }
+                for diagnostics in self.diagnostics.values_mut() {
+                    diagnostics.clear(client.name());
+                }
+                self.diagnostics.retain(|_, diagnostics| !diagnostics.is_empty());
+                self.set_error(format!("{} exited", client.name()));
+            }
+        }
+    }
+
+    fn handle_notification(
+        &mut self,
+        client: &Client,
+        notification: jsonrpc::Notification,
+    ) -> anyhow::Result<()> {
+        use lsp::notification::{LogMessage, Notification, PublishDiagnostics, ShowMessage};
+
+        match notification.method.as_str() {
+            PublishDiagnostics::METHOD => {
+                let params: lsp::PublishDiagnosticsParams =
+                    serde_json::from_value(notification.params)?;
+                let path = params
+                    .uri
+                    .to_file_path()
+                    .map_err(|()| anyhow!("not a file: {}", params.uri))?;
+                // positions of files that are not open refer to the file on disk
+                let text = match self.document_by_path(&path) {
+                    Some(id) => self.documents[&id].text().clone(),
+                    None => match std::fs::File::open(&path) {
+                        Ok(file) => Rope::from_reader(std::io::BufReader::new(file))?,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                        Err(e) => return Err(e.into()),
+                    },
+                };
+                let encoding = client.offset_encoding();
+                let diagnostics = params
+                    .diagnostics
+                    .into_iter()
+                    .filter_map(|d| diagnostic_from_lsp(&text, d, encoding))
+                    .collect();
+                self.set_diagnostics(&path, client.name(), diagnostics);
+            }
+            ShowMessage::METHOD => {
+                let params: lsp::ShowMessageParams = serde_json::from_value(notification.params)?;
+                match params.typ {
+                    lsp::MessageType::ERROR => self.set_error(params.message),
+                    _ => self.set_status(params.message),
+                }
+            }
+            LogMessage::METHOD => {
+                let params: lsp::LogMessageParams = serde_json::from_value(notification.params)?;
+                info!("{}", params.message);
+            }
+            method => info!("unhandled notification {}", method),
+        }
+        Ok(())
+    }
+
+    fn handle_request(
+        &mut self,
+        client: &Client,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, jsonrpc::Error> {
+        use lsp::request::{
+            ApplyWorkspaceEdit, RegisterCapability, Request, WorkDoneProgressCreate,
+            WorkspaceConfiguration,
+        };
+
+        let invalid = |e: serde_json::Error| jsonrpc::Error {
+            code: jsonrpc::Error::INTERNAL_ERROR,
+            message: e.to_string(),
+            data: None,
+        };
+        match method {
+            ApplyWorkspaceEdit::METHOD => {
+                let params: lsp::ApplyWorkspaceEditParams =
+                    serde_json::from_value(params).map_err(invalid)?;
+                let result = self.apply_workspace_edit(params.edit, client.offset_encoding());
+                let response = lsp::ApplyWorkspaceEditResponse {
+                    applied: result.is_ok(),
+                    failure_reason: result.err().map(|e| format!("{:#}", e)),
+                    failed_change: None,
+                };
+                Ok(serde_json::to_value(response).expect("response serializes"))
+            }
+            WorkspaceConfiguration::METHOD => {
+                let params: lsp::ConfigurationParams =
+                    serde_json::from_value(params).map_err(invalid)?;
+                Ok(Value::Array(vec![Value::Null; params.items.len()]))
+            }
+            WorkDoneProgressCreate::METHOD | RegisterCapability::METHOD => Ok(Value::Null),
+            method => Err(jsonrpc::Error::method_not_found(method)),
+        }
+    }
+}
@@ -1,14 +1,192 @@
+mod args;
+mod clock;
 mod config;
 mod ui;
 mod editor;
+#[cfg(test)]
+mod harness;
+mod headless;
+mod history;
 mod keymap;
 mod commands;
+mod completion;
+mod theme;
+mod view;
+mod job;
+mod lsp;
+mod session;
+mod swap;
+
+use std::io::Read;
+
+use anyhow::Context as _;
+use kk_core::selection::Selection;
+use ropey::Rope;
+
+use crate::{
+    args::{Args, Input},
+    commands::{typed, Context},
+    editor::KEditor,
+    session::Session,
+    ui::prompt::PromptEvent,
+};
+
+/// exit code for arguments and scripts that can not be parsed
+const USAGE_ERROR: i32 = 2;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    let args = match Args::parse(std::env::args_os().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("kk: {:#}\ntry `kk --help`", e);
+            std::process::exit(USAGE_ERROR);
+        }
+    };
+    if args.help {
+        print!("{}", args::help());
+        return Ok(());
+    }
+    if args.version {
+        println!("{}", args::version());
+        return Ok(());
+    }
+    init_log(&args)?;
+    if let Some(path) = &args.config {
+        config::set_config_file(std::path::absolute(path)?);
+    }
+    if args.check_config {
+        std::process::exit(config::check_config()?);
+    }
+    if args.dump_keymap {
+        let config = config::Config::load_user()?;
+        print!("{}", config::dump_keys(&config.keys));
+        return Ok(());
+    }
 
-    let mut editor = editor::KEditor::new();
+    let script: anyhow::Result<Vec<headless::Step>> =
+        args.script.iter().map(|s| s.parse()).collect();
+    let script = match script {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("kk: {:#}", e);
+            std::process::exit(USAGE_ERROR);
+        }
+    };
+
+    let mut editor = KEditor::new();
+    match args.headless {
+        true => editor.language_servers.disable(),
+        false => editor.enable_swap(swap::swap_dir()),
+    }
+    let project = std::env::current_dir()?;
+    restore_session(&mut editor, &args, &project);
+    open_inputs(&mut editor, &args);
+    for command in &args.commands {
+        let mut cx = Context {
+            editor: &mut editor,
+            key: None,
+            count: None,
+        };
+        if let Err(e) = typed::execute(&mut cx, command, PromptEvent::Validate) {
+            editor.set_error(format!("+{}: {:#}", command, e));
+        }
+    }
+    if args.headless {
+        let code = headless::run(&mut editor, &script);
+        editor.shutdown_language_servers().await;
+        std::process::exit(code);
+    }
     let return_code = editor.run(&mut crossterm::event::EventStream::new()).await?;
+    // `exit` skips the destructors that would kill the servers
+    editor.shutdown_language_servers().await;
+    if editor.auto_session() {
+        let path = session::project_path(&project);
+        let saved = Session::capture(&editor).and_then(|s| s.save(&path));
+        if let Err(e) = saved {
+            eprintln!("kk: failed to save the session: {:#}", e);
+        }
+    }
     std::process::exit(return_code)
 }
+
+/// `--session`, or with `auto-session` the one of the working directory when no files are given
+fn restore_session(editor: &mut KEditor, args: &Args, project: &std::path::Path) {
+    let path = match &args.session {
+        Some(name) => session::named_path(name),
+        None if editor.auto_session() && !args.headless && args.inputs.is_empty() => {
+            let path = session::project_path(project);
+            if !path.exists() {
+                return;
+            }
+            Ok(path)
+        }
+        None => return,
+    };
+    let restored = path.and_then(|path| Session::load(&path)?.restore(editor));
+    // like the files given on the command line
+    if args.readonly {
+        let files = editor.documents.values_mut().filter(|doc| doc.path().is_some());
+        files.for_each(|doc| doc.set_readonly(true));
+    }
+    if let Err(e) = restored {
+        editor.set_error(format!("{:#}", e));
+    }
+}
+
+/// the log goes to stderr unless `--log` is given, which the editor draws over
+fn init_log(args: &Args) -> anyhow::Result<()> {
+    let Some(path) = &args.log else {
+        env_logger::init();
+        return Ok(());
+    };
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .init();
+    Ok(())
+}
+
+/// Opens the inputs last to first, the first one ends up shown. Only its position is used,
+/// there is one view.
+fn open_inputs(editor: &mut KEditor, args: &Args) {
+    for input in args.inputs.iter().rev() {
+        let opened = match input {
+            Input::File { path, position } => editor.open(path).map(|_| {
+                if let Some((line, col)) = position {
+                    goto(editor, *line, *col);
+                }
+            }),
+            Input::Stdin => read_stdin().map(|text| {
+                editor.open_scratch(text, None);
+            }),
+        };
+        match opened {
+            Ok(()) if args.readonly => editor.current().1.set_readonly(true),
+            Ok(()) => {}
+            Err(e) => editor.set_error(format!("{:#}", e)),
+        }
+    }
+}
+
+fn read_stdin() -> anyhow::Result<Rope> {
+    let mut text = String::new();
+    std::io::stdin()
+        .read_to_string(&mut text)
+        .context("failed to read stdin")?;
+    Ok(Rope::from(text))
+}
+
+/// 1-based, clamped to the document
+fn goto(editor: &mut KEditor, line: usize, col: usize) {
+    let (view, doc) = editor.current();
+    let text = doc.text();
+    let line = (line - 1).min(text.len_lines() - 1);
+    let start = text.line_to_char(line);
+    let end = match line + 1 < text.len_lines() {
+        true => text.line_to_char(line + 1) - 1,
+        false => text.len_chars(),
+    };
+    view.selection = Selection::point((start + col - 1).min(end));
+}
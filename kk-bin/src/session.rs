@@ -0,0 +1,330 @@
+//! Sessions keep the working directory, the open files with their selections, the views on
+//! screen and the jumplist in `$XDG_STATE_HOME/kk/sessions/`, by name or by project directory.
+//! kk has no registers, there are none to keep.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _};
+use kk_core::selection::{Range, Selection};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{escape_path, state_dir},
+    editor::KEditor,
+    history::{Jump, JumpList},
+    view::View,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub cwd: PathBuf,
+    pub buffers: Vec<Buffer>,
+    /// index of the shown buffer
+    pub current: Option<usize>,
+    /// the other views on screen left to right, the shown buffer is drawn before
+    /// `splits[focus]`
+    #[serde(default)]
+    pub splits: Vec<Buffer>,
+    #[serde(default)]
+    pub focus: usize,
+    /// oldest first, jumps into documents without a file are left out
+    #[serde(default)]
+    pub jumps: Vec<SavedJump>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Buffer {
+    pub path: PathBuf,
+    /// `(anchor, head)` of each range
+    pub ranges: Vec<(usize, usize)>,
+    pub primary: usize,
+    /// first visible line
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedJump {
+    pub path: PathBuf,
+    pub ranges: Vec<(usize, usize)>,
+    pub primary: usize,
+}
+
+fn ranges(selection: &Selection) -> Vec<(usize, usize)> {
+    selection.iter().map(|r| (r.anchor, r.head)).collect()
+}
+
+fn buffer(path: &Path, selection: &Selection, offset: usize) -> anyhow::Result<Buffer> {
+    Ok(Buffer {
+        path: std::path::absolute(path)?,
+        ranges: ranges(selection),
+        primary: selection.primary_index(),
+        offset,
+    })
+}
+
+/// `None` without ranges
+fn selection(ranges: &[(usize, usize)], primary: usize, text: &Rope) -> Option<Selection> {
+    let ranges: Vec<_> = ranges
+        .iter()
+        .map(|(anchor, head)| Range::new(*anchor, *head))
+        .collect();
+    let primary = primary.min(ranges.len().checked_sub(1)?);
+    Some(Selection::new(ranges, primary).clamp(text))
+}
+
+pub fn sessions_dir() -> PathBuf {
+    state_dir().join("sessions")
+}
+
+/// `<name>.json`, the name can not leave the directory
+pub fn named_path(name: &str) -> anyhow::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        bail!("invalid session name '{}'", name);
+    }
+    Ok(sessions_dir().join(format!("{}.json", name)))
+}
+
+/// the session of a project directory, saved on quit with `auto-session`
+pub fn project_path(dir: &Path) -> PathBuf {
+    sessions_dir()
+        .join("projects")
+        .join(format!("{}.json", escape_path(dir)))
+}
+
+impl Session {
+    /// the files of the editor, documents without one are left out
+    pub fn capture(editor: &KEditor) -> anyhow::Result<Self> {
+        let mut buffers = Vec::new();
+        let mut current = None;
+        for (id, doc) in &editor.documents {
+            let Some(path) = doc.path() else {
+                continue;
+            };
+            if *id == editor.view.doc {
+                current = Some(buffers.len());
+            }
+            let (selection, offset) = match editor.view_of(*id) {
+                Some(view) => (view.selection.clone(), view.offset),
+                None => (Selection::point(0), 0),
+            };
+            buffers.push(buffer(path, &selection, offset)?);
+        }
+        let with_file = |view: &&View| editor.documents[&view.doc].path().is_some();
+        let focus = editor.splits[..editor.focus()].iter().filter(with_file).count();
+        let mut splits = Vec::new();
+        for view in editor.splits.iter().filter(with_file) {
+            let path = editor.documents[&view.doc].path().expect("filtered");
+            splits.push(buffer(path, &view.selection, view.offset)?);
+        }
+        let mut jumps = Vec::new();
+        for jump in editor.jumps.iter() {
+            if let Some(path) = editor.documents[&jump.doc].path() {
+                jumps.push(SavedJump {
+                    path: std::path::absolute(path)?,
+                    ranges: ranges(&jump.selection),
+                    primary: jump.selection.primary_index(),
+                });
+            }
+        }
+        Ok(Self {
+            cwd: std::env::current_dir()?,
+            buffers,
+            current,
+            splits,
+            focus,
+            jumps,
+        })
+    }
+
+    /// Changes to the working directory and opens the files with their selections, then
+    /// replaces the jumplist. Files that fail to open are skipped, the error of the last one is
+    /// returned.
+    pub fn restore(&self, editor: &mut KEditor) -> anyhow::Result<()> {
+        std::env::set_current_dir(&self.cwd)
+            .with_context(|| format!("failed to change to {}", self.cwd.display()))?;
+        let mut result = Ok(());
+        let mut shown = None;
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            let id = match editor.open(&buffer.path) {
+                Ok(id) => id,
+                Err(e) => {
+                    result = Err(e);
+                    continue;
+                }
+            };
+            let text = editor.documents[&id].text();
+            if let Some(selection) = selection(&buffer.ranges, buffer.primary, text) {
+                editor.view.selection = selection;
+            }
+            editor.view.offset = buffer.offset;
+            if self.current == Some(i) {
+                shown = Some(buffer.path.clone());
+            }
+        }
+        if let Some(path) = shown {
+            editor.open(&path)?;
+        }
+        let mut splits = Vec::new();
+        for buffer in &self.splits {
+            let id = match editor.open_document(&buffer.path) {
+                Ok(id) => id,
+                Err(e) => {
+                    result = Err(e);
+                    continue;
+                }
+            };
+            let mut view = View::new(id);
+            let text = editor.documents[&id].text();
+            if let Some(selection) = selection(&buffer.ranges, buffer.primary, text) {
+                view.selection = selection;
+            }
+            view.offset = buffer.offset;
+            splits.push(view);
+        }
+        editor.set_splits(splits, self.focus);
+        editor.jumps = JumpList::default();
+        for jump in &self.jumps {
+            let Some(doc) = editor.document_by_path(&jump.path) else {
+                continue;
+            };
+            let text = editor.documents[&doc].text();
+            if let Some(selection) = selection(&jump.ranges, jump.primary, text) {
+                editor.jumps.push(Jump { doc, selection });
+            }
+        }
+        result
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("invalid session {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use kk_core::selection::{Range, Selection};
+
+    use crate::harness::Harness;
+
+    use super::{named_path, Buffer, SavedJump, Session};
+
+    #[test]
+    fn names() {
+        assert!(named_path("work").unwrap().ends_with("sessions/work.json"));
+        assert!(named_path("../x").is_err());
+        assert!(named_path(".hidden").is_err());
+        assert!(named_path("").is_err());
+    }
+
+    #[tokio::test]
+    async fn capture_and_restore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "one\ntwo\n").unwrap();
+        std::fs::write(&b, "three\n").unwrap();
+        let cwd = std::env::current_dir().unwrap();
+
+        let mut h = Harness::new("");
+        h.editor.open(&a).unwrap();
+        h.keys("j").await;
+        h.editor.open(&b).unwrap();
+        h.keys("l l").await;
+        let session = Session::capture(&h.editor).unwrap();
+        assert_eq!(
+            session.buffers,
+            vec![
+                Buffer {
+                    path: a.clone(),
+                    ranges: vec![(4, 4)],
+                    primary: 0,
+                    offset: 0,
+                },
+                Buffer {
+                    path: b.clone(),
+                    ranges: vec![(2, 2)],
+                    primary: 0,
+                    offset: 0,
+                },
+            ]
+        );
+        assert_eq!(session.current, Some(1));
+        assert_eq!(
+            session.jumps,
+            vec![SavedJump {
+                path: a.clone(),
+                ranges: vec![(4, 4)],
+                primary: 0,
+            }]
+        );
+
+        let path = dir.join("s.json");
+        session.save(&path).unwrap();
+        let session = Session::load(&path).unwrap();
+        let mut h = Harness::new("");
+        session.restore(&mut h.editor).unwrap();
+        assert_eq!(h.text(), "three\n");
+        assert_eq!(h.selection(), &Selection::point(2));
+        h.keys("C-o").await;
+        assert_eq!(
+            h.editor.documents[&h.editor.view.doc].path(),
+            Some(a.as_path())
+        );
+        assert_eq!(h.selection(), &Selection::new(vec![Range::point(4)], 0));
+
+        let missing = Session {
+            cwd: PathBuf::from("/nonexistent/dir"),
+            buffers: vec![],
+            current: None,
+            splits: vec![],
+            focus: 0,
+            jumps: vec![],
+        };
+        assert!(missing.restore(&mut h.editor).is_err());
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+    }
+
+    #[tokio::test]
+    async fn splits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "one\ntwo\n").unwrap();
+        std::fs::write(&b, "three\n").unwrap();
+
+        let mut h = Harness::new("");
+        h.editor.open(&a).unwrap();
+        h.keys("j C-w v").await;
+        h.editor.open(&b).unwrap();
+        h.keys("C-w w").await;
+        let session = Session::capture(&h.editor).unwrap();
+        assert_eq!(session.current, Some(0));
+        assert_eq!(session.focus, 0);
+        assert_eq!(session.splits.len(), 1);
+        assert_eq!(session.splits[0].path, b);
+
+        let mut h = Harness::new("");
+        session.restore(&mut h.editor).unwrap();
+        assert_eq!(h.editor.focus(), 0);
+        let views: Vec<_> = h.editor.views().map(|v| v.doc).collect();
+        let paths: Vec<_> = views.iter().map(|id| h.editor.documents[id].path()).collect();
+        assert_eq!(paths, [Some(a.as_path()), Some(b.as_path())]);
+        assert_eq!(h.selection(), &Selection::point(4));
+    }
+}
@@ -0,0 +1,579 @@
+//! Swap files keep the text of modified documents in `$XDG_STATE_HOME/kk/swap/` until the
+//! documents are written, so a crash or a dropped session does not lose the changes. The whole
+//! text is written once, the changes after it are appended.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use kk_core::{
+    diff::{diff_lines, DiffLine},
+    transaction::{Change, ChangeSet},
+};
+use log::error;
+use ropey::Rope;
+
+use crate::{
+    config::{escape_path, state_dir},
+    editor::{DocumentId, KEditor},
+    ui::picker::Picker,
+};
+
+/// First line of a swap file, then the pid of the writer, the path of the file and the text.
+/// Each change set after them is its number of changes and for each change `from to` and the
+/// inserted text. Paths and texts are their length in bytes on a line of their own, then the
+/// bytes.
+const HEADER: &str = "kk swap 2";
+
+/// change sets appended before the swap file is written anew, replaying them stays quick
+const COMPACT_AFTER: usize = 1000;
+
+/// `swap` in the state directory
+pub fn swap_dir() -> PathBuf {
+    state_dir().join("swap")
+}
+
+/// `/home/a/b.rs` is `%2Fhome%2Fa%2Fb.rs.swp`
+fn swap_name(file: &Path) -> String {
+    format!("{}.swp", escape_path(file))
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(std::ffi::OsStr::from_bytes(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    Some(std::str::from_utf8(bytes).ok()?.into())
+}
+
+fn push_field(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(format!("{}\n", bytes.len()).as_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn push_changes(out: &mut Vec<u8>, changes: &ChangeSet) {
+    out.extend_from_slice(format!("{}\n", changes.changes().len()).as_bytes());
+    for change in changes.changes() {
+        out.extend_from_slice(format!("{} {}\n", change.from, change.to).as_bytes());
+        push_field(out, change.text.as_deref().unwrap_or_default().as_bytes());
+    }
+}
+
+/// reads a swap file front to back, `None` once it ends or does not make sense
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> Option<&'a [u8]> {
+        let end = self.bytes.iter().position(|b| *b == b'\n')?;
+        let line = &self.bytes[..end];
+        self.bytes = &self.bytes[end + 1..];
+        Some(line)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Option<T> {
+        std::str::from_utf8(self.line()?).ok()?.parse().ok()
+    }
+
+    fn field(&mut self) -> Option<&'a [u8]> {
+        let len = self.number()?;
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(field)
+    }
+
+    /// a change set that applies to `text`, a torn or broken one is `None`
+    fn changes(&mut self, text: &Rope) -> Option<ChangeSet> {
+        let count: usize = self.number()?;
+        let mut changes = Vec::new();
+        let mut end = 0;
+        for _ in 0..count {
+            let line = std::str::from_utf8(self.line()?).ok()?;
+            let (from, to) = line.split_once(' ')?;
+            let (from, to): (usize, usize) = (from.parse().ok()?, to.parse().ok()?);
+            let insert = std::str::from_utf8(self.field()?).ok()?;
+            // sorted and within the text, like `ChangeSet::new` and `apply` need them
+            if from < end || to < from || to > text.len_chars() {
+                return None;
+            }
+            end = to;
+            changes.push(Change::replace(from, to, insert));
+        }
+        Some(ChangeSet::new(changes))
+    }
+}
+
+/// a swap file read back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapFile {
+    pub pid: u32,
+    pub file: PathBuf,
+    pub text: String,
+}
+
+impl SwapFile {
+    /// `None` if there is none or it is not a swap file of kk
+    pub fn read(path: &Path) -> std::io::Result<Option<Self>> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Self::decode(&content))
+    }
+
+    /// the text with the changes replayed, up to one that was not written completely
+    fn decode(content: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes: content };
+        if reader.line()? != HEADER.as_bytes() {
+            return None;
+        }
+        let pid = reader.number()?;
+        let file = path_from_bytes(reader.field()?)?;
+        let mut text = Rope::from(std::str::from_utf8(reader.field()?).ok()?);
+        while let Some(changes) = reader.changes(&text) {
+            changes.apply(&mut text);
+        }
+        let text = text.to_string();
+        Some(Self { pid, file, text })
+    }
+
+    /// the start of a swap file, changes are appended to it
+    fn encode(pid: u32, file: &Path, text: &Rope) -> Vec<u8> {
+        let mut out = format!("{}\n{}\n", HEADER, pid).into_bytes();
+        push_field(&mut out, &path_to_bytes(file));
+        push_field(&mut out, text.to_string().as_bytes());
+        out
+    }
+
+    /// written for `file`, not for another file that got the same name
+    pub fn is_for(&self, file: &Path) -> bool {
+        std::path::absolute(file).is_ok_and(|file| file == self.file)
+    }
+
+    /// another kk that is still running writes it
+    pub fn in_use(&self) -> bool {
+        self.pid != std::process::id() && process_alive(self.pid)
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    let sent = unsafe { libc::kill(pid, 0) } == 0;
+    sent || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+/// what the next flush writes to a swap file
+#[derive(Debug)]
+struct Pending {
+    file: PathBuf,
+    /// the swap file is written anew with the whole text
+    snapshot: Option<Rope>,
+    /// appended to the snapshot, or to what the swap file has
+    changes: Vec<ChangeSet>,
+}
+
+/// by swap file
+type PendingWrites = BTreeMap<PathBuf, Pending>;
+
+/// A snapshot goes to a temporary file first, a crash while writing must not lose the last
+/// swap file. Changes are appended, a torn one is skipped when reading.
+fn write_pending(swap: &Path, pending: &Pending) -> std::io::Result<()> {
+    let mut bytes = match &pending.snapshot {
+        Some(text) => SwapFile::encode(std::process::id(), &pending.file, text),
+        None => Vec::new(),
+    };
+    for changes in &pending.changes {
+        push_changes(&mut bytes, changes);
+    }
+    if pending.snapshot.is_none() {
+        let mut out = std::fs::OpenOptions::new().append(true).open(swap)?;
+        out.write_all(&bytes)?;
+        return out.sync_data();
+    }
+    if let Some(dir) = swap.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = swap.with_extension("swp.tmp");
+    let mut out = std::fs::File::create(&tmp)?;
+    out.write_all(&bytes)?;
+    out.sync_all()?;
+    std::fs::rename(tmp, swap)
+}
+
+/// the swap files that failed to be written
+fn write_all_pending(pending: &mut PendingWrites) -> Vec<PathBuf> {
+    let mut failed = Vec::new();
+    for (swap, pending) in std::mem::take(pending) {
+        if let Err(e) = write_pending(&swap, &pending) {
+            error!("Failed to write {}: {}", swap.display(), e);
+            failed.push(pending.file);
+        }
+    }
+    failed
+}
+
+/// where the journal of a file is at
+#[derive(Debug)]
+struct Journal {
+    /// of the document, the journal ends with the change to it
+    version: i32,
+    /// change sets since the snapshot
+    entries: usize,
+}
+
+/// the swap files of the documents of one editor
+#[derive(Debug, Default)]
+pub struct Swaps {
+    /// off until enabled, the headless mode and the tests write none
+    dir: Option<PathBuf>,
+    /// by file
+    journals: HashMap<PathBuf, Journal>,
+    /// files whose swap file belongs to another kk, it is neither written nor removed
+    foreign: HashSet<PathBuf>,
+    /// shared with the panic hook, which can not reach the editor
+    pending: Arc<Mutex<PendingWrites>>,
+}
+
+impl Swaps {
+    pub fn enable(&mut self, dir: PathBuf) {
+        self.dir = Some(dir);
+    }
+
+    pub fn swap_path(&self, file: &Path) -> Option<PathBuf> {
+        let file = std::path::absolute(file).ok()?;
+        Some(self.dir.as_ref()?.join(swap_name(&file)))
+    }
+
+    /// the swap file of `file` is left to the kk that writes it, for as long as this one runs
+    pub fn leave_alone(&mut self, file: &Path) {
+        if let Ok(file) = std::path::absolute(file) {
+            self.journals.remove(&file);
+            self.foreign.insert(file);
+        }
+    }
+
+    /// Journals `changes` for the next `flush`, they took the document of `file` to `version`.
+    /// The first change since the swap file was written, and every `COMPACT_AFTER`th, writes
+    /// all of `text` instead.
+    pub fn record(&mut self, file: &Path, changes: &ChangeSet, version: i32, text: &Rope) {
+        let Some(swap) = self.swap_path(file) else {
+            return;
+        };
+        let Ok(file) = std::path::absolute(file) else {
+            return;
+        };
+        if self.foreign.contains(&file) {
+            return;
+        }
+        let mut pending = self.pending.lock().expect("not poisoned");
+        match self.journals.get_mut(&file) {
+            Some(journal) if journal.version + 1 == version && journal.entries < COMPACT_AFTER => {
+                journal.version = version;
+                journal.entries += 1;
+                let pending = pending.entry(swap).or_insert_with(|| Pending {
+                    file,
+                    snapshot: None,
+                    changes: Vec::new(),
+                });
+                pending.changes.push(changes.clone());
+            }
+            _ => {
+                let journal = Journal {
+                    version,
+                    entries: 0,
+                };
+                self.journals.insert(file.clone(), journal);
+                let snapshot = Some(text.clone());
+                let changes = Vec::new();
+                pending.insert(swap, Pending { file, snapshot, changes });
+            }
+        }
+    }
+
+    /// the next change of a file that failed to be written writes all of its text
+    pub fn flush(&mut self) {
+        let failed = write_all_pending(&mut self.pending.lock().expect("not poisoned"));
+        for file in failed {
+            self.journals.remove(&file);
+        }
+    }
+
+    /// writes what is left when the editor panics, skipped if it happened while writing
+    pub fn panic_flush(&self) -> impl Fn() + Send + Sync + 'static {
+        let pending = self.pending.clone();
+        move || {
+            if let Ok(mut pending) = pending.try_lock() {
+                write_all_pending(&mut pending);
+            }
+        }
+    }
+
+    /// the document was written, its swap file is not needed anymore
+    pub fn remove(&mut self, file: &Path) {
+        let Some(swap) = self.swap_path(file) else {
+            return;
+        };
+        if let Ok(file) = std::path::absolute(file) {
+            if self.foreign.contains(&file) {
+                return;
+            }
+            self.journals.remove(&file);
+        }
+        self.pending.lock().expect("not poisoned").remove(&swap);
+        if let Err(e) = std::fs::remove_file(&swap) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("Failed to remove {}: {}", swap.display(), e);
+            }
+        }
+    }
+
+    /// the editor quits, the swap files of the `modified` files stay for the next start
+    pub fn remove_all_but(&mut self, modified: &[PathBuf]) {
+        let modified: Vec<_> = modified
+            .iter()
+            .filter_map(|file| std::path::absolute(file).ok())
+            .collect();
+        let files: Vec<_> = self.journals.keys().cloned().collect();
+        for file in files.iter().filter(|file| !modified.contains(file)) {
+            self.remove(file);
+        }
+        self.keep_all();
+    }
+
+    /// the editor was killed, the swap files stay for the next start
+    pub fn keep_all(&mut self) {
+        self.flush();
+        self.journals.clear();
+    }
+}
+
+/// `--- file`, `+++ swap file` and the lines prefixed with ` `, `-` or `+`
+fn diff_text(file: &Path, old: &str, swap: &str) -> String {
+    let mut text = format!("--- {}\n+++ swap file\n", file.display());
+    for line in diff_lines(old, swap) {
+        let (prefix, line) = match line {
+            DiffLine::Same(line) => (' ', line),
+            DiffLine::Removed(line) => ('-', line),
+            DiffLine::Added(line) => ('+', line),
+        };
+        text.push(prefix);
+        text.push_str(line);
+        if !line.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// recover, diff or delete the swap file `swap_path` of document `id`
+pub fn recover_picker(id: DocumentId, swap_path: PathBuf, swap: SwapFile) -> Picker {
+    let title = format!("swap file of {}", swap.file.display());
+    let labels = vec![
+        "recover  replace the text with the one of the swap file".to_string(),
+        "diff     show how the swap file differs in a new buffer".to_string(),
+        "delete   delete the swap file".to_string(),
+        "keep     decide later with :recover".to_string(),
+    ];
+    Picker::new(&title, labels, move |editor: &mut KEditor, i| {
+        match i {
+            0 => {
+                let len = editor.documents[&id].text().len_chars();
+                editor.apply_to(id, &ChangeSet::new([Change::replace(0, len, &*swap.text)]));
+                editor.set_status(format!("recovered {}", swap.file.display()));
+            }
+            1 => {
+                let old = editor.documents[&id].text().to_string();
+                let diff = diff_text(&swap.file, &old, &swap.text);
+                editor.open_scratch(Rope::from(diff), None);
+            }
+            2 => std::fs::remove_file(&swap_path)?,
+            _ => {}
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use kk_core::transaction::{Change, ChangeSet};
+    use ropey::Rope;
+
+    use crate::harness::Harness;
+
+    use super::{diff_text, swap_name, SwapFile, Swaps};
+
+    #[test]
+    fn write_read_remove() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut swaps = Swaps::default();
+        let file = Path::new("/tmp/project/a.rs");
+        assert_eq!(swaps.swap_path(file), None);
+        swaps.enable(dir.to_path_buf());
+        let swap = swaps.swap_path(file).unwrap();
+        assert_eq!(swap, dir.join(swap_name(file)));
+        assert_eq!(swap_name(file), "%2Ftmp%2Fproject%2Fa.rs.swp");
+
+        let insert = |at, text: &str| ChangeSet::new([Change::insert(at, text)]);
+        swaps.record(file, &insert(0, "fn a() {}\n"), 1, &Rope::from("fn a() {}\n"));
+        assert!(!swap.exists());
+        swaps.flush();
+        let read = SwapFile::read(&swap).unwrap().unwrap();
+        assert_eq!(read.file, file);
+        assert_eq!(read.text, "fn a() {}\n");
+        assert_eq!(read.pid, std::process::id());
+        assert!(!read.in_use());
+
+        // appended to what is there
+        let before = std::fs::read(&swap).unwrap();
+        swaps.record(file, &insert(10, "b"), 2, &Rope::from("fn a() {}\nb"));
+        swaps.record(file, &insert(0, "// "), 3, &Rope::from("// fn a() {}\nb"));
+        swaps.flush();
+        let after = std::fs::read(&swap).unwrap();
+        assert!(after.starts_with(&before));
+        assert_eq!(SwapFile::read(&swap).unwrap().unwrap().text, "// fn a() {}\nb");
+        // a crash while appending
+        std::fs::write(&swap, [&after[..], b"1\n0 3\n5\nab"].concat()).unwrap();
+        assert_eq!(SwapFile::read(&swap).unwrap().unwrap().text, "// fn a() {}\nb");
+
+        swaps.keep_all();
+        swaps.remove_all_but(&[]);
+        assert!(swap.exists());
+        swaps.record(file, &insert(0, "x"), 4, &Rope::from("x"));
+        swaps.remove_all_but(&[]);
+        assert!(!swap.exists());
+        assert_eq!(SwapFile::read(&swap).unwrap(), None);
+
+        // still modified when quitting
+        let other = Path::new("/tmp/project/b.rs");
+        swaps.record(file, &insert(1, "a"), 5, &Rope::from("xa"));
+        swaps.record(other, &insert(0, "b"), 1, &Rope::from("b"));
+        swaps.remove_all_but(&[other.to_path_buf()]);
+        assert!(!swap.exists());
+        let read = SwapFile::read(&swaps.swap_path(other).unwrap()).unwrap().unwrap();
+        assert_eq!(read.text, "b");
+    }
+
+    #[test]
+    fn paths_are_kept_as_they_are() {
+        let text = Rope::from("a\n");
+        let file = Path::new("/tmp/new\nline");
+        let read = SwapFile::decode(&SwapFile::encode(1, file, &text)).unwrap();
+        assert_eq!((read.file.as_path(), read.text.as_str()), (file, "a\n"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let file = Path::new(std::ffi::OsStr::from_bytes(b"/tmp/\xff.rs"));
+            let read = SwapFile::decode(&SwapFile::encode(1, file, &text)).unwrap();
+            assert_eq!(read.file, file);
+        }
+    }
+
+    #[tokio::test]
+    async fn swap_of_another_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "old\n").unwrap();
+        let swap = dir.join(swap_name(&file));
+        let content = SwapFile::encode(u32::MAX, &dir.join("b.txt"), &Rope::from("b\n"));
+        std::fs::write(&swap, &content).unwrap();
+
+        let mut h = Harness::new("");
+        h.editor.enable_swap(dir.to_path_buf());
+        h.editor.open(&file).unwrap();
+        assert!(h.editor.picker.is_none());
+        assert!(h.editor.recover().is_err());
+        h.keys("i x esc : w ret").await;
+        h.editor.swaps.keep_all();
+        assert_eq!(std::fs::read(&swap).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn swap_of_running_kk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "old\n").unwrap();
+        let swap = dir.join(swap_name(&file));
+        // pid 1 is always running
+        let content = SwapFile::encode(1, &file, &Rope::from("theirs\n"));
+        std::fs::write(&swap, &content).unwrap();
+
+        let mut h = Harness::new("");
+        h.editor.enable_swap(dir.to_path_buf());
+        h.editor.open(&file).unwrap();
+        assert!(h.editor.picker.is_none());
+        h.keys("i x esc").await;
+        h.editor.swaps.keep_all();
+        assert_eq!(std::fs::read(&swap).unwrap(), content);
+        h.keys(": w ret").await;
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "xold\n");
+        h.editor.swaps.remove_all_but(&[]);
+        assert_eq!(std::fs::read(&swap).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn recover_orphan() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "old\n").unwrap();
+        let swap = dir.join(swap_name(&file));
+        // no process has that pid
+        let content = SwapFile::encode(u32::MAX, &file, &Rope::from("new\n"));
+        std::fs::write(&swap, content).unwrap();
+
+        let mut h = Harness::new("");
+        h.editor.enable_swap(dir.to_path_buf());
+        h.editor.open(&file).unwrap();
+        assert!(h.editor.picker.is_some());
+        h.keys("ret").await;
+        assert_eq!(h.text(), "new\n");
+        assert!(h.editor.current_ref().1.is_modified());
+        h.keys(": w ret").await;
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new\n");
+        assert!(!swap.exists());
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(
+            diff_text(Path::new("a.rs"), "a\nb\n", "a\nc"),
+            "--- a.rs\n+++ swap file\n a\n-b\n+c\n"
+        );
+    }
+}
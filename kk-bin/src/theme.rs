@@ -0,0 +1,521 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use kk_core::syntax::Highlight;
+use toml::Value;
+use tui::style::{Color, Modifier, Style};
+
+pub const DEFAULT_THEME: &str = "default";
+
+/// themes shipped with the binary, a file with the same name in the themes dir overrides them
+const BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", include_str!("../../.config/themes/default.toml")),
+    (
+        "default_light",
+        include_str!("../../.config/themes/default_light.toml"),
+    ),
+];
+
+/// how many colors the terminal can display
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// guesses from `COLORTERM` and `TERM`, the same way most terminal apps do
+    pub fn detect() -> Self {
+        Self::from_env(
+            std::env::var("COLORTERM").ok().as_deref(),
+            std::env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    fn from_env(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor") | Some("24bit")) {
+            return ColorDepth::TrueColor;
+        }
+        match term {
+            Some(term) if term.contains("256") => ColorDepth::Ansi256,
+            Some(term) if term.contains("truecolor") || term.contains("direct") => {
+                ColorDepth::TrueColor
+            }
+            _ => ColorDepth::Ansi16,
+        }
+    }
+
+    /// converts colors the terminal cannot display to the closest one it can
+    pub fn convert(&self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, color) => color,
+            (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(rgb_to_ansi256(r, g, b)),
+            (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => rgb_to_ansi16(r, g, b),
+            (ColorDepth::Ansi16, Color::Indexed(i)) => {
+                let (r, g, b) = ansi256_to_rgb(i);
+                rgb_to_ansi16(r, g, b)
+            }
+            (_, color) => color,
+        }
+    }
+}
+
+/// Styles for ui elements (`ui.*`) and syntax highlighting scopes
+#[derive(Debug, Clone)]
+pub struct Theme {
+    name: String,
+    styles: HashMap<String, Style>,
+    /// all scopes in a fixed order, the highlighter refers to them by index
+    scopes: Vec<String>,
+    highlights: Vec<Style>,
+}
+
+impl Theme {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Style of the scope, falls back to its parent scopes, `ui.cursor.insert` to `ui.cursor`
+    /// and then `ui`.
+    pub fn get(&self, scope: &str) -> Style {
+        let mut scope = scope;
+        loop {
+            if let Some(style) = self.styles.get(scope) {
+                return *style;
+            }
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => return Style::default(),
+            }
+        }
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn highlight(&self, highlight: Highlight) -> Style {
+        self.highlights
+            .get(highlight.0)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn from_values(name: &str, values: toml::Table, depth: ColorDepth) -> anyhow::Result<Self> {
+        let mut values = values;
+        let palette = match values.remove("palette") {
+            Some(Value::Table(palette)) => palette
+                .into_iter()
+                .map(|(name, color)| {
+                    let color = color
+                        .as_str()
+                        .ok_or_else(|| anyhow!("palette color '{}' is not a string", name))?;
+                    Ok((name, parse_color(color, &HashMap::new())?))
+                })
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+            Some(_) => bail!("'palette' has to be a table"),
+            None => HashMap::new(),
+        };
+        values.remove("inherits");
+
+        let mut styles = HashMap::new();
+        for (scope, value) in values {
+            let style = parse_style(&value, &palette, depth)
+                .with_context(|| format!("invalid style for '{}' in theme '{}'", scope, name))?;
+            styles.insert(scope, style);
+        }
+
+        let mut scopes: Vec<_> = styles.keys().cloned().collect();
+        scopes.sort();
+        let highlights = scopes.iter().map(|s| styles[s]).collect();
+        Ok(Self {
+            name: name.to_string(),
+            styles,
+            scopes,
+            highlights,
+        })
+    }
+}
+
+/// Finds themes in the themes dir next to the config, or builtin
+#[derive(Debug, Clone)]
+pub struct ThemeLoader {
+    theme_dir: Option<PathBuf>,
+    depth: ColorDepth,
+}
+
+impl ThemeLoader {
+    pub fn new(theme_dir: PathBuf, depth: ColorDepth) -> Self {
+        Self {
+            theme_dir: Some(theme_dir),
+            depth,
+        }
+    }
+
+    /// only the builtin themes, the ones of the user stay out of tests
+    #[cfg(test)]
+    pub fn builtin(depth: ColorDepth) -> Self {
+        Self {
+            theme_dir: None,
+            depth,
+        }
+    }
+
+    pub fn load(&self, name: &str) -> anyhow::Result<Theme> {
+        let values = self.load_merged(name, &mut Vec::new())?;
+        Theme::from_values(name, values, self.depth)
+    }
+
+    /// the builtin default if the one in the themes dir fails to load, with the error
+    pub fn default_theme(&self) -> (Theme, Option<anyhow::Error>) {
+        match self.load(DEFAULT_THEME) {
+            Ok(theme) => (theme, None),
+            Err(e) => (self.builtin_default(), Some(e)),
+        }
+    }
+
+    fn builtin_default(&self) -> Theme {
+        let (name, content) = BUILTIN_THEMES[0];
+        let values = toml::from_str(content).expect("builtin default theme is valid");
+        Theme::from_values(name, values, self.depth).expect("builtin default theme is valid")
+    }
+
+    /// loads the theme and all themes it inherits from, the child overriding the parent
+    fn load_merged(&self, name: &str, visited: &mut Vec<String>) -> anyhow::Result<toml::Table> {
+        if visited.iter().any(|n| n == name) {
+            bail!(
+                "cyclic theme inheritance: {} -> {}",
+                visited.join(" -> "),
+                name
+            );
+        }
+        visited.push(name.to_string());
+
+        let mut values = self.read(name)?;
+        let parent = match values.get("inherits") {
+            Some(Value::String(parent)) => Some(parent.clone()),
+            Some(_) => bail!("'inherits' of theme '{}' has to be a string", name),
+            None => None,
+        };
+        let Some(parent) = parent else {
+            return Ok(values);
+        };
+
+        let mut merged = self.load_merged(&parent, visited)?;
+        match values.remove("palette") {
+            Some(Value::Table(palette)) => match merged.get_mut("palette") {
+                Some(Value::Table(parent_palette)) => parent_palette.extend(palette),
+                _ => {
+                    merged.insert("palette".to_string(), Value::Table(palette));
+                }
+            },
+            Some(_) => bail!("'palette' of theme '{}' has to be a table", name),
+            None => {}
+        }
+        merged.extend(values);
+        Ok(merged)
+    }
+
+    fn read(&self, name: &str) -> anyhow::Result<toml::Table> {
+        let builtin = || {
+            BUILTIN_THEMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, content)| content.to_string())
+                .ok_or_else(|| anyhow!("theme '{}' not found", name))
+        };
+        let Some(theme_dir) = &self.theme_dir else {
+            return toml::from_str(&builtin()?)
+                .with_context(|| format!("failed to parse theme '{}'", name));
+        };
+        let path = theme_dir.join(format!("{}.toml", name));
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => builtin()?,
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        toml::from_str(&content).with_context(|| format!("failed to parse theme '{}'", name))
+    }
+}
+
+/// `"red"` is a shorthand for `{ fg = "red" }`
+fn parse_style(
+    value: &Value,
+    palette: &HashMap<String, Color>,
+    depth: ColorDepth,
+) -> anyhow::Result<Style> {
+    let mut style = Style::default();
+    match value {
+        Value::String(fg) => style = style.fg(depth.convert(parse_color(fg, palette)?)),
+        Value::Table(table) => {
+            for (key, value) in table {
+                match (key.as_str(), value) {
+                    ("fg", Value::String(c)) => {
+                        style = style.fg(depth.convert(parse_color(c, palette)?))
+                    }
+                    ("bg", Value::String(c)) => {
+                        style = style.bg(depth.convert(parse_color(c, palette)?))
+                    }
+                    ("modifiers", Value::Array(modifiers)) => {
+                        for modifier in modifiers {
+                            let modifier = modifier
+                                .as_str()
+                                .ok_or_else(|| anyhow!("modifier has to be a string"))?;
+                            style = style.add_modifier(parse_modifier(modifier)?);
+                        }
+                    }
+                    (key, _) => bail!("unknown style attribute '{}'", key),
+                }
+            }
+        }
+        _ => bail!("style has to be a color or a table"),
+    }
+    Ok(style)
+}
+
+fn parse_color(color: &str, palette: &HashMap<String, Color>) -> anyhow::Result<Color> {
+    if let Some(color) = palette.get(color) {
+        return Ok(*color);
+    }
+    let color = match color {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "light-red" => Color::LightRed,
+        "light-green" => Color::LightGreen,
+        "light-yellow" => Color::LightYellow,
+        "light-blue" => Color::LightBlue,
+        "light-magenta" => Color::LightMagenta,
+        "light-cyan" => Color::LightCyan,
+        "light-gray" => Color::Gray,
+        "dark-gray" => Color::DarkGray,
+        "white" => Color::White,
+        hex if hex.starts_with('#') && hex.len() == 7 && hex.is_ascii() => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+            Color::Rgb(channel(1)?, channel(3)?, channel(5)?)
+        }
+        index => match index.parse::<u8>() {
+            Ok(index) => Color::Indexed(index),
+            Err(_) => bail!("invalid color '{}'", color),
+        },
+    };
+    Ok(color)
+}
+
+fn parse_modifier(modifier: &str) -> anyhow::Result<Modifier> {
+    Ok(match modifier {
+        "bold" => Modifier::BOLD,
+        "dim" => Modifier::DIM,
+        "italic" => Modifier::ITALIC,
+        "underlined" => Modifier::UNDERLINED,
+        "slow_blink" => Modifier::SLOW_BLINK,
+        "rapid_blink" => Modifier::RAPID_BLINK,
+        "reversed" => Modifier::REVERSED,
+        "hidden" => Modifier::HIDDEN,
+        "crossed_out" => Modifier::CROSSED_OUT,
+        invalid => bail!("invalid modifier '{}'", invalid),
+    })
+}
+
+/// steps of the 6x6x6 color cube of xterm
+const CUBE_STEPS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// rgb values of the 16 ansi colors, as xterm displays them
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    let nearest_step = |c: u8| {
+        (0..CUBE_STEPS.len())
+            .min_by_key(|&i| (CUBE_STEPS[i] as i32 - c as i32).abs())
+            .unwrap()
+    };
+    let (ri, gi, bi) = (nearest_step(r), nearest_step(g), nearest_step(b));
+    let cube = (16 + 36 * ri + 6 * gi + bi) as u8;
+
+    // the grayscale ramp 232..=255 is often closer for grayish colors
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = ((avg.saturating_sub(8)) / 10).min(23) as u8;
+    let gray = 232 + gray_index;
+
+    if distance((r, g, b), ansi256_to_rgb(gray)) < distance((r, g, b), ansi256_to_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+fn ansi256_to_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI16[index as usize].1,
+        16..=231 => {
+            let i = index - 16;
+            (
+                CUBE_STEPS[(i / 36) as usize],
+                CUBE_STEPS[((i / 6) % 6) as usize],
+                CUBE_STEPS[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    }
+}
+
+fn rgb_to_ansi16(r: u8, g: u8, b: u8) -> Color {
+    ANSI16
+        .iter()
+        .min_by_key(|(_, rgb)| distance((r, g, b), *rgb))
+        .map(|(color, _)| *color)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use tui::style::{Color, Modifier, Style};
+
+    use super::{parse_color, ColorDepth, ThemeLoader, BUILTIN_THEMES};
+
+    fn loader(depth: ColorDepth) -> ThemeLoader {
+        ThemeLoader::new(PathBuf::from("/nonexistent"), depth)
+    }
+
+    #[test]
+    fn builtin_themes_load() {
+        let loader = loader(ColorDepth::TrueColor);
+        for (name, _) in BUILTIN_THEMES {
+            assert_eq!(loader.load(name).unwrap().name(), *name);
+        }
+        assert!(loader.load("doesnotexist").is_err());
+    }
+
+    #[test]
+    fn scope_fallback() {
+        let (theme, error) = loader(ColorDepth::TrueColor).default_theme();
+        assert!(error.is_none());
+        assert_eq!(theme.get("ui.cursor.doesnotexist"), theme.get("ui.cursor"));
+        assert_eq!(theme.get("doesnotexist"), Style::default());
+    }
+
+    #[test]
+    fn palette_and_inheritance() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(
+            dir.join("parent.toml"),
+            r##"
+            keyword = { fg = "accent", modifiers = ["bold"] }
+            string = "green"
+            [palette]
+            accent = "#ff0000"
+            "##,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("child.toml"),
+            r##"
+            inherits = "parent"
+            string = "accent"
+            [palette]
+            accent = "#00ff00"
+            "##,
+        )
+        .unwrap();
+        let loader = ThemeLoader::new(dir.to_path_buf(), ColorDepth::TrueColor);
+        let child = loader.load("child").unwrap();
+        // the palette of the child is used for inherited styles, too
+        assert_eq!(
+            child.get("keyword"),
+            Style::default()
+                .fg(Color::Rgb(0, 255, 0))
+                .add_modifier(Modifier::BOLD)
+        );
+        assert_eq!(child.get("string").fg, Some(Color::Rgb(0, 255, 0)));
+
+        std::fs::write(dir.join("cycle.toml"), "inherits = \"cycle\"").unwrap();
+        assert!(loader.load("cycle").is_err());
+        std::fs::write(dir.join("flat.toml"), "inherits = \"parent\"\npalette = 1").unwrap();
+        assert!(loader.load("flat").is_err());
+
+        // a broken default in the themes dir falls back to the builtin one
+        std::fs::write(dir.join("default.toml"), "keyword = 1").unwrap();
+        let (theme, error) = loader.default_theme();
+        assert_eq!(theme.name(), "default");
+        let builtin = self::loader(ColorDepth::TrueColor).load("default").unwrap();
+        assert_eq!(theme.get("ui.cursor"), builtin.get("ui.cursor"));
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn colors() {
+        let palette = HashMap::from([("accent".to_string(), Color::Red)]);
+        assert_eq!(parse_color("#ff8000", &palette).unwrap(), Color::Rgb(255, 128, 0));
+        assert_eq!(parse_color("accent", &palette).unwrap(), Color::Red);
+        assert_eq!(parse_color("42", &palette).unwrap(), Color::Indexed(42));
+        // seven bytes, but not seven chars
+        assert!(parse_color("#aéb12", &palette).is_err());
+        assert!(parse_color("#12345g", &palette).is_err());
+    }
+
+    #[test]
+    fn color_depth_fallback() {
+        assert_eq!(
+            ColorDepth::from_env(Some("truecolor"), Some("xterm")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::from_env(None, Some("xterm-256color")),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(
+            ColorDepth::from_env(None, Some("linux")),
+            ColorDepth::Ansi16
+        );
+
+        assert_eq!(
+            ColorDepth::Ansi256.convert(Color::Rgb(255, 0, 0)),
+            Color::Indexed(196)
+        );
+        assert_eq!(
+            ColorDepth::Ansi256.convert(Color::Rgb(128, 128, 128)),
+            Color::Indexed(244)
+        );
+        assert_eq!(
+            ColorDepth::Ansi16.convert(Color::Rgb(250, 10, 10)),
+            Color::LightRed
+        );
+        assert_eq!(ColorDepth::Ansi16.convert(Color::Indexed(21)), Color::Blue);
+        assert_eq!(ColorDepth::Ansi16.convert(Color::Red), Color::Red);
+    }
+}
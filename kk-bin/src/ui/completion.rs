@@ -0,0 +1,145 @@
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    widgets::{Block, Borders, Clear, Paragraph, Widget, Wrap},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{completion::Completion, theme::Theme};
+
+const MAX_ITEMS: u16 = 10;
+const MAX_WIDTH: u16 = 50;
+const MAX_DOC_WIDTH: u16 = 60;
+
+/// the completion menu at the cursor and the docs of the selected item next to it
+pub struct CompletionView<'a> {
+    completion: &'a Completion,
+    theme: &'a Theme,
+    cursor: (u16, u16),
+}
+
+impl<'a> CompletionView<'a> {
+    pub fn new(completion: &'a Completion, theme: &'a Theme, cursor: (u16, u16)) -> Self {
+        Self {
+            completion,
+            theme,
+            cursor,
+        }
+    }
+
+    fn render_docs(&self, menu: Rect, area: Rect, buf: &mut Buffer) {
+        let Some(docs) = self
+            .completion
+            .selected()
+            .and_then(|i| i.documentation.as_ref())
+        else {
+            return;
+        };
+        let docs = docs.trim();
+        if docs.is_empty() {
+            return;
+        }
+        let right = (area.x + area.width).saturating_sub(menu.x + menu.width);
+        let left = menu.x.saturating_sub(area.x);
+        let (x, room) = match right >= left {
+            true => (menu.x + menu.width, right),
+            false => (menu.x.saturating_sub(left.min(MAX_DOC_WIDTH)), left),
+        };
+        let width = room.min(MAX_DOC_WIDTH);
+        if width < 10 {
+            return;
+        }
+        let inner_width = width - 2;
+        let lines: u16 = docs
+            .lines()
+            .map(|l| (l.width() as u16).div_ceil(inner_width).max(1))
+            .sum();
+        let height = (lines + 2).min(area.height);
+        let y = menu.y.min((area.y + area.height).saturating_sub(height));
+        let docs_area = Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        Clear.render(docs_area, buf);
+        Paragraph::new(docs)
+            .style(self.theme.get("ui.popup"))
+            .block(Block::default().borders(Borders::ALL))
+            .wrap(Wrap { trim: false })
+            .render(docs_area, buf);
+    }
+}
+
+impl Widget for CompletionView<'_> {
+    /// `area` is the document area
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let len = self.completion.matches().len() as u16;
+        if len == 0 {
+            return;
+        }
+        let rows: Vec<_> = self
+            .completion
+            .matches()
+            .map(|(source, item)| {
+                (
+                    item.label.as_str(),
+                    item.detail.as_deref().unwrap_or(source),
+                )
+            })
+            .collect();
+        let label_width = rows.iter().map(|(l, _)| l.width()).max().unwrap_or(0) as u16;
+        let detail_width = rows.iter().map(|(_, d)| d.width()).max().unwrap_or(0) as u16;
+        let width = (label_width + detail_width + 3)
+            .min(MAX_WIDTH)
+            .min(area.width);
+
+        // below the cursor if it fits, above otherwise
+        let (cursor_x, cursor_y) = self.cursor;
+        let below = (area.y + area.height).saturating_sub(cursor_y + 1);
+        let above = cursor_y.saturating_sub(area.y);
+        let height = len.min(MAX_ITEMS).min(below.max(above));
+        if height == 0 {
+            return;
+        }
+        let y = match below >= height {
+            true => cursor_y + 1,
+            false => cursor_y - height,
+        };
+        let x = cursor_x.min((area.x + area.width).saturating_sub(width));
+        let menu = Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        let style = self.theme.get("ui.menu");
+        let selected_style = self.theme.get("ui.menu.selected");
+        let detail_style = style.patch(self.theme.get("comment"));
+        Clear.render(menu, buf);
+        buf.set_style(menu, style);
+        let selected = self.completion.selected_index();
+        let offset = (selected + 1).saturating_sub(height as usize);
+        for (row, (label, detail)) in rows.iter().enumerate().skip(offset).take(height as usize) {
+            let y = menu.y + (row - offset) as u16;
+            let row_area = Rect {
+                y,
+                height: 1,
+                ..menu
+            };
+            let (row_style, detail_style) = match row == selected {
+                true => (selected_style, selected_style),
+                false => (style, detail_style),
+            };
+            buf.set_style(row_area, row_style);
+            buf.set_stringn(menu.x + 1, y, label, width as usize - 1, row_style);
+            let detail_x = menu.x + width.saturating_sub(detail.width() as u16 + 1);
+            if detail_x > menu.x + label.width() as u16 + 1 {
+                buf.set_string(detail_x, y, detail, detail_style);
+            }
+        }
+
+        self.render_docs(menu, area, buf);
+    }
+}
@@ -0,0 +1,454 @@
+use kk_core::DocumentMode;
+use ropey::{Rope, RopeSlice};
+use tui::{buffer::Buffer, layout::Rect, style::Style, widgets::Widget};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{
+    commands::typed::TypedCommand,
+    editor::{KEditor, Severity},
+    view::View,
+};
+
+use super::{document_area, view_areas};
+
+const TAB_WIDTH: usize = 4;
+
+/// a sign column for diagnostics, then the line numbers
+fn gutter_width(text: &Rope) -> u16 {
+    text.len_lines().to_string().len() as u16 + 2
+}
+
+fn char_width(c: char, x: usize) -> usize {
+    match c {
+        '\t' => TAB_WIDTH - x % TAB_WIDTH,
+        '\n' | '\r' => 1,
+        c => c.width().unwrap_or(0),
+    }
+}
+
+/// Row and column of the char `idx` of `line`, the char after the line if it is out of range.
+/// With `wrap` a char that does not fit goes to the start of the next row.
+fn visual_position(line: RopeSlice, idx: usize, width: usize, wrap: bool) -> (usize, usize) {
+    let (mut row, mut x) = (0, 0);
+    for (i, c) in line.chars().chain(std::iter::once(' ')).enumerate() {
+        let mut w = char_width(c, x);
+        if wrap && x > 0 && x + w > width {
+            row += 1;
+            x = 0;
+            w = char_width(c, x);
+        }
+        if i == idx {
+            break;
+        }
+        x += w;
+    }
+    (row, x)
+}
+
+/// where the primary cursor is drawn within the document `area`, if it is visible
+pub fn cursor_position(editor: &KEditor, area: Rect) -> Option<(u16, u16)> {
+    let (view, doc) = editor.current_ref();
+    let text = doc.text();
+    let wrap = editor.settings(doc).soft_wrap;
+    let width = area.width.saturating_sub(gutter_width(text)) as usize;
+    let head = view.selection.primary().head.min(text.len_chars());
+    let line = text.char_to_line(head);
+    let line_start = text.line_to_char(line);
+    let (line_row, x) = visual_position(text.line(line), head - line_start, width, wrap);
+    let row = match wrap {
+        // the rows of the wrapped lines above
+        true => (view.offset..line)
+            .map(|l| {
+                let slice = text.line(l);
+                visual_position(slice, slice.len_chars().saturating_sub(1), width, true).0 + 1
+            })
+            .sum::<usize>(),
+        false => line.checked_sub(view.offset)?,
+    } + line_row;
+    if line < view.offset || row >= area.height as usize {
+        return None;
+    }
+    let x = area.x as usize + gutter_width(text) as usize + x;
+    (x < (area.x + area.width) as usize).then_some((x as u16, area.y + row as u16))
+}
+
+/// The char drawn at `x` of `row`, rows start at line `offset`. Clicks past the end of a row
+/// hit its last char, clicks below the text hit nothing.
+fn char_at(
+    text: &Rope,
+    offset: usize,
+    width: usize,
+    wrap: bool,
+    x: usize,
+    row: usize,
+) -> Option<usize> {
+    let mut line_row = 0;
+    for line_idx in offset..text.len_lines() {
+        let line = text.line(line_idx);
+        // the end of the text is a valid cursor position, drawn like a trailing space
+        let eof = (line_idx + 1 == text.len_lines()).then_some(' ');
+        let (mut r, mut line_x, mut hit) = (0, 0, None);
+        for (i, c) in line.chars().chain(eof).enumerate() {
+            let mut w = char_width(c, line_x);
+            if wrap && line_x > 0 && line_x + w > width {
+                r += 1;
+                line_x = 0;
+                w = char_width(c, line_x);
+            }
+            if line_row + r == row && line_x <= x {
+                hit = Some(i);
+            }
+            line_x += w;
+        }
+        if row <= line_row + r {
+            return hit.map(|i| text.line_to_char(line_idx) + i);
+        }
+        line_row += r + 1;
+    }
+    None
+}
+
+/// the char of the current document under the mouse at `column` and `row` of the terminal
+pub fn position_at(editor: &KEditor, area: Rect, column: u16, row: u16) -> Option<usize> {
+    let inside = (area.x..area.x + area.width).contains(&column)
+        && (area.y..area.y + area.height).contains(&row);
+    if !inside {
+        return None;
+    }
+    let (view, doc) = editor.current_ref();
+    let text = doc.text();
+    let gutter = gutter_width(text);
+    let width = area.width.saturating_sub(gutter) as usize;
+    let x = column.saturating_sub(area.x + gutter) as usize;
+    let wrap = editor.settings(doc).soft_wrap;
+    char_at(text, view.offset, width, wrap, x, (row - area.y) as usize)
+}
+
+/// with soft wrap the lines above the cursor may take more rows than there are, scrolls further
+/// down until it is visible
+pub fn scroll_to_cursor(editor: &mut KEditor, area: Rect) {
+    if !editor.settings(editor.current_ref().1).soft_wrap {
+        return;
+    }
+    while cursor_position(editor, area).is_none() {
+        let (view, doc) = editor.current();
+        let text = doc.text();
+        let line = text.char_to_line(view.selection.primary().head.min(text.len_chars()));
+        if view.offset >= line {
+            break;
+        }
+        view.offset += 1;
+    }
+}
+
+/// draws the views, the statusline of the focused one and the command line
+pub struct EditorView<'a> {
+    editor: &'a KEditor,
+}
+
+impl<'a> EditorView<'a> {
+    pub fn new(editor: &'a KEditor) -> Self {
+        Self { editor }
+    }
+
+    /// only the focused view shows its cursors
+    fn render_document(&self, view: &View, focused: bool, area: Rect, buf: &mut Buffer) {
+        let theme = &self.editor.theme;
+        let doc = &self.editor.documents[&view.doc];
+        let text = doc.text();
+        buf.set_style(area, theme.get("ui.background"));
+
+        let first_line = view.offset.min(text.len_lines().saturating_sub(1));
+        let last_line = (first_line + area.height as usize).min(text.len_lines());
+        let start = text.line_to_char(first_line);
+        let end = if last_line < text.len_lines() {
+            text.line_to_char(last_line)
+        } else {
+            text.len_chars()
+        };
+
+        // one style per visible char, plus one for the position at the end of the text
+        let mut styles = vec![theme.get("ui.text"); end - start + 1];
+        if let Some(syntax) = doc.syntax() {
+            for span in syntax.highlights(text, start..end) {
+                let style = theme.highlight(span.highlight);
+                for s in &mut styles[span.start.max(start) - start..span.end.min(end) - start] {
+                    *s = s.patch(style);
+                }
+            }
+        }
+        for d in doc.diagnostics().iter() {
+            // points are drawn on the char they are at
+            let to = d.to.max(d.from + 1);
+            if d.from >= end || to <= start {
+                continue;
+            }
+            let style = theme.get(&format!("diagnostic.{}", d.severity.name()));
+            for s in &mut styles[d.from.max(start) - start..to.min(end) - start] {
+                *s = s.patch(style);
+            }
+        }
+        let selection_style = theme.get("ui.selection");
+        let cursor_style = match self.editor.mode {
+            DocumentMode::Normal => theme.get("ui.cursor.normal"),
+            DocumentMode::Insert => theme.get("ui.cursor.insert"),
+        };
+        for range in view.selection.iter() {
+            if !range.is_point() {
+                for i in range.from().max(start)..range.to(text).min(end) {
+                    styles[i - start] = styles[i - start].patch(selection_style);
+                }
+            }
+            if (start..=end).contains(&range.head) && focused && self.editor.prompt.is_none() {
+                let s = &mut styles[range.head - start];
+                *s = s.patch(cursor_style);
+            }
+        }
+
+        let gutter_width = gutter_width(text);
+        let linenr_style = theme.get("ui.linenr");
+        let mut signs = vec![None; last_line - first_line];
+        for d in doc.diagnostics().iter().filter(|d| d.from <= text.len_chars()) {
+            let line = text.char_to_line(d.from);
+            if (first_line..last_line).contains(&line) {
+                let sign = &mut signs[line - first_line];
+                *sign = (*sign).max(Some(d.severity));
+            }
+        }
+        let text_area = Rect {
+            x: area.x + gutter_width.min(area.width),
+            width: area.width.saturating_sub(gutter_width),
+            ..area
+        };
+        let wrap = self.editor.settings(doc).soft_wrap;
+        let mut y = area.y;
+        for (row, line_idx) in (first_line..last_line).enumerate() {
+            if y >= area.y + area.height {
+                break;
+            }
+            let sign = signs[row].map(|s| ("●", theme.get(s.name())));
+            let (sign, sign_style) = sign.unwrap_or((" ", linenr_style));
+            buf.set_stringn(area.x, y, sign, gutter_width as usize, sign_style);
+            buf.set_stringn(
+                area.x + 1,
+                y,
+                format!(
+                    "{:>width$} ",
+                    line_idx + 1,
+                    width = gutter_width as usize - 2
+                ),
+                gutter_width as usize - 1,
+                linenr_style,
+            );
+
+            let line_start = text.line_to_char(line_idx);
+            // the end of the text is a valid cursor position, drawn like a trailing space
+            let eof = (line_idx + 1 == text.len_lines()).then_some(' ');
+            let mut x = 0;
+            for (i, c) in text.line(line_idx).chars().chain(eof).enumerate() {
+                let style = styles[line_start + i - start];
+                let mut width = char_width(c, x);
+                if x + width > text_area.width as usize {
+                    if !wrap || x == 0 {
+                        break;
+                    }
+                    y += 1;
+                    x = 0;
+                    width = char_width(c, x);
+                    if y >= area.y + area.height {
+                        break;
+                    }
+                }
+                let symbol = match c {
+                    '\t' | '\n' | '\r' => " ".to_string(),
+                    c => c.to_string(),
+                };
+                for w in 0..width {
+                    let cell = buf.get_mut(text_area.x + (x + w) as u16, y);
+                    cell.set_symbol(if w == 0 { &symbol } else { " " });
+                    cell.set_style(style);
+                }
+                x += width;
+            }
+            y += 1;
+        }
+    }
+
+    fn render_statusline(&self, area: Rect, buf: &mut Buffer) {
+        let theme = &self.editor.theme;
+        let (view, doc) = self.editor.current_ref();
+        let base = theme.get("ui.statusline");
+        buf.set_style(area, base);
+
+        let (mode, mode_style) = match self.editor.mode {
+            DocumentMode::Normal => (" NOR ", theme.get("ui.statusline.normal")),
+            DocumentMode::Insert => (" INS ", theme.get("ui.statusline.insert")),
+        };
+        let (x, _) = buf.set_stringn(area.x, area.y, mode, area.width as usize, mode_style);
+
+        let name = doc
+            .path()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "[scratch]".to_string());
+        buf.set_stringn(
+            x + 1,
+            area.y,
+            name,
+            area.width.saturating_sub(x + 1 - area.x) as usize,
+            base,
+        );
+
+        let text = doc.text();
+        let head = view.selection.primary().head.min(text.len_chars());
+        let line = text.char_to_line(head);
+        let mut position = format!(" {}:{} ", line + 1, head - text.line_to_char(line) + 1);
+        if let Some(input) = self.editor.keymap.pending_input() {
+            position = format!(" {} {}", input, position);
+        }
+        let width = position.len() as u16;
+        if width < area.width {
+            buf.set_string(area.x + area.width - width, area.y, position, base);
+        }
+    }
+
+    fn render_command_line(&self, area: Rect, buf: &mut Buffer) {
+        let theme = &self.editor.theme;
+        buf.set_style(area, theme.get("ui.background"));
+        if let Some(prompt) = &self.editor.prompt {
+            let style = theme.get("ui.prompt");
+            let (x, _) =
+                buf.set_stringn(area.x, area.y, prompt.prefix(), area.width as usize, style);
+            buf.set_stringn(
+                x,
+                area.y,
+                prompt.line(),
+                area.width.saturating_sub(x - area.x) as usize,
+                style,
+            );
+            let cursor_x = x as usize
+                + prompt
+                    .line()
+                    .chars()
+                    .take(prompt.cursor())
+                    .map(|c| c.width().unwrap_or(0))
+                    .sum::<usize>();
+            if cursor_x < (area.x + area.width) as usize {
+                let cell = buf.get_mut(cursor_x as u16, area.y);
+                cell.set_style(theme.get("ui.cursor"));
+            }
+
+            // the hint or the doc of the command being typed, right aligned if there is room
+            let name = prompt.line().split_whitespace().next().unwrap_or_default();
+            let hint = prompt.hint().or_else(|| {
+                let command = TypedCommand::from_name(name).map(|c| c.doc);
+                command.or_else(|| self.editor.command(name).map(|c| c.doc))
+            });
+            if let Some(hint) = hint {
+                let width = hint.width() as u16 + 1;
+                if cursor_x as u16 + width < area.x + area.width {
+                    buf.set_string(
+                        area.x + area.width - width,
+                        area.y,
+                        hint,
+                        theme.get("comment"),
+                    );
+                }
+            }
+        } else if let Some((status, severity)) = &self.editor.status {
+            let style = match severity {
+                Severity::Info => theme.get("info"),
+                Severity::Error => theme.get("error"),
+            };
+            buf.set_stringn(area.x, area.y, status, area.width as usize, style);
+        } else {
+            // the diagnostic under the cursor
+            let (view, doc) = self.editor.current_ref();
+            let head = view.selection.primary().head;
+            if let Some(d) = doc.diagnostics().at(head) {
+                let first_line = d.message.lines().next().unwrap_or_default();
+                let message = match &d.source {
+                    Some(source) => format!("{} ({})", first_line, source),
+                    None => first_line.to_string(),
+                };
+                let style = theme.get(d.severity.name());
+                buf.set_stringn(area.x, area.y, message, area.width as usize, style);
+            }
+        }
+    }
+}
+
+impl Widget for EditorView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.height < 3 {
+            buf.set_style(area, Style::default());
+            return;
+        }
+        let doc_area = document_area(area);
+        let areas = view_areas(doc_area, self.editor.views().count());
+        let border = self.editor.theme.get("ui.background");
+        let border = border.patch(self.editor.theme.get("ui.window"));
+        for (i, (view, view_area)) in self.editor.views().zip(&areas).enumerate() {
+            self.render_document(view, i == self.editor.focus(), *view_area, buf);
+            if i + 1 < areas.len() {
+                for y in doc_area.y..doc_area.y + doc_area.height {
+                    buf.set_string(view_area.x + view_area.width, y, "│", border);
+                }
+            }
+        }
+        self.render_statusline(
+            Rect {
+                y: doc_area.y + doc_area.height,
+                height: 1,
+                ..area
+            },
+            buf,
+        );
+        self.render_command_line(
+            Rect {
+                y: doc_area.y + doc_area.height + 1,
+                height: 1,
+                ..area
+            },
+            buf,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::{char_at, visual_position};
+
+    #[test]
+    fn soft_wrap_positions() {
+        let text = Rope::from("abcdef\tg\n");
+        let line = text.line(0);
+        assert_eq!(visual_position(line, 5, 4, false), (0, 5));
+        assert_eq!(visual_position(line, 3, 4, true), (0, 3));
+        assert_eq!(visual_position(line, 4, 4, true), (1, 0));
+        // the tab fills the rest of the row, g goes to the next one
+        assert_eq!(visual_position(line, 6, 4, true), (1, 2));
+        assert_eq!(visual_position(line, 7, 4, true), (2, 0));
+    }
+
+    #[test]
+    fn click_positions() {
+        let text = Rope::from("abcdef\n\tg\nxy");
+        assert_eq!(char_at(&text, 0, 4, false, 2, 0), Some(2));
+        // past the end of the line is its newline
+        assert_eq!(char_at(&text, 0, 4, false, 10, 0), Some(6));
+        // inside the tab
+        assert_eq!(char_at(&text, 0, 4, false, 2, 1), Some(7));
+        assert_eq!(char_at(&text, 0, 4, false, 4, 1), Some(8));
+        assert_eq!(char_at(&text, 1, 4, false, 0, 1), Some(10));
+        // the end of the text
+        assert_eq!(char_at(&text, 1, 4, false, 5, 1), Some(12));
+        assert_eq!(char_at(&text, 1, 4, false, 0, 2), None);
+
+        // `abcd` and `ef` are two rows
+        assert_eq!(char_at(&text, 0, 4, true, 1, 1), Some(5));
+        assert_eq!(char_at(&text, 0, 4, true, 3, 1), Some(6));
+        assert_eq!(char_at(&text, 0, 4, true, 0, 2), Some(7));
+    }
+}
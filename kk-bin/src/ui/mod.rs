@@ -1,31 +1,80 @@
-use std::io::{Stdout, Write};
+pub mod completion;
+mod editor_view;
+pub mod picker;
+pub mod popup;
+pub mod prompt;
+mod terminal;
+pub mod which_key;
 
-use crossterm::{
-    event::EnableMouseCapture,
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+pub use editor_view::position_at;
+pub use terminal::{enter_ui, exit_ui, resume, set_mouse_capture, suspend, Signal, Signals};
+
+use tui::{backend::Backend, layout::Rect, Frame};
+
+use crate::editor::KEditor;
+
+use self::{
+    completion::CompletionView,
+    editor_view::{cursor_position, scroll_to_cursor, EditorView},
+    picker::PickerView,
+    popup::PopupView,
+    which_key::WhichKeyView,
 };
-use tui::{backend::CrosstermBackend, Terminal};
-
-/// enters raw mode
-pub fn enter_ui() -> anyhow::Result<Terminal<CrosstermBackend<Stdout>>> {
-    enable_raw_mode()?;
-    let mut stdout = std::io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let terminal = Terminal::new(backend)?;
-    Ok(terminal)
+
+/// area of the document, everything but the statusline and the command line
+pub fn document_area(area: Rect) -> Rect {
+    Rect {
+        height: area.height.saturating_sub(2),
+        ..area
+    }
+}
+
+/// The areas of `count` views side by side in the document `area`, with a column for the
+/// border between them. Views that do not fit get no width.
+pub fn view_areas(area: Rect, count: usize) -> Vec<Rect> {
+    let count = count.max(1) as u16;
+    let width = area.width.saturating_sub(count - 1) / count;
+    (0..count)
+        .map(|i| {
+            let x = (area.x + i * (width + 1)).min(area.x + area.width);
+            let width = match i + 1 == count {
+                true => area.x + area.width - x,
+                false => width,
+            };
+            Rect { x, width, ..area }
+        })
+        .collect()
+}
+
+/// the area of the focused view
+fn focused_area(editor: &KEditor, area: Rect) -> Rect {
+    view_areas(document_area(area), editor.views().count())[editor.focus()]
 }
 
-/// exits raw mode
-pub fn exit_ui() -> anyhow::Result<()> {
-    disable_raw_mode();
-    let mut stdout = std::io::stdout();
-    execute!(
-        stdout, 
-        LeaveAlternateScreen,
-        EnableMouseCapture
-    );
-    std::io::stdout().write(b"\x1B[0 q")?;
-    Ok(())
+pub fn render<B: Backend>(editor: &mut KEditor, frame: &mut Frame<B>) {
+    let area = frame.size();
+    let height = document_area(area).height as usize;
+    for view in std::iter::once(&mut editor.view).chain(&mut editor.splits) {
+        view.ensure_cursor_in_view(&editor.documents[&view.doc], height);
+    }
+    scroll_to_cursor(editor, focused_area(editor, area));
+    frame.render_widget(EditorView::new(editor), area);
+    if let Some(completion) = &editor.completion {
+        if let Some(cursor) = cursor_position(editor, focused_area(editor, area)) {
+            let view = CompletionView::new(completion, &editor.theme, cursor);
+            frame.render_widget(view, document_area(area));
+        }
+    }
+    if let Some(which_key) = editor.which_key() {
+        frame.render_widget(
+            WhichKeyView::new(&which_key, &editor.theme),
+            document_area(area),
+        );
+    }
+    if let Some(popup) = &editor.popup {
+        frame.render_widget(PopupView::new(popup, &editor.theme), document_area(area));
+    }
+    if let Some(picker) = &editor.picker {
+        frame.render_widget(PickerView::new(picker, &editor.theme), document_area(area));
+    }
 }
@@ -0,0 +1,260 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    widgets::{Block, Borders, Clear, Widget},
+};
+
+use crate::{editor::KEditor, theme::Theme};
+
+use super::prompt::{Prompt, PromptEvent};
+
+type OnSelect = Box<dyn FnOnce(&mut KEditor, usize) -> anyhow::Result<()>>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PickerAction {
+    Close,
+    Select,
+}
+
+/// Fuzzy filtered list of labels. The callback gets the index of the picked label in the
+/// original list, so callers keep their items themselves.
+pub struct Picker {
+    title: String,
+    items: Vec<String>,
+    prompt: Prompt,
+    /// indices into `items`, best match first
+    matches: Vec<usize>,
+    cursor: usize,
+    on_select: OnSelect,
+}
+
+impl std::fmt::Debug for Picker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Picker")
+            .field("title", &self.title)
+            .field("items", &self.items.len())
+            .field("query", &self.prompt.line())
+            .finish()
+    }
+}
+
+impl Picker {
+    pub fn new(
+        title: impl Into<String>,
+        items: Vec<String>,
+        on_select: impl FnOnce(&mut KEditor, usize) -> anyhow::Result<()> + 'static,
+    ) -> Self {
+        let mut picker = Self {
+            title: title.into(),
+            items,
+            prompt: Prompt::new("> "),
+            matches: Vec::new(),
+            cursor: 0,
+            on_select: Box::new(on_select),
+        };
+        picker.filter();
+        picker
+    }
+
+    fn filter(&mut self) {
+        let matcher = SkimMatcherV2::default();
+        let query = self.prompt.line();
+        let mut scored: Vec<_> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| Some((matcher.fuzzy_match(item, query)?, i)))
+            .collect();
+        // stable, equal scores keep the original order
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.cursor = 0;
+    }
+
+    /// index of the highlighted item in the original list
+    pub fn selected(&self) -> Option<usize> {
+        self.matches.get(self.cursor).copied()
+    }
+
+    fn move_cursor(&mut self, forward: bool) {
+        let len = self.matches.len();
+        if len == 0 {
+            return;
+        }
+        self.cursor = if forward {
+            (self.cursor + 1) % len
+        } else {
+            (self.cursor + len - 1) % len
+        };
+    }
+
+    pub fn handle_key(&mut self, event: KeyEvent) -> Option<PickerAction> {
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        match event.code {
+            KeyCode::Esc => return Some(PickerAction::Close),
+            KeyCode::Enter => return Some(PickerAction::Select),
+            KeyCode::Down | KeyCode::Tab => self.move_cursor(true),
+            KeyCode::Char('n') if ctrl => self.move_cursor(true),
+            KeyCode::Up | KeyCode::BackTab => self.move_cursor(false),
+            KeyCode::Char('p') if ctrl => self.move_cursor(false),
+            _ => match self.prompt.handle_key(event) {
+                Some(PromptEvent::Update) => self.filter(),
+                Some(PromptEvent::Abort) => return Some(PickerAction::Close),
+                _ => {}
+            },
+        }
+        None
+    }
+
+    /// pasted into the query
+    pub fn insert(&mut self, text: &str) {
+        self.prompt.insert(text);
+        self.filter();
+    }
+
+    /// runs the callback with the highlighted item, nothing happens if nothing matched
+    pub fn select(self, editor: &mut KEditor) -> anyhow::Result<()> {
+        match self.selected() {
+            Some(i) => (self.on_select)(editor, i),
+            None => Ok(()),
+        }
+    }
+}
+
+/// draws the picker centered over the editor
+pub struct PickerView<'a> {
+    picker: &'a Picker,
+    theme: &'a Theme,
+}
+
+impl<'a> PickerView<'a> {
+    pub fn new(picker: &'a Picker, theme: &'a Theme) -> Self {
+        Self { picker, theme }
+    }
+}
+
+impl Widget for PickerView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let width = (area.width * 4 / 5).max(area.width.min(20));
+        let height = (area.height * 3 / 5).max(area.height.min(5));
+        let area = Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+        let style = self.theme.get("ui.popup");
+        Clear.render(area, buf);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(self.picker.title.as_str())
+            .style(style);
+        let inner = block.inner(area);
+        block.render(area, buf);
+        if inner.height == 0 {
+            return;
+        }
+
+        let prompt = self.picker.prompt.prefix().to_string() + self.picker.prompt.line();
+        buf.set_stringn(inner.x, inner.y, prompt, inner.width as usize, style);
+        let count = format!("{}/{}", self.picker.matches.len(), self.picker.items.len());
+        if (count.len() as u16) < inner.width {
+            buf.set_string(
+                inner.x + inner.width - count.len() as u16,
+                inner.y,
+                count,
+                self.theme.get("comment"),
+            );
+        }
+
+        // keeps the cursor on the last visible row when scrolling down
+        let rows = inner.height.saturating_sub(1) as usize;
+        let offset = (self.picker.cursor + 1).saturating_sub(rows);
+        let selected = style.patch(self.theme.get("ui.selection"));
+        for (row, &i) in self
+            .picker
+            .matches
+            .iter()
+            .skip(offset)
+            .take(rows)
+            .enumerate()
+        {
+            let y = inner.y + 1 + row as u16;
+            let line_style = if row + offset == self.picker.cursor {
+                buf.set_style(Rect::new(inner.x, y, inner.width, 1), selected);
+                selected
+            } else {
+                style
+            };
+            buf.set_stringn(
+                inner.x,
+                y,
+                &self.picker.items[i],
+                inner.width as usize,
+                line_style,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Picker, PickerAction};
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn fuzzy_filter_and_navigation() {
+        let items = vec!["src/main.rs", "src/editor.rs", "Cargo.toml"];
+        let mut picker = Picker::new(
+            "files",
+            items.into_iter().map(String::from).collect(),
+            |_, _| Ok(()),
+        );
+        assert_eq!(picker.selected(), Some(0));
+        picker.handle_key(key(KeyCode::Up));
+        assert_eq!(picker.selected(), Some(2));
+
+        for c in "edr".chars() {
+            picker.handle_key(key(KeyCode::Char(c)));
+        }
+        assert_eq!(picker.selected(), Some(1));
+        picker.handle_key(key(KeyCode::Down));
+        assert_eq!(
+            picker.selected(),
+            Some(1),
+            "only one match to cycle through"
+        );
+
+        picker.handle_key(key(KeyCode::Char('x')));
+        assert_eq!(picker.selected(), None);
+        assert_eq!(
+            picker.handle_key(key(KeyCode::Enter)),
+            Some(PickerAction::Select)
+        );
+        assert_eq!(
+            picker.handle_key(key(KeyCode::Esc)),
+            Some(PickerAction::Close)
+        );
+    }
+
+    #[test]
+    fn paste_into_query() {
+        let items = vec!["src/main.rs", "src/editor.rs"];
+        let mut picker = Picker::new(
+            "files",
+            items.into_iter().map(String::from).collect(),
+            |_, _| Ok(()),
+        );
+        picker.insert("edi");
+        assert_eq!(picker.selected(), Some(1));
+        picker.insert("\r\nx");
+        assert_eq!(picker.prompt.line(), "edi x");
+    }
+}
@@ -0,0 +1,61 @@
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    widgets::{Block, Borders, Clear, Paragraph, Widget, Wrap},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::theme::Theme;
+
+/// Some text shown above the statusline until the next key press, e.g. hover docs
+#[derive(Debug, Clone)]
+pub struct Popup {
+    contents: String,
+}
+
+impl Popup {
+    pub fn new(contents: impl Into<String>) -> Self {
+        Self {
+            contents: contents.into(),
+        }
+    }
+}
+
+pub struct PopupView<'a> {
+    popup: &'a Popup,
+    theme: &'a Theme,
+}
+
+impl<'a> PopupView<'a> {
+    pub fn new(popup: &'a Popup, theme: &'a Theme) -> Self {
+        Self { popup, theme }
+    }
+}
+
+impl Widget for PopupView<'_> {
+    /// `area` is the document area, the popup sticks to its bottom left corner
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let contents = self.popup.contents.trim_end();
+        let longest = contents.lines().map(|l| l.width()).max().unwrap_or(0) as u16;
+        let width = (longest + 2).min(area.width).min(80);
+        let inner_width = width.saturating_sub(2).max(1);
+        let lines: u16 = contents
+            .lines()
+            .map(|l| (l.width() as u16).div_ceil(inner_width).max(1))
+            .sum();
+        let height = (lines + 2).min(area.height / 2).max(area.height.min(3));
+        let area = Rect {
+            x: area.x,
+            y: area.y + area.height - height,
+            width,
+            height,
+        };
+
+        Clear.render(area, buf);
+        Paragraph::new(contents)
+            .style(self.theme.get("ui.popup"))
+            .block(Block::default().borders(Borders::ALL))
+            .wrap(Wrap { trim: false })
+            .render(area, buf);
+    }
+}
@@ -0,0 +1,135 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use kk_core::document::LineEnding;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PromptEvent {
+    /// the input changed
+    Update,
+    /// enter was pressed
+    Validate,
+    /// the prompt was closed without validating
+    Abort,
+}
+
+/// single line input, e.g. for typed commands
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    prefix: String,
+    line: String,
+    /// char index into `line`
+    cursor: usize,
+    /// shown after the line until it is edited, e.g. the candidates of a completion
+    hint: Option<String>,
+}
+
+impl Prompt {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            line: String::new(),
+            cursor: 0,
+            hint: None,
+        }
+    }
+
+    /// prompt with some input already typed, the cursor is at its end
+    pub fn with_line(prefix: impl Into<String>, line: impl Into<String>) -> Self {
+        let line = line.into();
+        Self {
+            prefix: prefix.into(),
+            cursor: line.chars().count(),
+            line,
+            hint: None,
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    pub fn set_hint(&mut self, hint: impl Into<String>) {
+        self.hint = Some(hint.into());
+    }
+
+    fn byte_index(&self, char_idx: usize) -> usize {
+        self.line
+            .char_indices()
+            .nth(char_idx)
+            .map_or(self.line.len(), |(i, _)| i)
+    }
+
+    /// inserts `text` at the cursor, line breaks become spaces
+    pub fn insert(&mut self, text: &str) {
+        let text = LineEnding::Lf.normalize(text).replace('\n', " ");
+        let i = self.byte_index(self.cursor);
+        self.line.insert_str(i, &text);
+        self.cursor += text.chars().count();
+        self.hint = None;
+    }
+
+    /// edits the line, returns what happened if the key was relevant to the prompt
+    pub fn handle_key(&mut self, event: KeyEvent) -> Option<PromptEvent> {
+        let prompt_event = self.edit(event);
+        if prompt_event == Some(PromptEvent::Update) {
+            self.hint = None;
+        }
+        prompt_event
+    }
+
+    fn edit(&mut self, event: KeyEvent) -> Option<PromptEvent> {
+        match event.code {
+            KeyCode::Enter => Some(PromptEvent::Validate),
+            KeyCode::Esc => Some(PromptEvent::Abort),
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(PromptEvent::Abort)
+            }
+            KeyCode::Backspace if self.line.is_empty() => Some(PromptEvent::Abort),
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let i = self.byte_index(self.cursor);
+                self.line.remove(i);
+                Some(PromptEvent::Update)
+            }
+            KeyCode::Delete if self.cursor < self.line.chars().count() => {
+                let i = self.byte_index(self.cursor);
+                self.line.remove(i);
+                Some(PromptEvent::Update)
+            }
+            KeyCode::Left => {
+                self.cursor = self.cursor.saturating_sub(1);
+                None
+            }
+            KeyCode::Right => {
+                self.cursor = (self.cursor + 1).min(self.line.chars().count());
+                None
+            }
+            KeyCode::Home => {
+                self.cursor = 0;
+                None
+            }
+            KeyCode::End => {
+                self.cursor = self.line.chars().count();
+                None
+            }
+            KeyCode::Char(c) => {
+                let i = self.byte_index(self.cursor);
+                self.line.insert(i, c);
+                self.cursor += 1;
+                Some(PromptEvent::Update)
+            }
+            _ => None,
+        }
+    }
+}
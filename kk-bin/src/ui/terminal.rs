@@ -0,0 +1,232 @@
+//! Sets the terminal up for the editor and restores it on exit, on panic, on SIGTERM and
+//! around suspending to the shell. Restoring undoes the setup in reverse order and only the
+//! first restore after a setup writes anything.
+
+use std::{
+    io::{Stdout, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crossterm::{
+    cursor::SetCursorStyle,
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
+};
+use log::error;
+use tui::{backend::CrosstermBackend, Terminal};
+
+/// set between `enter_ui` and `exit_ui`
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// set while the terminal reports keys with the kitty keyboard protocol
+static KEYBOARD_ENHANCED: AtomicBool = AtomicBool::new(false);
+
+/// the escape sequences of the setup, everything but raw mode
+fn setup(w: &mut impl Write, keyboard_enhancement: bool) -> std::io::Result<()> {
+    // pastes come in as one event instead of keys that would run commands
+    queue!(
+        w,
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    // tells `C-i` from `tab` and `C-m` from `ret`, `C-S-1` comes in as `C-!`
+    if keyboard_enhancement {
+        queue!(
+            w,
+            PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
+            )
+        )?;
+    }
+    w.flush()
+}
+
+/// undoes `setup`, the cursor shape set by the editor goes back to the one of the user
+fn teardown(w: &mut impl Write, keyboard_enhancement: bool) -> std::io::Result<()> {
+    if keyboard_enhancement {
+        queue!(w, PopKeyboardEnhancementFlags)?;
+    }
+    queue!(
+        w,
+        DisableBracketedPaste,
+        DisableMouseCapture,
+        SetCursorStyle::DefaultUserShape,
+        LeaveAlternateScreen
+    )?;
+    w.flush()
+}
+
+/// raw mode first, keys typed during the setup must not be echoed
+fn init() -> anyhow::Result<()> {
+    enable_raw_mode()?;
+    let keyboard_enhancement = supports_keyboard_enhancement().unwrap_or(false);
+    KEYBOARD_ENHANCED.store(keyboard_enhancement, Ordering::Relaxed);
+    ACTIVE.store(true, Ordering::Relaxed);
+    setup(&mut std::io::stdout(), keyboard_enhancement)?;
+    Ok(())
+}
+
+/// enters raw mode
+pub fn enter_ui() -> anyhow::Result<Terminal<CrosstermBackend<Stdout>>> {
+    init()?;
+    let backend = CrosstermBackend::new(std::io::stdout());
+    let terminal = Terminal::new(backend)?;
+    Ok(terminal)
+}
+
+/// exits raw mode, after the escape sequences so they are not mangled by the line
+/// discipline. Raw mode is left even when writing them failed.
+pub fn exit_ui() -> anyhow::Result<()> {
+    if !ACTIVE.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let keyboard_enhancement = KEYBOARD_ENHANCED.swap(false, Ordering::Relaxed);
+    let written = teardown(&mut std::io::stdout(), keyboard_enhancement);
+    let raw_mode = disable_raw_mode();
+    written?;
+    raw_mode?;
+    Ok(())
+}
+
+/// `enter_ui` captures the mouse, without capture the terminal selects text itself
+pub fn set_mouse_capture(enabled: bool) -> std::io::Result<()> {
+    match enabled {
+        true => execute!(std::io::stdout(), EnableMouseCapture),
+        false => execute!(std::io::stdout(), DisableMouseCapture),
+    }
+}
+
+/// Restores the terminal and stops the process like `C-z` in a cooked terminal would. Returns
+/// once the shell continues it, with the terminal set up again. The screen has to be redrawn
+/// completely, the shell drew over it.
+#[cfg(unix)]
+pub fn suspend() -> anyhow::Result<()> {
+    exit_ui()?;
+    // SAFETY: raising a signal has no preconditions, SIGTSTP is not handled so the default
+    // action stops the process until SIGCONT
+    if unsafe { libc::raise(libc::SIGTSTP) } != 0 {
+        error!("Failed to suspend: {}", std::io::Error::last_os_error());
+    }
+    init()
+}
+
+#[cfg(not(unix))]
+pub fn suspend() -> anyhow::Result<()> {
+    anyhow::bail!("suspending is not supported on this platform")
+}
+
+/// re-enables raw mode after SIGCONT, everything else survives a stop
+pub fn resume() -> anyhow::Result<()> {
+    if ACTIVE.load(Ordering::Relaxed) {
+        enable_raw_mode()?;
+    }
+    Ok(())
+}
+
+/// what the signals the event loop waits for ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGTERM and SIGHUP, exit like `quit` so the terminal gets restored
+    Terminate,
+    /// SIGCONT after something else than `suspend` stopped the process, the shell may have
+    /// reset raw mode and drawn over the screen
+    Continue,
+}
+
+/// Signals that would otherwise kill the editor with the terminal still in raw mode. A
+/// resize comes through the event stream as `Event::Resize`, crossterm listens for SIGWINCH.
+#[derive(Debug, Default)]
+pub struct Signals {
+    #[cfg(unix)]
+    streams: Vec<(Signal, tokio::signal::unix::Signal)>,
+}
+
+impl Signals {
+    /// needs a tokio runtime, signals that can not be listened for are logged and skipped
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let kinds = [
+                (Signal::Terminate, SignalKind::terminate()),
+                (Signal::Terminate, SignalKind::hangup()),
+                (Signal::Continue, SignalKind::from_raw(libc::SIGCONT)),
+            ];
+            let streams = kinds
+                .into_iter()
+                .filter_map(|(what, kind)| match signal(kind) {
+                    Ok(stream) => Some((what, stream)),
+                    Err(e) => {
+                        error!("Failed to listen for {:?}: {}", kind, e);
+                        None
+                    }
+                })
+                .collect();
+            Self { streams }
+        }
+        #[cfg(not(unix))]
+        Self::default()
+    }
+
+    /// waits for the next signal, pending forever when none are listened for
+    pub async fn next(&mut self) -> Signal {
+        #[cfg(unix)]
+        {
+            let received: Vec<_> = self
+                .streams
+                .iter_mut()
+                .map(|(signal, stream)| {
+                    Box::pin(async move {
+                        stream.recv().await;
+                        *signal
+                    })
+                })
+                .collect();
+            if !received.is_empty() {
+                return futures_util::future::select_all(received).await.0;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{setup, teardown};
+
+    fn written(f: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const MOUSE_ON: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1003h\x1b[?1015h\x1b[?1006h";
+    const MOUSE_OFF: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1003l\x1b[?1002l\x1b[?1000l";
+
+    #[test]
+    fn setup_sequences() {
+        let plain = written(|w| setup(w, false));
+        assert_eq!(plain, format!("\x1b[?1049h{}\x1b[?2004h", MOUSE_ON));
+        let enhanced = written(|w| setup(w, true));
+        assert_eq!(enhanced, format!("{}\x1b[>5u", plain));
+    }
+
+    #[test]
+    fn teardown_reverses_setup() {
+        let plain = written(|w| teardown(w, false));
+        assert_eq!(
+            plain,
+            format!("\x1b[?2004l{}\x1b[0 q\x1b[?1049l", MOUSE_OFF)
+        );
+        let enhanced = written(|w| teardown(w, true));
+        assert_eq!(enhanced, format!("\x1b[<1u{}", plain));
+    }
+}
@@ -0,0 +1,164 @@
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    widgets::{Block, Borders, Clear, Widget},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    keymap::{
+        input::KeyInput,
+        tree::{KeyInputTypes, KeymapTree},
+    },
+    theme::Theme,
+};
+
+/// The keys that continue a started sequence and what they do. Commands come first, then
+/// the prefixes by their label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhichKey {
+    title: String,
+    entries: Vec<(String, String)>,
+}
+
+impl WhichKey {
+    pub fn new(tree: &KeymapTree, keys: &[KeyInput]) -> Self {
+        let typed = keys
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let title = match &tree.label {
+            Some(label) => format!("{} ({})", label, typed),
+            None => typed,
+        };
+
+        let mut commands = Vec::new();
+        let mut prefixes = Vec::new();
+        for (node, subtree) in &tree.nodes {
+            let key = match node.key() {
+                KeyInputTypes::MATCH(key) => key.to_string(),
+                KeyInputTypes::MATCH_ALL => "any".to_string(),
+                KeyInputTypes::MATCH_NONE => continue,
+            };
+            let docs = node
+                .get_cmds()
+                .iter()
+                .map(|c| c.doc)
+                .collect::<Vec<_>>()
+                .join(", ");
+            match subtree {
+                Some(subtree) => {
+                    let label = subtree.label.as_deref().unwrap_or("...");
+                    let doc = match docs.is_empty() {
+                        true => format!("+{}", label),
+                        false => format!("{}, +{}", docs, label),
+                    };
+                    prefixes.push((label.to_string(), key, doc));
+                }
+                None => commands.push((key, docs)),
+            }
+        }
+        commands.sort();
+        prefixes.sort();
+        let entries = commands
+            .into_iter()
+            .chain(prefixes.into_iter().map(|(_, key, doc)| (key, doc)))
+            .collect();
+        Self { title, entries }
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+}
+
+pub struct WhichKeyView<'a> {
+    which_key: &'a WhichKey,
+    theme: &'a Theme,
+}
+
+impl<'a> WhichKeyView<'a> {
+    pub fn new(which_key: &'a WhichKey, theme: &'a Theme) -> Self {
+        Self { which_key, theme }
+    }
+}
+
+impl Widget for WhichKeyView<'_> {
+    /// `area` is the document area, the popup sticks to its bottom right corner
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let entries = self.which_key.entries();
+        let key_width = entries.iter().map(|(k, _)| k.width()).max().unwrap_or(0);
+        let doc_width = entries.iter().map(|(_, d)| d.width()).max().unwrap_or(0);
+        let width = ((key_width + doc_width + 4) as u16)
+            .max(self.which_key.title.width() as u16 + 2)
+            .min(area.width);
+        let height = (entries.len() as u16 + 2).min(area.height);
+        if width < 3 || height < 3 {
+            return;
+        }
+        let area = Rect {
+            x: area.x + area.width - width,
+            y: area.y + area.height - height,
+            width,
+            height,
+        };
+
+        let style = self.theme.get("ui.popup");
+        let key_style = style.patch(self.theme.get("function"));
+        Clear.render(area, buf);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(self.which_key.title.as_str())
+            .style(style);
+        let inner = block.inner(area);
+        block.render(area, buf);
+        for (row, (key, doc)) in entries.iter().take(inner.height as usize).enumerate() {
+            let y = inner.y + row as u16;
+            buf.set_stringn(inner.x, y, key, inner.width as usize, key_style);
+            let x = inner.x + key_width as u16 + 2;
+            if x < inner.x + inner.width {
+                buf.set_stringn(x, y, doc, (inner.x + inner.width - x) as usize, style);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::{
+        commands::KCommand,
+        keymap::{
+            input::KeyInput,
+            tree::{KeyInputTypes, KeymapTree},
+        },
+    };
+
+    use super::WhichKey;
+
+    #[test]
+    fn entries_with_docs_and_labels() {
+        let key = |k: &str| KeyInputTypes::MATCH(KeyInput::from_str(k).unwrap());
+        let mut root = KeymapTree::new();
+        root.set_label(&[key("space")], "leader".to_string());
+        root.set_label(&[key("space"), key("g")], "goto".to_string());
+        root.insert_chain(vec![key("space"), key("q")], vec![&KCommand::quit]);
+        root.insert_chain(vec![key("space"), key("g"), key("d")], vec![&KCommand::nop]);
+        root.insert_chain(vec![key("space"), key("w"), key("d")], vec![&KCommand::nop]);
+
+        let mut keymap = crate::keymap::map::Keymap::new();
+        keymap.load_keymap_tree(kk_core::DocumentMode::Normal, std::sync::Arc::new(root));
+        keymap.get(KeyInput::from_str("space").unwrap());
+        let (tree, keys) = keymap.pending().unwrap();
+        let which_key = WhichKey::new(tree, keys);
+        assert_eq!(which_key.title, "leader (space)");
+        let entries: Vec<_> = which_key
+            .entries()
+            .iter()
+            .map(|(k, d)| format!("{} {}", k, d))
+            .collect();
+        assert_eq!(entries, vec!["q Quit the editor", "w +...", "g +goto"]);
+    }
+}
@@ -0,0 +1,33 @@
+use kk_core::{document::Document, selection::Selection};
+
+use crate::editor::DocumentId;
+
+/// A window onto a document with its own selection and scroll position
+#[derive(Debug, Clone)]
+pub struct View {
+    pub doc: DocumentId,
+    pub selection: Selection,
+    /// first visible line
+    pub offset: usize,
+}
+
+impl View {
+    pub fn new(doc: DocumentId) -> Self {
+        Self {
+            doc,
+            selection: Selection::point(0),
+            offset: 0,
+        }
+    }
+
+    /// scrolls so the primary cursor is within `height` lines
+    pub fn ensure_cursor_in_view(&mut self, doc: &Document, height: usize) {
+        let text = doc.text();
+        let line = text.char_to_line(self.selection.primary().head.min(text.len_chars()));
+        if line < self.offset {
+            self.offset = line;
+        } else if height > 0 && line >= self.offset + height {
+            self.offset = line + 1 - height;
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use ropey::Rope;
+
+use crate::transaction::{Assoc, ChangeSet};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Severity {
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// also the theme scope of the severity
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Hint => "hint",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// a message about the chars `from..to`, a point if both are equal
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub from: usize,
+    pub to: usize,
+    pub severity: Severity,
+    pub message: String,
+    /// who reported it, e.g. `rustc` or `clippy`
+    pub source: Option<String>,
+    pub code: Option<String>,
+}
+
+impl Diagnostic {
+    /// whether the cursor at `pos` is on the diagnostic, points cover the char they are at
+    pub fn covers(&self, pos: usize) -> bool {
+        (self.from <= pos && pos < self.to) || (self.from == self.to && self.from == pos)
+    }
+}
+
+/// Diagnostics of one document. Every provider (a language server, a linter, ...) owns its
+/// set and replaces it as a whole, all of them are kept anchored to the text through edits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    providers: BTreeMap<String, Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn set(&mut self, provider: &str, mut diagnostics: Vec<Diagnostic>) {
+        if diagnostics.is_empty() {
+            self.providers.remove(provider);
+            return;
+        }
+        diagnostics.sort_by_key(|d| (d.from, d.to));
+        self.providers.insert(provider.to_string(), diagnostics);
+    }
+
+    pub fn clear(&mut self, provider: &str) {
+        self.providers.remove(provider);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// all diagnostics ordered by position
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        let mut all: Vec<_> = self.providers.values().flatten().collect();
+        all.sort_by_key(|d| (d.from, d.to, std::cmp::Reverse(d.severity)));
+        all.into_iter()
+    }
+
+    /// the most severe diagnostic under the cursor
+    pub fn at(&self, pos: usize) -> Option<&Diagnostic> {
+        self.providers
+            .values()
+            .flatten()
+            .filter(|d| d.covers(pos))
+            .max_by_key(|d| d.severity)
+    }
+
+    /// first diagnostic starting after `pos`
+    pub fn next(&self, pos: usize) -> Option<&Diagnostic> {
+        self.iter().find(|d| d.from > pos)
+    }
+
+    /// last diagnostic starting before `pos`
+    pub fn prev(&self, pos: usize) -> Option<&Diagnostic> {
+        self.iter().filter(|d| d.from < pos).last()
+    }
+
+    /// Maps the ranges through an edit of the document. Text typed at either end of a
+    /// diagnostic does not become part of it, an empty one stays empty.
+    pub fn map(&mut self, changes: &ChangeSet) {
+        for diagnostic in self.providers.values_mut().flatten() {
+            let from = changes.map_pos(diagnostic.from, Assoc::After);
+            let to = match diagnostic.from == diagnostic.to {
+                true => from,
+                false => changes.map_pos(diagnostic.to, Assoc::Before),
+            };
+            diagnostic.from = from.min(to);
+            diagnostic.to = to.max(from);
+        }
+    }
+
+    /// keeps all ranges within the text, e.g. after it was reloaded
+    pub fn clamp(&mut self, text: &Rope) {
+        let len = text.len_chars();
+        for diagnostic in self.providers.values_mut().flatten() {
+            diagnostic.from = diagnostic.from.min(len);
+            diagnostic.to = diagnostic.to.min(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::{Change, ChangeSet};
+
+    use super::{Diagnostic, Diagnostics, Severity};
+
+    fn diagnostic(from: usize, to: usize, severity: Severity) -> Diagnostic {
+        Diagnostic {
+            from,
+            to,
+            severity,
+            message: format!("{}..{}", from, to),
+            source: None,
+            code: None,
+        }
+    }
+
+    #[test]
+    fn providers_are_replaced_separately() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.set("lsp", vec![diagnostic(4, 6, Severity::Error)]);
+        diagnostics.set("lint", vec![diagnostic(0, 2, Severity::Hint)]);
+        assert_eq!(diagnostics.iter().count(), 2);
+        diagnostics.set("lsp", vec![]);
+        assert_eq!(
+            diagnostics.iter().collect::<Vec<_>>(),
+            vec![&diagnostic(0, 2, Severity::Hint)]
+        );
+    }
+
+    #[test]
+    fn navigation_and_lookup() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.set(
+            "lsp",
+            vec![
+                diagnostic(10, 12, Severity::Warning),
+                diagnostic(2, 5, Severity::Info),
+                diagnostic(3, 4, Severity::Error),
+                diagnostic(8, 8, Severity::Hint),
+            ],
+        );
+        assert_eq!(diagnostics.at(3).unwrap().severity, Severity::Error);
+        assert_eq!(diagnostics.at(4).unwrap().severity, Severity::Info);
+        assert_eq!(diagnostics.at(8).unwrap().severity, Severity::Hint);
+        assert!(diagnostics.at(6).is_none());
+        assert_eq!(diagnostics.next(3).unwrap().from, 8);
+        assert_eq!(diagnostics.prev(10).unwrap().from, 8);
+        assert!(diagnostics.next(10).is_none());
+        assert!(diagnostics.prev(2).is_none());
+    }
+
+    #[test]
+    fn ranges_stay_anchored() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.set("lsp", vec![diagnostic(4, 8, Severity::Error)]);
+        // typing right before and right after does not grow the range
+        diagnostics.map(&ChangeSet::new(vec![
+            Change::insert(4, "ab"),
+            Change::insert(8, "cd"),
+        ]));
+        let d = diagnostics.iter().next().unwrap();
+        assert_eq!((d.from, d.to), (6, 10));
+        // typing inside does
+        diagnostics.map(&ChangeSet::new(vec![Change::insert(7, "x")]));
+        let d = diagnostics.iter().next().unwrap();
+        assert_eq!((d.from, d.to), (6, 11));
+        // an empty one moves with the text after it
+        diagnostics.set("lint", vec![diagnostic(2, 2, Severity::Hint)]);
+        diagnostics.map(&ChangeSet::new(vec![Change::insert(2, "yz")]));
+        let d = diagnostics.iter().next().unwrap();
+        assert_eq!((d.from, d.to), (4, 4));
+        // deleting everything collapses it
+        diagnostics.map(&ChangeSet::new(vec![Change::delete(0, 14)]));
+        let d = diagnostics.iter().next().unwrap();
+        assert_eq!((d.from, d.to), (0, 0));
+    }
+}
@@ -0,0 +1,85 @@
+/// a line of `diff_lines`, with its line ending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// above this many line pairs in the changed middle it is shown as removed and then added
+const MAX_TABLE: usize = 4_000_000;
+
+/// Line diff of `old` to `new`, a longest common subsequence of the lines between the common
+/// start and end.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<_> = old.split_inclusive('\n').collect();
+    let new: Vec<_> = new.split_inclusive('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut lines: Vec<_> = old[..prefix].iter().map(|l| DiffLine::Same(l)).collect();
+    if a.len().saturating_mul(b.len()) > MAX_TABLE {
+        lines.extend(a.iter().map(|l| DiffLine::Removed(l)));
+        lines.extend(b.iter().map(|l| DiffLine::Added(l)));
+    } else {
+        // lengths of the common subsequences of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut table = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i * width + j] = match a[i] == b[j] {
+                    true => table[(i + 1) * width + j + 1] + 1,
+                    false => table[(i + 1) * width + j].max(table[i * width + j + 1]),
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                lines.push(DiffLine::Same(a[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == b.len()
+                || (i < a.len() && table[(i + 1) * width + j] >= table[i * width + j + 1])
+            {
+                lines.push(DiffLine::Removed(a[i]));
+                i += 1;
+            } else {
+                lines.push(DiffLine::Added(b[j]));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Same(l)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_lines, DiffLine::*};
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc\nd\n", "a\nc\nx\nd\n"),
+            vec![
+                Same("a\n"),
+                Removed("b\n"),
+                Same("c\n"),
+                Added("x\n"),
+                Same("d\n")
+            ]
+        );
+        assert_eq!(diff_lines("", "a"), vec![Added("a")]);
+        assert_eq!(diff_lines("a\n", "a\n"), vec![Same("a\n")]);
+        assert_eq!(diff_lines("a", "a\n"), vec![Removed("a"), Added("a\n")]);
+    }
+}
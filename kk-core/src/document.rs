@@ -0,0 +1,276 @@
+use std::path::{Path, PathBuf};
+
+use log::error;
+use ropey::Rope;
+use tree_sitter::InputEdit;
+
+use crate::{
+    diagnostic::Diagnostics,
+    syntax::{point_at, Language, Loader, Syntax},
+    transaction::ChangeSet,
+};
+
+/// how lines end in a document, inserted text is converted to it
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// the first line ending of `text`, `Lf` if it has none
+    pub fn detect(text: &Rope) -> Self {
+        let mut prev = None;
+        for c in text.chars() {
+            if c == '\n' {
+                return match prev {
+                    Some('\r') => LineEnding::Crlf,
+                    _ => LineEnding::Lf,
+                };
+            }
+            prev = Some(c);
+        }
+        LineEnding::Lf
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    /// `text` with every `\r\n`, `\r` and `\n` replaced by this line ending
+    pub fn normalize(&self, text: &str) -> String {
+        let mut normalized = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' => {
+                    chars.next_if_eq(&'\n');
+                    normalized.push_str(self.as_str());
+                }
+                '\n' => normalized.push_str(self.as_str()),
+                c => normalized.push(c),
+            }
+        }
+        normalized
+    }
+}
+
+/// A text buffer, optionally backed by a file
+#[derive(Debug)]
+pub struct Document {
+    text: Rope,
+    path: Option<PathBuf>,
+    line_ending: LineEnding,
+    language: Option<Language>,
+    syntax: Option<Syntax>,
+    /// bumped on every change, language servers use it to order edits
+    version: i32,
+    /// `version` when the text was last loaded or written
+    saved_version: i32,
+    diagnostics: Diagnostics,
+    /// refuses changes, e.g. when opened with `--readonly`
+    readonly: bool,
+}
+
+impl Document {
+    pub fn new(text: Rope) -> Self {
+        Self {
+            line_ending: LineEnding::detect(&text),
+            text,
+            path: None,
+            language: None,
+            syntax: None,
+            version: 0,
+            saved_version: 0,
+            diagnostics: Diagnostics::default(),
+            readonly: false,
+        }
+    }
+
+    pub fn open(path: &Path, loader: &Loader) -> std::io::Result<Self> {
+        let text = match std::fs::File::open(path) {
+            Ok(file) => Rope::from_reader(std::io::BufReader::new(file))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Rope::new(),
+            Err(e) => return Err(e),
+        };
+        let mut doc = Self::new(text);
+        doc.path = Some(path.to_path_buf());
+        doc.detect_language(loader);
+        Ok(doc)
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn language(&self) -> Option<Language> {
+        self.language
+    }
+
+    pub fn syntax(&self) -> Option<&Syntax> {
+        self.syntax.as_ref()
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    /// changed since it was loaded or written
+    pub fn is_modified(&self) -> bool {
+        self.version != self.saved_version
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_version = self.version;
+    }
+
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn diagnostics_mut(&mut self) -> &mut Diagnostics {
+        &mut self.diagnostics
+    }
+
+    /// replaces all diagnostics, e.g. with the ones reported before the document was opened
+    pub fn set_diagnostics(&mut self, diagnostics: Diagnostics) {
+        self.diagnostics = diagnostics;
+        self.diagnostics.clamp(&self.text);
+    }
+
+    /// detects the language from path and shebang and (re)creates the parse tree
+    pub fn detect_language(&mut self, loader: &Loader) {
+        let language = Language::detect(self.path(), &self.text);
+        self.set_language(language, loader);
+    }
+
+    pub fn set_language(&mut self, language: Option<Language>, loader: &Loader) {
+        self.language = language;
+        self.syntax = language
+            .and_then(|l| loader.language_config(l))
+            .and_then(|config| match Syntax::new(&self.text, config) {
+                Ok(syntax) => Some(syntax),
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                }
+            });
+    }
+
+    /// applies the changes to the text and updates the parse tree incrementally
+    pub fn apply(&mut self, changes: &ChangeSet) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut edits = Vec::with_capacity(changes.changes().len());
+        let mut delta: isize = 0;
+        for change in changes.changes() {
+            let from = (change.from as isize + delta) as usize;
+            let to = (change.to as isize + delta) as usize;
+
+            let start_byte = self.text.char_to_byte(from);
+            let old_end_byte = self.text.char_to_byte(to);
+            let start_position = point_at(&self.text, from);
+            let old_end_position = point_at(&self.text, to);
+
+            self.text.remove(from..to);
+            let inserted = match &change.text {
+                Some(text) => {
+                    self.text.insert(from, text);
+                    text.chars().count()
+                }
+                None => 0,
+            };
+            delta += inserted as isize - (change.to - change.from) as isize;
+
+            edits.push(InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte: self.text.char_to_byte(from + inserted),
+                start_position,
+                old_end_position,
+                new_end_position: point_at(&self.text, from + inserted),
+            });
+        }
+        if let Some(syntax) = &mut self.syntax {
+            syntax.update(&self.text, &edits);
+        }
+        self.diagnostics.map(changes);
+        self.version += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use crate::{
+        syntax::{Language, Loader},
+        transaction::{Change, ChangeSet},
+    };
+
+    use super::{Document, LineEnding};
+
+    #[test]
+    fn incremental_reparse() {
+        let loader = Loader::new(vec!["keyword".to_string()]);
+        let mut doc = Document::new(Rope::from_str("fn main() {\n}\n"));
+        doc.set_language(Some(Language::Rust), &loader);
+        let keywords = |doc: &Document| -> Vec<String> {
+            let text = doc.text();
+            doc.syntax()
+                .unwrap()
+                .highlights(text, 0..text.len_chars())
+                .into_iter()
+                .map(|span| text.slice(span.start..span.end).to_string())
+                .collect()
+        };
+        assert_eq!(keywords(&doc), vec!["fn"]);
+
+        doc.apply(&ChangeSet::new(vec![
+            Change::replace(3, 7, "start"),
+            Change::insert(12, "    let a = 1;\n"),
+        ]));
+        assert_eq!(doc.text().to_string(), "fn start() {\n    let a = 1;\n}\n");
+        assert!(!doc.syntax().unwrap().root().has_error());
+        assert_eq!(keywords(&doc), vec!["fn", "let"]);
+        assert_eq!(doc.version(), 1);
+        assert!(doc.is_modified());
+        doc.mark_saved();
+        assert!(!doc.is_modified());
+    }
+
+    #[test]
+    fn line_endings() {
+        let doc = Document::new(Rope::from_str("a\r\nb\n"));
+        assert_eq!(doc.line_ending(), LineEnding::Crlf);
+        assert_eq!(
+            LineEnding::detect(&Rope::from_str("a\nb\r\n")),
+            LineEnding::Lf
+        );
+        assert_eq!(LineEnding::detect(&Rope::from_str("a")), LineEnding::Lf);
+
+        assert_eq!(LineEnding::Lf.normalize("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert_eq!(LineEnding::Crlf.normalize("a\nb\r\n\r"), "a\r\nb\r\n\r\n");
+    }
+}
@@ -1,5 +1,23 @@
+pub mod diagnostic;
+pub mod diff;
+pub mod document;
+pub mod pairs;
+pub mod selection;
+pub mod syntax;
+pub mod transaction;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum DocumentMode {
     Normal,
     Insert,
 }
+
+impl DocumentMode {
+    /// as used in the config, e.g. `[keys.normal]`
+    pub fn name(&self) -> &'static str {
+        match self {
+            DocumentMode::Normal => "normal",
+            DocumentMode::Insert => "insert",
+        }
+    }
+}
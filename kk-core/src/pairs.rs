@@ -0,0 +1,298 @@
+use ropey::Rope;
+use tree_sitter::Node;
+
+use crate::syntax::Syntax;
+
+/// the pairs that are auto-paired unless configured otherwise
+pub const DEFAULT_PAIRS: &[(char, char)] = &[
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('"', '"'),
+    ('\'', '\''),
+    ('`', '`'),
+];
+
+/// what matching and surround treat as brackets, they nest
+const BRACKETS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')];
+
+/// quotes do not nest, they pair up within a line
+const QUOTES: &[char] = &['"', '\'', '`'];
+
+/// the pair `c` opens or closes, any other char surrounds with itself
+pub fn pair_of(c: char) -> (char, char) {
+    BRACKETS
+        .iter()
+        .copied()
+        .find(|(open, close)| *open == c || *close == c)
+        .unwrap_or((c, c))
+}
+
+/// The position of the bracket matching the one at `pos`, or the first one after `pos` on the
+/// line. With a syntax tree, only brackets of the same node pair up, so the ones in strings
+/// and comments do not count.
+pub fn matching_bracket(text: &Rope, syntax: Option<&Syntax>, pos: usize) -> Option<usize> {
+    let line_end = text.line_to_char(text.char_to_line(pos.min(text.len_chars())) + 1);
+    let pos = (pos..line_end).find(|i| bracket(text.char(*i)).is_some())?;
+    if let Some(syntax) = syntax {
+        if let Some(found) = matching_node(text, syntax, pos) {
+            return found;
+        }
+    }
+    match_in_text(text, pos)
+}
+
+/// `(pair, is_open)`
+fn bracket(c: char) -> Option<((char, char), bool)> {
+    BRACKETS.iter().find_map(|&(open, close)| match c {
+        _ if c == open => Some(((open, close), true)),
+        _ if c == close => Some(((open, close), false)),
+        _ => None,
+    })
+}
+
+/// `None` if the bracket is not a node of its own, like one in a string
+fn matching_node(text: &Rope, syntax: &Syntax, pos: usize) -> Option<Option<usize>> {
+    let ((open, close), is_open) = bracket(text.char(pos))?;
+    let byte = text.char_to_byte(pos);
+    let node = syntax
+        .root()
+        .descendant_for_byte_range(byte, byte + 1)
+        .filter(|node| !node.is_named() && node.byte_range() == (byte..byte + 1))?;
+    let (same, other) = match is_open {
+        true => (open.to_string(), close.to_string()),
+        false => (close.to_string(), open.to_string()),
+    };
+    let mut depth = 0;
+    let mut next = sibling(node, is_open);
+    while let Some(node) = next {
+        if node.kind() == same {
+            depth += 1;
+        } else if node.kind() == other {
+            if depth == 0 {
+                return Some(Some(text.byte_to_char(node.start_byte())));
+            }
+            depth -= 1;
+        }
+        next = sibling(node, is_open);
+    }
+    Some(None)
+}
+
+fn sibling(node: Node<'_>, next: bool) -> Option<Node<'_>> {
+    match next {
+        true => node.next_sibling(),
+        false => node.prev_sibling(),
+    }
+}
+
+/// counts the brackets of the pair in the text, without knowing about strings
+fn match_in_text(text: &Rope, pos: usize) -> Option<usize> {
+    let ((open, close), is_open) = bracket(text.char(pos))?;
+    match is_open {
+        true => find_unmatched(text, pos + 1, (open, close), true),
+        false => find_unmatched(text, pos, (open, close), false),
+    }
+}
+
+/// The first bracket of the pair from `pos` on that closes more than opened, forwards looking
+/// for `close`, backwards (from before `pos`) for `open`.
+fn find_unmatched(
+    text: &Rope,
+    pos: usize,
+    (open, close): (char, char),
+    forward: bool,
+) -> Option<usize> {
+    let (same, wanted) = match forward {
+        true => (open, close),
+        false => (close, open),
+    };
+    let mut depth = 0;
+    let mut chars = text.chars_at(pos);
+    let mut i = pos;
+    loop {
+        let c = match forward {
+            true => chars.next()?,
+            false => chars.prev()?,
+        };
+        if !forward {
+            i -= 1;
+        }
+        if c == same {
+            depth += 1;
+        } else if c == wanted {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        }
+        if forward {
+            i += 1;
+        }
+    }
+}
+
+/// The innermost pair of brackets or quotes around the chars `from..to`, as the positions of
+/// the open and the close char. With a syntax tree only tokens of the same node pair up, so
+/// brackets and quotes in strings and comments do not count. The text is searched when the
+/// tree has no pair around.
+pub fn surrounding_pair(
+    text: &Rope,
+    syntax: Option<&Syntax>,
+    from: usize,
+    to: usize,
+) -> Option<(usize, usize)> {
+    syntax
+        .and_then(|syntax| surrounding_tokens(text, syntax, from, to))
+        .or_else(|| surrounding_in_text(text, from, to))
+}
+
+/// the pair of the innermost node that has one around `from..to`
+fn surrounding_tokens(
+    text: &Rope,
+    syntax: &Syntax,
+    from: usize,
+    to: usize,
+) -> Option<(usize, usize)> {
+    let (start, end) = (
+        text.char_to_byte(from),
+        text.char_to_byte(to.min(text.len_chars())),
+    );
+    let mut node = syntax.root().descendant_for_byte_range(start, end);
+    while let Some(parent) = node {
+        if let Some(pair) = pair_of_children(text, parent, from, to) {
+            return Some(pair);
+        }
+        node = parent.parent();
+    }
+    None
+}
+
+/// The innermost pair among the children of `node` around `from..to`. Brackets nest, quotes
+/// pair the first with the last one.
+fn pair_of_children(text: &Rope, node: Node<'_>, from: usize, to: usize) -> Option<(usize, usize)> {
+    let mut cursor = node.walk();
+    let tokens: Vec<_> = node
+        .children(&mut cursor)
+        .filter(|child| !child.is_named())
+        .filter_map(|child| {
+            let mut kind = child.kind().chars();
+            let c = kind.next().filter(|_| kind.next().is_none())?;
+            let pos = text.byte_to_char(child.start_byte());
+            (bracket(c).is_some() || QUOTES.contains(&c)).then_some((c, pos))
+        })
+        .collect();
+    let mut pairs = Vec::new();
+    let mut opened: Vec<(char, usize)> = Vec::new();
+    for &(c, pos) in &tokens {
+        match bracket(c) {
+            Some((_, true)) => opened.push((c, pos)),
+            // an unmatched `<` is most likely a comparison
+            Some(((open, _), false)) => {
+                if let Some(i) = opened.iter().rposition(|(c, _)| *c == open) {
+                    pairs.push((opened[i].1, pos));
+                    opened.truncate(i);
+                }
+            }
+            None => {}
+        }
+    }
+    for quote in QUOTES {
+        let mut quotes = tokens.iter().filter(|(c, _)| c == quote);
+        if let (Some((_, open)), Some((_, close))) = (quotes.next(), quotes.next_back()) {
+            pairs.push((*open, *close));
+        }
+    }
+    pairs
+        .into_iter()
+        .filter(|(open, close)| *open < from && *close >= to)
+        .max_by_key(|(open, _)| *open)
+}
+
+/// counts the brackets and quotes in the text, without knowing about strings
+fn surrounding_in_text(text: &Rope, from: usize, to: usize) -> Option<(usize, usize)> {
+    let brackets = BRACKETS.iter().filter_map(|&pair| {
+        let open = find_unmatched(text, from, pair, false)?;
+        let close = find_unmatched(text, open + 1, pair, true)?;
+        (close >= to).then_some((open, close))
+    });
+    let quotes = QUOTES.iter().filter_map(|&quote| {
+        let line = text.char_to_line(from);
+        let start = text.line_to_char(line);
+        let end = text.line_to_char(line + 1);
+        let before: Vec<_> = (start..from).filter(|i| text.char(*i) == quote).collect();
+        let open = *before.last().filter(|_| before.len() % 2 == 1)?;
+        let close = (to.max(from)..end).find(|i| text.char(*i) == quote)?;
+        Some((open, close))
+    });
+    brackets.chain(quotes).max_by_key(|(open, _)| *open)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ropey::Rope;
+
+    use crate::syntax::{HighlightConfiguration, Language, Syntax};
+
+    use super::{matching_bracket, pair_of, surrounding_pair};
+
+    fn rust(text: &Rope) -> Syntax {
+        let config = HighlightConfiguration::new(Language::Rust).unwrap();
+        Syntax::new(text, Arc::new(config)).unwrap()
+    }
+
+    #[test]
+    fn match_in_text() {
+        let text = Rope::from("f(a, (b)) [x]\n");
+        assert_eq!(matching_bracket(&text, None, 1), Some(8));
+        assert_eq!(matching_bracket(&text, None, 8), Some(1));
+        assert_eq!(matching_bracket(&text, None, 5), Some(7));
+        // the next bracket on the line
+        assert_eq!(matching_bracket(&text, None, 9), Some(12));
+        assert_eq!(matching_bracket(&text, None, 13), None);
+        assert_eq!(matching_bracket(&Rope::from("(("), None, 0), None);
+    }
+
+    #[test]
+    fn match_with_syntax() {
+        let text = Rope::from("fn f() { let s = \"(}\"; g(1) }\n");
+        let config = HighlightConfiguration::new(Language::Rust).unwrap();
+        let syntax = Syntax::new(&text, Arc::new(config)).unwrap();
+        assert_eq!(matching_bracket(&text, None, 7), Some(19));
+        assert_eq!(matching_bracket(&text, Some(&syntax), 7), Some(28));
+        assert_eq!(matching_bracket(&text, Some(&syntax), 28), Some(7));
+        // brackets in strings are matched by their text
+        assert_eq!(matching_bracket(&text, Some(&syntax), 18), None);
+    }
+
+    #[test]
+    fn surrounding() {
+        let text = Rope::from("a(b [c] \"d\")\n");
+        assert_eq!(surrounding_pair(&text, None, 5, 6), Some((4, 6)));
+        assert_eq!(surrounding_pair(&text, None, 3, 4), Some((1, 11)));
+        assert_eq!(surrounding_pair(&text, None, 9, 10), Some((8, 10)));
+        assert_eq!(surrounding_pair(&text, None, 3, 7), Some((1, 11)));
+        assert_eq!(surrounding_pair(&text, None, 0, 1), None);
+        assert_eq!(pair_of(')'), ('(', ')'));
+        assert_eq!(pair_of('*'), ('*', '*'));
+    }
+
+    #[test]
+    fn surrounding_with_syntax() {
+        let text = Rope::from("fn f() { g(\"(\", x); }\n");
+        let syntax = rust(&text);
+        assert_eq!(surrounding_pair(&text, None, 16, 17), Some((12, 17)));
+        assert_eq!(
+            surrounding_pair(&text, Some(&syntax), 16, 17),
+            Some((10, 17))
+        );
+        // the quotes of the string
+        assert_eq!(
+            surrounding_pair(&text, Some(&syntax), 12, 13),
+            Some((11, 13))
+        );
+        assert_eq!(surrounding_pair(&text, Some(&syntax), 0, 1), None);
+    }
+}
@@ -0,0 +1,178 @@
+use ropey::Rope;
+
+use crate::transaction::{Assoc, ChangeSet};
+
+/// A selection of chars between `anchor` and `head`, both inclusive. `head` is where the cursor
+/// is drawn, so a range with `anchor == head` still covers the char under the cursor.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Range {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Range {
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Self { anchor, head }
+    }
+
+    pub fn point(pos: usize) -> Self {
+        Self::new(pos, pos)
+    }
+
+    pub fn from(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    /// exclusive end, clamped to the text
+    pub fn to(&self, text: &Rope) -> usize {
+        (self.anchor.max(self.head) + 1).min(text.len_chars())
+    }
+
+    pub fn is_point(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn contains(&self, pos: usize) -> bool {
+        self.from() <= pos && pos <= self.anchor.max(self.head)
+    }
+
+    pub fn map(&self, changes: &ChangeSet) -> Self {
+        Self {
+            anchor: changes.map_pos(self.anchor, Assoc::After),
+            head: changes.map_pos(self.head, Assoc::After),
+        }
+    }
+
+    /// keeps both ends within the text, the end of the text itself is a valid position
+    pub fn clamp(&self, text: &Rope) -> Self {
+        let max = text.len_chars();
+        Self::new(self.anchor.min(max), self.head.min(max))
+    }
+}
+
+/// One or more ranges, one of them being the primary one
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Selection {
+    ranges: Vec<Range>,
+    primary: usize,
+}
+
+impl Selection {
+    pub fn new(ranges: Vec<Range>, primary: usize) -> Self {
+        assert!(primary < ranges.len(), "primary range out of bounds");
+        Self { ranges, primary }.normalize()
+    }
+
+    pub fn single(anchor: usize, head: usize) -> Self {
+        Self {
+            ranges: vec![Range::new(anchor, head)],
+            primary: 0,
+        }
+    }
+
+    pub fn point(pos: usize) -> Self {
+        Self::single(pos, pos)
+    }
+
+    pub fn primary(&self) -> Range {
+        self.ranges[self.primary]
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    pub fn ranges(&self) -> &[Range] {
+        &self.ranges
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range> {
+        self.ranges.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// applies `f` to every range
+    pub fn transform(&self, f: impl FnMut(Range) -> Range) -> Self {
+        Self {
+            ranges: self.ranges.iter().copied().map(f).collect(),
+            primary: self.primary,
+        }
+        .normalize()
+    }
+
+    pub fn push(mut self, range: Range) -> Self {
+        self.ranges.push(range);
+        self.primary = self.ranges.len() - 1;
+        self.normalize()
+    }
+
+    pub fn map(&self, changes: &ChangeSet) -> Self {
+        self.transform(|r| r.map(changes))
+    }
+
+    pub fn clamp(&self, text: &Rope) -> Self {
+        self.transform(|r| r.clamp(text))
+    }
+
+    /// sorts the ranges and merges overlapping ones, keeping track of the primary range
+    fn normalize(mut self) -> Self {
+        let primary = self.ranges[self.primary];
+        self.ranges.sort_by_key(|r| r.from());
+        let mut merged: Vec<Range> = Vec::with_capacity(self.ranges.len());
+        let mut primary_index = 0;
+        for range in self.ranges {
+            match merged.last_mut() {
+                Some(last) if range.from() <= last.anchor.max(last.head) => {
+                    let from = last.from();
+                    let to = last.anchor.max(last.head).max(range.anchor.max(range.head));
+                    *last = if range.head < range.anchor {
+                        Range::new(to, from)
+                    } else {
+                        Range::new(from, to)
+                    };
+                }
+                _ => merged.push(range),
+            }
+            if range == primary {
+                primary_index = merged.len() - 1;
+            }
+        }
+        Self {
+            ranges: merged,
+            primary: primary_index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::{Change, ChangeSet};
+
+    use super::{Range, Selection};
+
+    #[test]
+    fn normalize_merges_overlapping() {
+        let selection = Selection::new(
+            vec![Range::new(8, 10), Range::new(0, 2), Range::new(2, 4)],
+            0,
+        );
+        assert_eq!(selection.ranges(), &[Range::new(0, 4), Range::new(8, 10)]);
+        assert_eq!(selection.primary(), Range::new(8, 10));
+    }
+
+    #[test]
+    fn map_through_insert() {
+        let selection = Selection::new(vec![Range::point(2), Range::new(5, 7)], 1);
+        let changes = ChangeSet::new(vec![Change::insert(2, "ab"), Change::insert(6, "x")]);
+        let mapped = selection.map(&changes);
+        assert_eq!(mapped.ranges(), &[Range::point(4), Range::new(7, 10)]);
+        assert_eq!(mapped.primary_index(), 1);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use log::error;
+use ropey::Rope;
+use thiserror::Error;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+
+/// Languages with a grammar compiled into the binary
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Language {
+    Rust,
+    Toml,
+    Markdown,
+    Json,
+    Python,
+}
+
+impl Language {
+    pub const ALL: &'static [Language] = &[
+        Language::Rust,
+        Language::Toml,
+        Language::Markdown,
+        Language::Json,
+        Language::Python,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::Toml => "toml",
+            Language::Markdown => "markdown",
+            Language::Json => "json",
+            Language::Python => "python",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|l| l.name() == name)
+    }
+
+    /// detects the language by path first and falls back to the shebang of the first line
+    pub fn detect(path: Option<&Path>, text: &Rope) -> Option<Self> {
+        path.and_then(Self::from_path).or_else(|| {
+            let first_line = text.get_line(0)?.to_string();
+            Self::from_shebang(&first_line)
+        })
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        if file_name == "Cargo.lock" {
+            return Some(Language::Toml);
+        }
+        match path.extension()?.to_str()? {
+            "rs" => Some(Language::Rust),
+            "toml" => Some(Language::Toml),
+            "md" | "markdown" => Some(Language::Markdown),
+            "json" => Some(Language::Json),
+            "py" | "pyi" => Some(Language::Python),
+            _ => None,
+        }
+    }
+
+    /// e.g. `#!/usr/bin/env python3` or `#!/usr/bin/python3.11 -u`
+    pub fn from_shebang(line: &str) -> Option<Self> {
+        let mut tokens = line.strip_prefix("#!")?.split_whitespace();
+        let mut interpreter = tokens.next()?.rsplit('/').next()?;
+        if interpreter == "env" {
+            interpreter = tokens.find(|t| !t.starts_with('-') && !t.contains('='))?;
+        }
+        let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        match interpreter {
+            "python" | "pypy" => Some(Language::Python),
+            "rust-script" | "cargo-script" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        match self {
+            Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Language::Toml => tree_sitter_toml_ng::LANGUAGE.into(),
+            // note: only the block grammar, inline markdown is not injected (yet)
+            Language::Markdown => tree_sitter_md::LANGUAGE.into(),
+            Language::Json => tree_sitter_json::LANGUAGE.into(),
+            Language::Python => tree_sitter_python::LANGUAGE.into(),
+        }
+    }
+
+    fn highlights_query(&self) -> &'static str {
+        match self {
+            Language::Rust => tree_sitter_rust::HIGHLIGHTS_QUERY,
+            Language::Toml => tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+            Language::Markdown => tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+            Language::Json => tree_sitter_json::HIGHLIGHTS_QUERY,
+            Language::Python => tree_sitter_python::HIGHLIGHTS_QUERY,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SyntaxError {
+    #[error("invalid highlight query for {0:?}: {1}")]
+    Query(Language, tree_sitter::QueryError),
+    #[error("incompatible grammar for {0:?}: {1}")]
+    Grammar(Language, tree_sitter::LanguageError),
+}
+
+/// Index into the list of scopes the highlighter was configured with
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Highlight(pub usize);
+
+/// highlighted range of chars
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub highlight: Highlight,
+}
+
+/// compiled highlight query of a language and the mapping of its capture names to scopes
+#[derive(Debug)]
+pub struct HighlightConfiguration {
+    pub language: Language,
+    grammar: tree_sitter::Language,
+    query: Query,
+    highlight_indices: RwLock<Vec<Option<Highlight>>>,
+}
+
+impl HighlightConfiguration {
+    pub fn new(language: Language) -> Result<Self, SyntaxError> {
+        let grammar = language.grammar();
+        let query = Query::new(&grammar, language.highlights_query())
+            .map_err(|e| SyntaxError::Query(language, e))?;
+        let highlight_indices = RwLock::new(vec![None; query.capture_names().len()]);
+        Ok(Self {
+            language,
+            grammar,
+            query,
+            highlight_indices,
+        })
+    }
+
+    /// Maps every capture name to the most specific scope that is a prefix of it, i.e. with the
+    /// scopes `keyword` and `keyword.control` the capture `keyword.control.conditional` maps to
+    /// `keyword.control` while `keyword.operator` maps to `keyword`. Taken from helix.
+    pub fn configure(&self, scopes: &[String]) {
+        let indices = self
+            .query
+            .capture_names()
+            .iter()
+            .map(|capture| {
+                let capture_parts: Vec<_> = capture.split('.').collect();
+                let mut best: Option<(usize, usize)> = None;
+                for (i, scope) in scopes.iter().enumerate() {
+                    let parts: Vec<_> = scope.split('.').collect();
+                    let matches = parts.len() <= capture_parts.len()
+                        && parts.iter().zip(capture_parts.iter()).all(|(a, b)| a == b);
+                    if matches && best.is_none_or(|(_, len)| parts.len() > len) {
+                        best = Some((i, parts.len()));
+                    }
+                }
+                best.map(|(i, _)| Highlight(i))
+            })
+            .collect();
+        *self.highlight_indices.write().unwrap() = indices;
+    }
+}
+
+/// Creates and caches the highlight configurations, so the queries are only compiled once
+#[derive(Debug, Default)]
+pub struct Loader {
+    configs: Mutex<HashMap<Language, Arc<HighlightConfiguration>>>,
+    scopes: RwLock<Vec<String>>,
+}
+
+impl Loader {
+    pub fn new(scopes: Vec<String>) -> Self {
+        Self {
+            configs: Mutex::new(HashMap::new()),
+            scopes: RwLock::new(scopes),
+        }
+    }
+
+    pub fn language_config(&self, language: Language) -> Option<Arc<HighlightConfiguration>> {
+        let mut configs = self.configs.lock().unwrap();
+        if let Some(config) = configs.get(&language) {
+            return Some(config.clone());
+        }
+        match HighlightConfiguration::new(language) {
+            Ok(config) => {
+                config.configure(&self.scopes.read().unwrap());
+                let config = Arc::new(config);
+                configs.insert(language, config.clone());
+                Some(config)
+            }
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// remaps all captures, e.g. after the theme changed
+    pub fn set_scopes(&self, scopes: Vec<String>) {
+        for config in self.configs.lock().unwrap().values() {
+            config.configure(&scopes);
+        }
+        *self.scopes.write().unwrap() = scopes;
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        self.scopes.read().unwrap().clone()
+    }
+}
+
+/// Parse tree of a document, kept up to date with [`Syntax::update`]
+pub struct Syntax {
+    parser: Parser,
+    tree: Tree,
+    config: Arc<HighlightConfiguration>,
+}
+
+impl std::fmt::Debug for Syntax {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Syntax")
+            .field("language", &self.config.language)
+            .field("tree", &self.tree)
+            .finish()
+    }
+}
+
+impl Syntax {
+    pub fn new(text: &Rope, config: Arc<HighlightConfiguration>) -> Result<Self, SyntaxError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&config.grammar)
+            .map_err(|e| SyntaxError::Grammar(config.language, e))?;
+        let tree = parse(&mut parser, text, None);
+        Ok(Self {
+            parser,
+            tree,
+            config,
+        })
+    }
+
+    pub fn language(&self) -> Language {
+        self.config.language
+    }
+
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    pub fn root(&self) -> Node<'_> {
+        self.tree.root_node()
+    }
+
+    /// reparses incrementally, `edits` have to be in the order they were applied to `text`
+    pub fn update(&mut self, text: &Rope, edits: &[InputEdit]) {
+        if edits.is_empty() {
+            return;
+        }
+        for edit in edits {
+            self.tree.edit(edit);
+        }
+        self.tree = parse(&mut self.parser, text, Some(&self.tree));
+    }
+
+    /// Highlights of the chars in `range`, sorted by start. Nested spans come after their parent,
+    /// so painting them in order lets the innermost capture win.
+    pub fn highlights(&self, text: &Rope, range: Range<usize>) -> Vec<HighlightSpan> {
+        let indices = self.config.highlight_indices.read().unwrap();
+        let byte_range = text.char_to_byte(range.start)..text.char_to_byte(range.end);
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(byte_range);
+        let provider = |node: Node| {
+            text.byte_slice(node.byte_range())
+                .chunks()
+                .map(str::as_bytes)
+        };
+        let mut captures = cursor.captures(&self.config.query, self.root(), provider);
+
+        // (byte range, pattern index, highlight)
+        let mut found: Vec<(Range<usize>, usize, Highlight)> = Vec::new();
+        while let Some((m, i)) = captures.next() {
+            let capture = m.captures[*i];
+            if let Some(highlight) = indices[capture.index as usize] {
+                found.push((capture.node.byte_range(), m.pattern_index, highlight));
+            }
+        }
+        found.sort_by(|(a, ai, _), (b, bi, _)| {
+            a.start
+                .cmp(&b.start)
+                .then(b.end.cmp(&a.end))
+                .then(ai.cmp(bi))
+        });
+        // the first pattern matching a node wins, like in tree-sitter-highlight
+        found.dedup_by(|(b, _, _), (a, _, _)| a == b);
+
+        found
+            .into_iter()
+            .map(|(bytes, _, highlight)| HighlightSpan {
+                start: text.byte_to_char(bytes.start),
+                end: text.byte_to_char(bytes.end),
+                highlight,
+            })
+            .collect()
+    }
+}
+
+fn parse(parser: &mut Parser, text: &Rope, old_tree: Option<&Tree>) -> Tree {
+    let mut read = |byte: usize, _: Point| -> &[u8] {
+        if byte >= text.len_bytes() {
+            return &[];
+        }
+        let (chunk, start, _, _) = text.chunk_at_byte(byte);
+        &chunk.as_bytes()[byte - start..]
+    };
+    parser
+        .parse_with_options(&mut read, old_tree, None)
+        .expect("parser has a language and no timeout")
+}
+
+/// row and byte column of a char index, as tree-sitter wants it
+pub fn point_at(text: &Rope, char_idx: usize) -> Point {
+    let row = text.char_to_line(char_idx);
+    let column = text.char_to_byte(char_idx) - text.line_to_byte(row);
+    Point { row, column }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use ropey::Rope;
+
+    use super::{HighlightConfiguration, Language, Syntax};
+
+    fn scopes() -> Vec<String> {
+        ["keyword", "function", "string", "type", "type.builtin"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn detect_language() {
+        let empty = Rope::new();
+        assert_eq!(
+            Language::detect(Some(Path::new("src/main.rs")), &empty),
+            Some(Language::Rust)
+        );
+        assert_eq!(
+            Language::detect(Some(Path::new("Cargo.lock")), &empty),
+            Some(Language::Toml)
+        );
+        assert_eq!(
+            Language::detect(Some(Path::new("README.md")), &empty),
+            Some(Language::Markdown)
+        );
+        assert_eq!(Language::detect(Some(Path::new("Makefile")), &empty), None);
+
+        let script = Rope::from_str("#!/usr/bin/env -S python3.11 -u\nprint(1)\n");
+        assert_eq!(
+            Language::detect(Some(Path::new("script")), &script),
+            Some(Language::Python)
+        );
+        assert_eq!(
+            Language::from_shebang("#!/usr/bin/python"),
+            Some(Language::Python)
+        );
+        assert_eq!(Language::from_shebang("#!/bin/sh"), None);
+    }
+
+    #[test]
+    fn all_queries_compile() {
+        for language in Language::ALL {
+            assert!(
+                HighlightConfiguration::new(*language).is_ok(),
+                "{:?}",
+                language
+            );
+        }
+    }
+
+    #[test]
+    fn capture_to_scope() {
+        let config = HighlightConfiguration::new(Language::Rust).unwrap();
+        config.configure(&scopes());
+        let text = Rope::from_str("fn main() -> u8 { \"hi\" }");
+        let syntax = Syntax::new(&text, Arc::new(config)).unwrap();
+        let spans = syntax.highlights(&text, 0..text.len_chars());
+        let scopes = scopes();
+        let find = |s: &str| {
+            spans
+                .iter()
+                .find(|span| text.slice(span.start..span.end) == s)
+                .map(|span| scopes[span.highlight.0].as_str())
+        };
+        assert_eq!(find("fn"), Some("keyword"));
+        assert_eq!(find("main"), Some("function"));
+        assert_eq!(find("u8"), Some("type.builtin"));
+        assert_eq!(find("\"hi\""), Some("string"));
+    }
+}
@@ -0,0 +1,183 @@
+use ropey::Rope;
+
+/// replaces the chars `from..to` of the original text with `text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub from: usize,
+    pub to: usize,
+    pub text: Option<String>,
+}
+
+impl Change {
+    pub fn insert(at: usize, text: impl Into<String>) -> Self {
+        Self {
+            from: at,
+            to: at,
+            text: Some(text.into()),
+        }
+    }
+
+    pub fn delete(from: usize, to: usize) -> Self {
+        Self {
+            from,
+            to,
+            text: None,
+        }
+    }
+
+    pub fn replace(from: usize, to: usize, text: impl Into<String>) -> Self {
+        Self {
+            from,
+            to,
+            text: Some(text.into()),
+        }
+    }
+
+    /// number of chars inserted by this change
+    pub fn inserted_len(&self) -> usize {
+        self.text.as_ref().map_or(0, |t| t.chars().count())
+    }
+}
+
+/// which side of an insertion a mapped position sticks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Before,
+    After,
+}
+
+/// A set of non overlapping changes, all positions refer to the text *before* the changes are
+/// applied. Changes are kept sorted so they can be walked front to back when mapping positions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    /// panics on overlapping changes, that is a bug of the caller
+    pub fn new(changes: impl IntoIterator<Item = Change>) -> Self {
+        let mut changes: Vec<_> = changes.into_iter().collect();
+        changes.sort_by_key(|c| (c.from, c.to));
+        for pair in changes.windows(2) {
+            assert!(
+                pair[0].to <= pair[1].from,
+                "overlapping changes: {:?} and {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+        Self { changes }
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// applies all changes back to front so the indices stay valid
+    pub fn apply(&self, text: &mut Rope) {
+        for change in self.changes.iter().rev() {
+            text.remove(change.from..change.to);
+            if let Some(insert) = &change.text {
+                text.insert(change.from, insert);
+            }
+        }
+    }
+
+    /// maps a position in the old text to the corresponding position in the new text.
+    /// Positions inside a replaced range collapse to its start or end depending on `assoc`.
+    pub fn map_pos(&self, pos: usize, assoc: Assoc) -> usize {
+        let mut delta: isize = 0;
+        for change in &self.changes {
+            if pos < change.from {
+                break;
+            }
+            let inserted = change.inserted_len();
+            if pos > change.to || (pos == change.to && change.from < change.to) {
+                delta += inserted as isize - (change.to - change.from) as isize;
+                continue;
+            }
+            let start = (change.from as isize + delta) as usize;
+            return match assoc {
+                Assoc::Before => start,
+                Assoc::After => start + inserted,
+            };
+        }
+        (pos as isize + delta) as usize
+    }
+
+    /// changeset that undoes `self` when applied to the text produced by `self`
+    pub fn invert(&self, original: &Rope) -> ChangeSet {
+        let mut delta: isize = 0;
+        let changes = self
+            .changes
+            .iter()
+            .map(|change| {
+                let from = (change.from as isize + delta) as usize;
+                let inserted = change.inserted_len();
+                delta += inserted as isize - (change.to - change.from) as isize;
+                let removed = original.slice(change.from..change.to).to_string();
+                Change {
+                    from,
+                    to: from + inserted,
+                    text: (!removed.is_empty()).then_some(removed),
+                }
+            })
+            .collect::<Vec<_>>();
+        Self { changes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::{Assoc, Change, ChangeSet};
+
+    #[test]
+    fn apply_multiple() {
+        let mut text = Rope::from_str("hello world");
+        let changes = ChangeSet::new(vec![
+            Change::replace(6, 11, "there"),
+            Change::insert(0, ">> "),
+            Change::delete(4, 5),
+        ]);
+        changes.apply(&mut text);
+        assert_eq!(text.to_string(), ">> hell there");
+    }
+
+    #[test]
+    fn map_pos_around_changes() {
+        let changes = ChangeSet::new(vec![Change::insert(2, "abc"), Change::delete(5, 8)]);
+        assert_eq!(changes.map_pos(0, Assoc::Before), 0);
+        assert_eq!(changes.map_pos(2, Assoc::Before), 2);
+        assert_eq!(changes.map_pos(2, Assoc::After), 5);
+        assert_eq!(changes.map_pos(4, Assoc::Before), 7);
+        assert_eq!(changes.map_pos(6, Assoc::After), 8);
+        assert_eq!(changes.map_pos(8, Assoc::Before), 8);
+        assert_eq!(changes.map_pos(10, Assoc::Before), 10);
+    }
+
+    #[test]
+    fn invert_restores_text() {
+        let original = Rope::from_str("fn main() {}");
+        let changes = ChangeSet::new(vec![
+            Change::replace(3, 7, "start"),
+            Change::insert(11, "x"),
+        ]);
+        let mut text = original.clone();
+        changes.apply(&mut text);
+        assert_eq!(text.to_string(), "fn start() {x}");
+        changes.invert(&original).apply(&mut text);
+        assert_eq!(text, original);
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlapping_changes() {
+        ChangeSet::new(vec![Change::delete(0, 4), Change::delete(2, 6)]);
+    }
+}
@@ -0,0 +1,356 @@
+//! A language server that speaks just enough LSP for the tests. It keeps the text of the open
+//! documents, reports every `TODO` as a warning and answers requests based on the word under
+//! the cursor.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use kk_lsp::{
+    jsonrpc::{self, Message},
+    lsp::{self, Url},
+    util, OffsetEncoding,
+};
+use ropey::Rope;
+use serde_json::{json, Value};
+
+const ENCODING: OffsetEncoding = OffsetEncoding::Utf16;
+
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Message>> {
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(length) = line.strip_prefix("Content-Length: ") {
+            content_length = length.parse().map_err(io::Error::other)?;
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(message: Value) {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn notify(method: &str, params: impl serde::Serialize) {
+    write_message(json!({"jsonrpc": jsonrpc::VERSION, "method": method, "params": params}));
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// char range of the word around `pos`
+fn word_at(text: &Rope, pos: usize) -> Option<(usize, usize)> {
+    let mut from = pos;
+    while from > 0 && is_word(text.char(from - 1)) {
+        from -= 1;
+    }
+    let mut to = pos;
+    while to < text.len_chars() && is_word(text.char(to)) {
+        to += 1;
+    }
+    (from < to).then_some((from, to))
+}
+
+/// char ranges of all whole word occurrences of `word`
+fn occurrences(text: &Rope, word: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    (0..chars.len().saturating_sub(word.len() - 1))
+        .filter(|&i| {
+            chars[i..i + word.len()] == word[..]
+                && (i == 0 || !is_word(chars[i - 1]))
+                && chars.get(i + word.len()).is_none_or(|&c| !is_word(c))
+        })
+        .map(|i| (i, i + word.len()))
+        .collect()
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, Rope>,
+}
+
+impl Server {
+    fn publish_diagnostics(&self, uri: &Url, version: Option<i32>) {
+        let text = &self.documents[uri];
+        let diagnostics: Vec<_> = occurrences(text, "TODO")
+            .into_iter()
+            .map(|(from, to)| lsp::Diagnostic {
+                range: util::range_to_lsp_range(text, from, to, ENCODING),
+                severity: Some(lsp::DiagnosticSeverity::WARNING),
+                source: Some("fake-lsp".to_string()),
+                message: "unfinished work".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        notify(
+            "textDocument/publishDiagnostics",
+            lsp::PublishDiagnosticsParams::new(uri.clone(), diagnostics, version),
+        );
+    }
+
+    /// the document and the word under the position
+    fn word(&self, params: &lsp::TextDocumentPositionParams) -> Option<(&Rope, String)> {
+        let text = self.documents.get(&params.text_document.uri)?;
+        let pos = util::lsp_pos_to_pos(text, params.position, ENCODING)?;
+        let (from, to) = word_at(text, pos)?;
+        Some((text, text.slice(from..to).to_string()))
+    }
+
+    fn locations(&self, params: &lsp::TextDocumentPositionParams) -> Vec<lsp::Location> {
+        let Some((text, word)) = self.word(params) else {
+            return Vec::new();
+        };
+        occurrences(text, &word)
+            .into_iter()
+            .map(|(from, to)| lsp::Location {
+                uri: params.text_document.uri.clone(),
+                range: util::range_to_lsp_range(text, from, to, ENCODING),
+            })
+            .collect()
+    }
+
+    fn notification(&mut self, method: &str, params: Value) -> bool {
+        match method {
+            "initialized" => notify(
+                "window/showMessage",
+                lsp::ShowMessageParams {
+                    typ: lsp::MessageType::INFO,
+                    message: "fake-lsp ready".to_string(),
+                },
+            ),
+            "textDocument/didOpen" => {
+                let params: lsp::DidOpenTextDocumentParams =
+                    serde_json::from_value(params).unwrap();
+                let doc = params.text_document;
+                self.documents
+                    .insert(doc.uri.clone(), Rope::from_str(&doc.text));
+                self.publish_diagnostics(&doc.uri, Some(doc.version));
+            }
+            "textDocument/didChange" => {
+                let params: lsp::DidChangeTextDocumentParams =
+                    serde_json::from_value(params).unwrap();
+                let doc = params.text_document;
+                let text = self.documents.get_mut(&doc.uri).unwrap();
+                for change in params.content_changes {
+                    match change.range {
+                        Some(range) => {
+                            let edit = lsp::TextEdit::new(range, change.text);
+                            util::edits_to_changes(text, vec![edit], ENCODING).apply(text);
+                        }
+                        None => *text = Rope::from_str(&change.text),
+                    }
+                }
+                self.publish_diagnostics(&doc.uri, Some(doc.version));
+            }
+            "textDocument/didClose" => {
+                let params: lsp::DidCloseTextDocumentParams =
+                    serde_json::from_value(params).unwrap();
+                self.documents.remove(&params.text_document.uri);
+            }
+            "exit" => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Result<Value, jsonrpc::Error> {
+        let result = match method {
+            "initialize" => json!(lsp::InitializeResult {
+                capabilities: lsp::ServerCapabilities {
+                    position_encoding: Some(lsp::PositionEncodingKind::UTF16),
+                    text_document_sync: Some(lsp::TextDocumentSyncCapability::Kind(
+                        lsp::TextDocumentSyncKind::INCREMENTAL,
+                    )),
+                    hover_provider: Some(lsp::HoverProviderCapability::Simple(true)),
+                    definition_provider: Some(lsp::OneOf::Left(true)),
+                    references_provider: Some(lsp::OneOf::Left(true)),
+                    completion_provider: Some(lsp::CompletionOptions::default()),
+                    rename_provider: Some(lsp::OneOf::Left(true)),
+                    code_action_provider: Some(lsp::CodeActionProviderCapability::Simple(true)),
+                    document_formatting_provider: Some(lsp::OneOf::Left(true)),
+                    execute_command_provider: Some(lsp::ExecuteCommandOptions {
+                        commands: vec!["fake.applyEdit".to_string()],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                server_info: Some(lsp::ServerInfo {
+                    name: "fake-lsp".to_string(),
+                    version: None,
+                }),
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/hover" => {
+                let params: lsp::HoverParams = serde_json::from_value(params).unwrap();
+                match self.word(&params.text_document_position_params) {
+                    Some((_, word)) => json!(lsp::Hover {
+                        contents: lsp::HoverContents::Markup(lsp::MarkupContent {
+                            kind: lsp::MarkupKind::PlainText,
+                            value: format!("word `{}`", word),
+                        }),
+                        range: None,
+                    }),
+                    None => Value::Null,
+                }
+            }
+            "textDocument/definition" => {
+                let params: lsp::GotoDefinitionParams = serde_json::from_value(params).unwrap();
+                let locations = self.locations(&params.text_document_position_params);
+                match locations.into_iter().next() {
+                    Some(location) => json!(lsp::GotoDefinitionResponse::Scalar(location)),
+                    None => Value::Null,
+                }
+            }
+            "textDocument/references" => {
+                let params: lsp::ReferenceParams = serde_json::from_value(params).unwrap();
+                json!(self.locations(&params.text_document_position))
+            }
+            "textDocument/completion" => {
+                let params: lsp::CompletionParams = serde_json::from_value(params).unwrap();
+                let position = params.text_document_position;
+                let text = &self.documents[&position.text_document.uri];
+                let pos = util::lsp_pos_to_pos(text, position.position, ENCODING).unwrap();
+                let mut from = pos;
+                while from > 0 && is_word(text.char(from - 1)) {
+                    from -= 1;
+                }
+                let prefix = text.slice(from..pos).to_string();
+                let mut words: Vec<String> = text
+                    .to_string()
+                    .split(|c| !is_word(c))
+                    .filter(|w| w.starts_with(&prefix) && *w != prefix)
+                    .map(String::from)
+                    .collect();
+                words.sort();
+                words.dedup();
+                let items: Vec<_> = words
+                    .into_iter()
+                    .map(|word| lsp::CompletionItem {
+                        detail: Some("word".to_string()),
+                        label: word,
+                        kind: Some(lsp::CompletionItemKind::TEXT),
+                        ..Default::default()
+                    })
+                    .collect();
+                json!(lsp::CompletionResponse::Array(items))
+            }
+            "textDocument/rename" => {
+                let params: lsp::RenameParams = serde_json::from_value(params).unwrap();
+                let uri = params.text_document_position.text_document.uri.clone();
+                let edits: Vec<_> = self
+                    .locations(&params.text_document_position)
+                    .into_iter()
+                    .map(|location| lsp::TextEdit::new(location.range, params.new_name.clone()))
+                    .collect();
+                json!(lsp::WorkspaceEdit::new(HashMap::from([(uri, edits)])))
+            }
+            "textDocument/codeAction" => {
+                let params: lsp::CodeActionParams = serde_json::from_value(params).unwrap();
+                let uri = params.text_document.uri;
+                let actions: Vec<_> = params
+                    .context
+                    .diagnostics
+                    .into_iter()
+                    .map(|diagnostic| {
+                        let edit = lsp::TextEdit::new(diagnostic.range, "DONE".to_string());
+                        lsp::CodeActionOrCommand::CodeAction(lsp::CodeAction {
+                            title: "mark as done".to_string(),
+                            kind: Some(lsp::CodeActionKind::QUICKFIX),
+                            edit: Some(lsp::WorkspaceEdit::new(HashMap::from([(
+                                uri.clone(),
+                                vec![edit],
+                            )]))),
+                            diagnostics: Some(vec![diagnostic]),
+                            ..Default::default()
+                        })
+                    })
+                    .collect();
+                json!(actions)
+            }
+            "textDocument/formatting" => {
+                let params: lsp::DocumentFormattingParams = serde_json::from_value(params).unwrap();
+                let text = &self.documents[&params.text_document.uri];
+                // trims trailing whitespace
+                let edits: Vec<_> = (0..text.len_lines())
+                    .filter_map(|line| {
+                        let content = text.line(line).to_string();
+                        let content = content.trim_end_matches(['\n', '\r']);
+                        let trimmed = content.trim_end().chars().count();
+                        let len = content.chars().count();
+                        (trimmed < len).then(|| {
+                            let start = text.line_to_char(line);
+                            lsp::TextEdit::new(
+                                util::range_to_lsp_range(
+                                    text,
+                                    start + trimmed,
+                                    start + len,
+                                    ENCODING,
+                                ),
+                                String::new(),
+                            )
+                        })
+                    })
+                    .collect();
+                json!(edits)
+            }
+            "workspace/executeCommand" => {
+                let params: lsp::ExecuteCommandParams = serde_json::from_value(params).unwrap();
+                if params.command != "fake.applyEdit" {
+                    return Err(jsonrpc::Error::method_not_found(&params.command));
+                }
+                // asks the client to insert the arguments at the start of the document
+                let uri: Url = serde_json::from_value(params.arguments[0].clone()).unwrap();
+                let text = params.arguments[1].as_str().unwrap_or_default().to_string();
+                let edit = lsp::TextEdit::new(lsp::Range::default(), text);
+                write_message(json!({
+                    "jsonrpc": jsonrpc::VERSION,
+                    "id": "apply-edit",
+                    "method": "workspace/applyEdit",
+                    "params": lsp::ApplyWorkspaceEditParams {
+                        label: None,
+                        edit: lsp::WorkspaceEdit::new(HashMap::from([(uri, vec![edit])])),
+                    },
+                }));
+                Value::Null
+            }
+            _ => return Err(jsonrpc::Error::method_not_found(method)),
+        };
+        Ok(result)
+    }
+}
+
+fn main() {
+    let mut server = Server::default();
+    let mut stdin = io::stdin().lock();
+    while let Ok(Some(message)) = read_message(&mut stdin) {
+        match message {
+            Message::Request(request) => {
+                let result = server.request(&request.method, request.params);
+                write_message(json!(jsonrpc::Response::new(request.id, result)));
+            }
+            Message::Notification(notification) => {
+                if !server.notification(&notification.method, notification.params) {
+                    break;
+                }
+            }
+            Message::Response(response) => {
+                eprintln!("client responded to {}", response.id);
+            }
+        }
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    future::Future,
+    path::Path,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+use kk_core::transaction::ChangeSet;
+use log::{info, warn};
+use lsp::Url;
+use lsp_types as lsp;
+use ropey::Rope;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    jsonrpc::{self, Id},
+    transport::{Payload, Transport},
+    util, Error, OffsetEncoding, Result, ServerMessage,
+};
+
+/// how long to wait for a response before giving up on a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running language server. Requests are sent right away and return `'static` futures that
+/// resolve to the response, so they can be awaited without borrowing the client.
+#[derive(Debug)]
+pub struct Client {
+    id: usize,
+    name: String,
+    _process: Child,
+    server_tx: mpsc::UnboundedSender<Payload>,
+    request_counter: AtomicU64,
+    capabilities: OnceLock<lsp::ServerCapabilities>,
+    root_uri: Option<Url>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Spawns the server, messages the server sends on its own are tagged with `id` and sent
+    /// to `incoming`. The server still needs to be `initialize`d.
+    pub fn start(
+        command: &str,
+        args: &[String],
+        root: Option<&Path>,
+        id: usize,
+        incoming: mpsc::UnboundedSender<(usize, ServerMessage)>,
+    ) -> Result<Self> {
+        let mut process = Command::new(command)
+            .args(args)
+            .current_dir(root.unwrap_or_else(|| Path::new(".")))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin = process.stdin.take().expect("stdin is piped");
+        let stdout = process.stdout.take().expect("stdout is piped");
+        let stderr = process.stderr.take().expect("stderr is piped");
+
+        let name = command.to_string();
+        let server_tx = Transport::start(BufReader::new(stdout), stdin, id, name.clone(), incoming);
+
+        let stderr_name = name.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                info!("{} stderr: {}", stderr_name, line);
+            }
+        });
+
+        Ok(Self {
+            id,
+            name,
+            _process: process,
+            server_tx,
+            request_counter: AtomicU64::new(0),
+            capabilities: OnceLock::new(),
+            root_uri: root.and_then(|root| Url::from_directory_path(root).ok()),
+            timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.capabilities.get().is_some()
+    }
+
+    /// panics if the server is not initialized yet
+    pub fn capabilities(&self) -> &lsp::ServerCapabilities {
+        self.capabilities
+            .get()
+            .expect("language server not yet initialized")
+    }
+
+    pub fn offset_encoding(&self) -> OffsetEncoding {
+        match self
+            .capabilities
+            .get()
+            .and_then(|c| c.position_encoding.as_ref())
+        {
+            Some(encoding) if *encoding == lsp::PositionEncodingKind::UTF8 => OffsetEncoding::Utf8,
+            _ => OffsetEncoding::Utf16,
+        }
+    }
+
+    fn next_id(&self) -> Id {
+        Id::Num(self.request_counter.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn send_request(
+        &self,
+        method: &str,
+        params: impl Serialize,
+    ) -> Result<(Id, oneshot::Receiver<Result<Value>>)> {
+        let id = self.next_id();
+        let request = jsonrpc::Request {
+            jsonrpc: jsonrpc::VERSION.to_string(),
+            id: id.clone(),
+            method: method.to_string(),
+            params: serde_json::to_value(params)?,
+        };
+        let (chan, rx) = oneshot::channel();
+        self.server_tx
+            .send(Payload::Request { request, chan })
+            .map_err(|_| Error::StreamClosed)?;
+        Ok((id, rx))
+    }
+
+    /// sends the request unless `check` failed, the response is parsed as `R::Result`
+    fn call_checked<R>(
+        &self,
+        check: Result<()>,
+        params: R::Params,
+    ) -> impl Future<Output = Result<R::Result>> + 'static
+    where
+        R: lsp::request::Request,
+        R::Result: DeserializeOwned + 'static,
+    {
+        let sent = check.and_then(|()| self.send_request(R::METHOD, params));
+        let timeout = self.timeout;
+        let server_tx = self.server_tx.clone();
+        async move {
+            let (id, rx) = sent?;
+            let value = match tokio::time::timeout(timeout, rx).await {
+                Ok(response) => response.map_err(|_| Error::StreamClosed)??,
+                Err(_) => {
+                    let _ = server_tx.send(Payload::Cancel(id.clone()));
+                    return Err(Error::Timeout(id));
+                }
+            };
+            Ok(serde_json::from_value(value)?)
+        }
+    }
+
+    pub fn call<R>(&self, params: R::Params) -> impl Future<Output = Result<R::Result>> + 'static
+    where
+        R: lsp::request::Request,
+        R::Result: DeserializeOwned + 'static,
+    {
+        self.call_checked::<R>(Ok(()), params)
+    }
+
+    pub fn notify<N>(&self, params: N::Params) -> Result<()>
+    where
+        N: lsp::notification::Notification,
+    {
+        let notification = jsonrpc::Notification {
+            jsonrpc: jsonrpc::VERSION.to_string(),
+            method: N::METHOD.to_string(),
+            params: serde_json::to_value(params)?,
+        };
+        self.server_tx
+            .send(Payload::Notification(notification))
+            .map_err(|_| Error::StreamClosed)
+    }
+
+    /// answers a request the server sent us
+    pub fn reply(&self, id: Id, result: std::result::Result<Value, jsonrpc::Error>) -> Result<()> {
+        self.server_tx
+            .send(Payload::Response(jsonrpc::Response::new(id, result)))
+            .map_err(|_| Error::StreamClosed)
+    }
+
+    fn supports(
+        &self,
+        feature: &'static str,
+        supported: impl FnOnce(&lsp::ServerCapabilities) -> bool,
+    ) -> Result<()> {
+        match self.capabilities.get() {
+            Some(capabilities) if supported(capabilities) => Ok(()),
+            _ => Err(Error::Unsupported(feature)),
+        }
+    }
+
+    // lifecycle
+
+    pub async fn initialize(&self) -> Result<()> {
+        #[allow(deprecated)]
+        let params = lsp::InitializeParams {
+            process_id: Some(std::process::id()),
+            root_uri: self.root_uri.clone(),
+            workspace_folders: self.root_uri.clone().map(|uri| {
+                vec![lsp::WorkspaceFolder {
+                    name: uri
+                        .path_segments()
+                        .and_then(|mut s| s.rfind(|s| !s.is_empty()))
+                        .unwrap_or_default()
+                        .to_string(),
+                    uri,
+                }]
+            }),
+            capabilities: client_capabilities(),
+            client_info: Some(lsp::ClientInfo {
+                name: "kk".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+            ..Default::default()
+        };
+        let result = self.call::<lsp::request::Initialize>(params).await?;
+        if self.capabilities.set(result.capabilities).is_err() {
+            warn!("{}: initialized twice", self.name);
+        }
+        self.notify::<lsp::notification::Initialized>(lsp::InitializedParams {})
+    }
+
+    pub async fn shutdown_and_exit(&self) -> Result<()> {
+        self.call::<lsp::request::Shutdown>(()).await?;
+        self.notify::<lsp::notification::Exit>(())
+    }
+
+    // text synchronization
+
+    pub fn did_open(&self, uri: Url, version: i32, text: &Rope, language_id: &str) -> Result<()> {
+        self.notify::<lsp::notification::DidOpenTextDocument>(lsp::DidOpenTextDocumentParams {
+            text_document: lsp::TextDocumentItem {
+                uri,
+                language_id: language_id.to_string(),
+                version,
+                text: text.to_string(),
+            },
+        })
+    }
+
+    /// `old_text` is the text `changes` were applied to
+    pub fn did_change(
+        &self,
+        uri: Url,
+        version: i32,
+        old_text: &Rope,
+        new_text: &Rope,
+        changes: &ChangeSet,
+    ) -> Result<()> {
+        let kind = match self
+            .capabilities
+            .get()
+            .and_then(|c| c.text_document_sync.as_ref())
+        {
+            Some(lsp::TextDocumentSyncCapability::Kind(kind)) => *kind,
+            Some(lsp::TextDocumentSyncCapability::Options(options)) => {
+                options.change.unwrap_or(lsp::TextDocumentSyncKind::NONE)
+            }
+            None => lsp::TextDocumentSyncKind::NONE,
+        };
+        let content_changes = match kind {
+            lsp::TextDocumentSyncKind::FULL => vec![lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: new_text.to_string(),
+            }],
+            lsp::TextDocumentSyncKind::INCREMENTAL => {
+                util::changes_to_content_changes(old_text, changes, self.offset_encoding())
+            }
+            _ => return Ok(()),
+        };
+        self.notify::<lsp::notification::DidChangeTextDocument>(lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier::new(uri, version),
+            content_changes,
+        })
+    }
+
+    pub fn did_close(&self, uri: Url) -> Result<()> {
+        self.notify::<lsp::notification::DidCloseTextDocument>(lsp::DidCloseTextDocumentParams {
+            text_document: lsp::TextDocumentIdentifier::new(uri),
+        })
+    }
+
+    // language features
+
+    fn position_params(uri: Url, position: lsp::Position) -> lsp::TextDocumentPositionParams {
+        lsp::TextDocumentPositionParams::new(lsp::TextDocumentIdentifier::new(uri), position)
+    }
+
+    pub fn hover(
+        &self,
+        uri: Url,
+        position: lsp::Position,
+    ) -> impl Future<Output = Result<Option<lsp::Hover>>> + 'static {
+        self.call_checked::<lsp::request::HoverRequest>(
+            self.supports("hover", |c| c.hover_provider.is_some()),
+            lsp::HoverParams {
+                text_document_position_params: Self::position_params(uri, position),
+                work_done_progress_params: Default::default(),
+            },
+        )
+    }
+
+    pub fn goto_definition(
+        &self,
+        uri: Url,
+        position: lsp::Position,
+    ) -> impl Future<Output = Result<Option<lsp::GotoDefinitionResponse>>> + 'static {
+        self.call_checked::<lsp::request::GotoDefinition>(
+            self.supports("goto definition", |c| c.definition_provider.is_some()),
+            lsp::GotoDefinitionParams {
+                text_document_position_params: Self::position_params(uri, position),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        )
+    }
+
+    pub fn references(
+        &self,
+        uri: Url,
+        position: lsp::Position,
+    ) -> impl Future<Output = Result<Option<Vec<lsp::Location>>>> + 'static {
+        self.call_checked::<lsp::request::References>(
+            self.supports("references", |c| c.references_provider.is_some()),
+            lsp::ReferenceParams {
+                text_document_position: Self::position_params(uri, position),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: lsp::ReferenceContext {
+                    include_declaration: true,
+                },
+            },
+        )
+    }
+
+    pub fn completion(
+        &self,
+        uri: Url,
+        position: lsp::Position,
+    ) -> impl Future<Output = Result<Option<lsp::CompletionResponse>>> + 'static {
+        self.call_checked::<lsp::request::Completion>(
+            self.supports("completion", |c| c.completion_provider.is_some()),
+            lsp::CompletionParams {
+                text_document_position: Self::position_params(uri, position),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: Some(lsp::CompletionContext {
+                    trigger_kind: lsp::CompletionTriggerKind::INVOKED,
+                    trigger_character: None,
+                }),
+            },
+        )
+    }
+
+    pub fn rename(
+        &self,
+        uri: Url,
+        position: lsp::Position,
+        new_name: String,
+    ) -> impl Future<Output = Result<Option<lsp::WorkspaceEdit>>> + 'static {
+        self.call_checked::<lsp::request::Rename>(
+            self.supports("rename", |c| c.rename_provider.is_some()),
+            lsp::RenameParams {
+                text_document_position: Self::position_params(uri, position),
+                new_name,
+                work_done_progress_params: Default::default(),
+            },
+        )
+    }
+
+    pub fn code_actions(
+        &self,
+        uri: Url,
+        range: lsp::Range,
+        diagnostics: Vec<lsp::Diagnostic>,
+    ) -> impl Future<Output = Result<Option<lsp::CodeActionResponse>>> + 'static {
+        self.call_checked::<lsp::request::CodeActionRequest>(
+            self.supports("code actions", |c| c.code_action_provider.is_some()),
+            lsp::CodeActionParams {
+                text_document: lsp::TextDocumentIdentifier::new(uri),
+                range,
+                context: lsp::CodeActionContext {
+                    diagnostics,
+                    only: None,
+                    trigger_kind: Some(lsp::CodeActionTriggerKind::INVOKED),
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        )
+    }
+
+    pub fn execute_command(
+        &self,
+        command: lsp::Command,
+    ) -> impl Future<Output = Result<Option<Value>>> + 'static {
+        self.call_checked::<lsp::request::ExecuteCommand>(
+            self.supports("execute command", |c| c.execute_command_provider.is_some()),
+            lsp::ExecuteCommandParams {
+                command: command.command,
+                arguments: command.arguments.unwrap_or_default(),
+                work_done_progress_params: Default::default(),
+            },
+        )
+    }
+
+    pub fn formatting(
+        &self,
+        uri: Url,
+        options: lsp::FormattingOptions,
+    ) -> impl Future<Output = Result<Option<Vec<lsp::TextEdit>>>> + 'static {
+        self.call_checked::<lsp::request::Formatting>(
+            self.supports("formatting", |c| c.document_formatting_provider.is_some()),
+            lsp::DocumentFormattingParams {
+                text_document: lsp::TextDocumentIdentifier::new(uri),
+                options,
+                work_done_progress_params: Default::default(),
+            },
+        )
+    }
+}
+
+fn client_capabilities() -> lsp::ClientCapabilities {
+    lsp::ClientCapabilities {
+        workspace: Some(lsp::WorkspaceClientCapabilities {
+            apply_edit: Some(true),
+            configuration: Some(true),
+            workspace_edit: Some(lsp::WorkspaceEditClientCapabilities {
+                document_changes: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        text_document: Some(lsp::TextDocumentClientCapabilities {
+            synchronization: Some(lsp::TextDocumentSyncClientCapabilities {
+                did_save: Some(false),
+                ..Default::default()
+            }),
+            hover: Some(lsp::HoverClientCapabilities {
+                content_format: Some(vec![lsp::MarkupKind::PlainText, lsp::MarkupKind::Markdown]),
+                ..Default::default()
+            }),
+            completion: Some(lsp::CompletionClientCapabilities {
+                completion_item: Some(lsp::CompletionItemCapability {
+                    documentation_format: Some(vec![lsp::MarkupKind::PlainText]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            publish_diagnostics: Some(lsp::PublishDiagnosticsClientCapabilities {
+                version_support: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        window: Some(lsp::WindowClientCapabilities {
+            work_done_progress: Some(true),
+            ..Default::default()
+        }),
+        general: Some(lsp::GeneralClientCapabilities {
+            position_encodings: Some(vec![
+                lsp::PositionEncodingKind::UTF8,
+                lsp::PositionEncodingKind::UTF16,
+            ]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
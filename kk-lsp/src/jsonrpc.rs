@@ -0,0 +1,140 @@
+//! The subset of JSON-RPC 2.0 LSP uses
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const VERSION: &str = "2.0";
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Id {
+    Num(u64),
+    Str(String),
+}
+
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Id::Num(n) => write!(f, "{}", n),
+            Id::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// error object of a failed request
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, thiserror::Error)]
+#[error("{message} ({code})")]
+pub struct Error {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl Error {
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self {
+            code: Self::METHOD_NOT_FOUND,
+            message: format!("method not found: {}", method),
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub id: Id,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Id,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+impl Response {
+    pub fn new(id: Id, result: Result<Value, Error>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+
+    /// a response without result and error is a successful `null`
+    pub fn into_result(self) -> Result<Value, Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+/// Any message, the variants are tried in order: a request has both `id` and `method`, a
+/// notification only `method` and a response only `id`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Message {
+    Request(Request),
+    Notification(Notification),
+    Response(Response),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Id, Message};
+
+    #[test]
+    fn deserialize_message_kinds() {
+        let request: Message =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "id": 1, "method": "a", "params": {}}))
+                .unwrap();
+        assert!(matches!(request, Message::Request(r) if r.id == Id::Num(1)));
+
+        let notification: Message =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "method": "b"})).unwrap();
+        assert!(matches!(notification, Message::Notification(n) if n.method == "b"));
+
+        let response: Message =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "id": "x", "result": null})).unwrap();
+        match response {
+            Message::Response(r) => assert_eq!(r.into_result(), Ok(json!(null))),
+            _ => panic!("not a response"),
+        }
+
+        let error: Message = serde_json::from_value(
+            json!({"jsonrpc": "2.0", "id": 2, "error": {"code": -32601, "message": "nope"}}),
+        )
+        .unwrap();
+        match error {
+            Message::Response(r) => assert_eq!(r.into_result().unwrap_err().code, -32601),
+            _ => panic!("not a response"),
+        }
+    }
+}
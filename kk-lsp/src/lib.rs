@@ -0,0 +1,46 @@
+//! Language server client, heavily inspired by helix-lsp
+
+pub mod client;
+pub mod jsonrpc;
+pub mod transport;
+pub mod util;
+
+pub use client::Client;
+pub use lsp_types as lsp;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("protocol error: {0}")]
+    Rpc(#[from] jsonrpc::Error),
+    #[error("failed to parse: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("request {0} timed out")]
+    Timeout(jsonrpc::Id),
+    #[error("server closed the stream")]
+    StreamClosed,
+    #[error("server does not support {0}")]
+    Unsupported(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// how the server counts the `character` of a position
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OffsetEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+}
+
+/// whatever the server sends without us asking for it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    Request(jsonrpc::Request),
+    Notification(jsonrpc::Notification),
+    /// the server process went away, all pending requests failed
+    Exited,
+}
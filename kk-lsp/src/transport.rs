@@ -0,0 +1,226 @@
+use std::{collections::HashMap, sync::Arc};
+
+use log::{error, info, warn};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot, Mutex},
+};
+
+use crate::{
+    jsonrpc::{self, Id, Message},
+    Error, Result, ServerMessage,
+};
+
+/// what the client sends to the server
+#[derive(Debug)]
+pub enum Payload {
+    Request {
+        request: jsonrpc::Request,
+        chan: oneshot::Sender<Result<Value>>,
+    },
+    Notification(jsonrpc::Notification),
+    Response(jsonrpc::Response),
+    /// the request timed out, the server is told to drop it and its response is ignored
+    Cancel(Id),
+}
+
+/// Reads and writes `Content-Length` framed messages. Responses are routed to the pending
+/// requests, everything else the server sends goes to `incoming`.
+#[derive(Debug)]
+pub struct Transport {
+    id: usize,
+    name: String,
+    pending: Mutex<HashMap<Id, oneshot::Sender<Result<Value>>>>,
+}
+
+impl Transport {
+    pub fn start<R, W>(
+        reader: R,
+        writer: W,
+        id: usize,
+        name: String,
+        incoming: mpsc::UnboundedSender<(usize, ServerMessage)>,
+    ) -> mpsc::UnboundedSender<Payload>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let transport = Arc::new(Self {
+            id,
+            name,
+            pending: Mutex::new(HashMap::new()),
+        });
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(transport.clone().recv(reader, incoming));
+        tokio::spawn(transport.send(writer, rx));
+        tx
+    }
+
+    async fn recv<R>(
+        self: Arc<Self>,
+        mut reader: R,
+        incoming: mpsc::UnboundedSender<(usize, ServerMessage)>,
+    ) where
+        R: AsyncBufRead + Unpin,
+    {
+        loop {
+            let message = match read_message(&mut reader).await {
+                Ok(message) => message,
+                Err(Error::StreamClosed) => break,
+                Err(Error::Parse(e)) => {
+                    error!("{}: failed to parse message: {}", self.name, e);
+                    continue;
+                }
+                Err(e) => {
+                    error!("{}: failed to read message: {}", self.name, e);
+                    break;
+                }
+            };
+            let message = match message {
+                Message::Response(response) => {
+                    let id = response.id.clone();
+                    match self.pending.lock().await.remove(&id) {
+                        Some(chan) => {
+                            let _ = chan.send(response.into_result().map_err(Error::from));
+                        }
+                        None => warn!("{}: response to unknown request {}", self.name, id),
+                    }
+                    continue;
+                }
+                Message::Request(request) => ServerMessage::Request(request),
+                Message::Notification(notification) => ServerMessage::Notification(notification),
+            };
+            if incoming.send((self.id, message)).is_err() {
+                break;
+            }
+        }
+
+        info!("{}: stream closed", self.name);
+        for (_, chan) in self.pending.lock().await.drain() {
+            let _ = chan.send(Err(Error::StreamClosed));
+        }
+        let _ = incoming.send((self.id, ServerMessage::Exited));
+    }
+
+    async fn send<W>(self: Arc<Self>, mut writer: W, mut rx: mpsc::UnboundedReceiver<Payload>)
+    where
+        W: AsyncWrite + Unpin,
+    {
+        while let Some(payload) = rx.recv().await {
+            let json = match payload {
+                Payload::Request { request, chan } => {
+                    self.pending.lock().await.insert(request.id.clone(), chan);
+                    serde_json::to_string(&request)
+                }
+                Payload::Notification(notification) => serde_json::to_string(&notification),
+                Payload::Response(response) => serde_json::to_string(&response),
+                Payload::Cancel(id) => {
+                    self.pending.lock().await.remove(&id);
+                    serde_json::to_string(&jsonrpc::Notification {
+                        jsonrpc: jsonrpc::VERSION.to_string(),
+                        method: "$/cancelRequest".to_string(),
+                        params: serde_json::json!({ "id": id }),
+                    })
+                }
+            };
+            let json = match json {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("{}: failed to serialize: {}", self.name, e);
+                    continue;
+                }
+            };
+            if let Err(e) = write_message(&mut writer, &json).await {
+                error!("{}: failed to write: {}", self.name, e);
+                break;
+            }
+        }
+    }
+}
+
+/// reads the headers and the body of one message
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Message> {
+    let mut content_length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(Error::StreamClosed);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let content_length = content_length.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, json: &str) -> Result<()> {
+    let header = format!("Content-Length: {}\r\n\r\n", json.len());
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(json.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::BufReader,
+        sync::{mpsc, oneshot},
+    };
+
+    use crate::jsonrpc::{self, Id, Message};
+
+    use super::{read_message, Payload, Transport};
+
+    #[tokio::test]
+    async fn cancel_drops_pending_request() {
+        let (client, server) = tokio::io::duplex(4096);
+        let (client_read, client_write) = tokio::io::split(client);
+        let (incoming, _incoming) = mpsc::unbounded_channel();
+        let tx = Transport::start(
+            BufReader::new(client_read),
+            client_write,
+            0,
+            "test".to_string(),
+            incoming,
+        );
+        let mut server = BufReader::new(server);
+
+        let (chan, rx) = oneshot::channel();
+        let request = jsonrpc::Request {
+            jsonrpc: jsonrpc::VERSION.to_string(),
+            id: Id::Num(3),
+            method: "slow".to_string(),
+            params: serde_json::Value::Null,
+        };
+        tx.send(Payload::Request { request, chan }).unwrap();
+        tx.send(Payload::Cancel(Id::Num(3))).unwrap();
+        assert!(matches!(
+            read_message(&mut server).await.unwrap(),
+            Message::Request(_)
+        ));
+        match read_message(&mut server).await.unwrap() {
+            Message::Notification(n) => {
+                assert_eq!(n.method, "$/cancelRequest");
+                assert_eq!(n.params["id"], 3);
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+        // the sender was dropped with the pending entry
+        assert!(rx.await.is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! conversions between char indices into a rope and lsp positions
+
+use kk_core::transaction::{Change, ChangeSet};
+use lsp_types as lsp;
+use ropey::Rope;
+
+use crate::OffsetEncoding;
+
+/// char index of the end of `line`, excluding its line break
+fn line_end(text: &Rope, line: usize) -> usize {
+    let start = text.line_to_char(line);
+    let line_text = text.line(line);
+    let mut len = line_text.len_chars();
+    while len > 0 && matches!(line_text.char(len - 1), '\n' | '\r') {
+        len -= 1;
+    }
+    start + len
+}
+
+pub fn pos_to_lsp_pos(text: &Rope, pos: usize, encoding: OffsetEncoding) -> lsp::Position {
+    let pos = pos.min(text.len_chars());
+    let line = text.char_to_line(pos);
+    let line_start = text.line_to_char(line);
+    let character = match encoding {
+        OffsetEncoding::Utf8 => text.char_to_byte(pos) - text.char_to_byte(line_start),
+        OffsetEncoding::Utf16 => text.char_to_utf16_cu(pos) - text.char_to_utf16_cu(line_start),
+    };
+    lsp::Position::new(line as u32, character as u32)
+}
+
+/// `None` if the line is out of bounds, a too large `character` is clamped to the line end
+pub fn lsp_pos_to_pos(text: &Rope, pos: lsp::Position, encoding: OffsetEncoding) -> Option<usize> {
+    let line = pos.line as usize;
+    if line >= text.len_lines() {
+        return None;
+    }
+    let line_start = text.line_to_char(line);
+    let end = line_end(text, line);
+    let character = pos.character as usize;
+    let pos = match encoding {
+        OffsetEncoding::Utf8 => {
+            let byte = text.char_to_byte(line_start) + character;
+            text.byte_to_char(byte.min(text.char_to_byte(end)))
+        }
+        OffsetEncoding::Utf16 => {
+            let cu = text.char_to_utf16_cu(line_start) + character;
+            text.utf16_cu_to_char(cu.min(text.char_to_utf16_cu(end)))
+        }
+    };
+    Some(pos)
+}
+
+pub fn range_to_lsp_range(
+    text: &Rope,
+    from: usize,
+    to: usize,
+    encoding: OffsetEncoding,
+) -> lsp::Range {
+    lsp::Range::new(
+        pos_to_lsp_pos(text, from, encoding),
+        pos_to_lsp_pos(text, to, encoding),
+    )
+}
+
+/// returns `(from, to)` as char indices
+pub fn lsp_range_to_range(
+    text: &Rope,
+    range: lsp::Range,
+    encoding: OffsetEncoding,
+) -> Option<(usize, usize)> {
+    let from = lsp_pos_to_pos(text, range.start, encoding)?;
+    let to = lsp_pos_to_pos(text, range.end, encoding)?;
+    Some((from.min(to), from.max(to)))
+}
+
+/// Turns the edits of a server into a changeset. Edits that are out of bounds or overlap a
+/// previous edit are dropped, the spec forbids them anyway.
+pub fn edits_to_changes(
+    text: &Rope,
+    edits: Vec<lsp::TextEdit>,
+    encoding: OffsetEncoding,
+) -> ChangeSet {
+    let mut changes: Vec<Change> = edits
+        .into_iter()
+        .filter_map(|edit| {
+            let (from, to) = lsp_range_to_range(text, edit.range, encoding)?;
+            let new_text = (!edit.new_text.is_empty()).then_some(edit.new_text);
+            Some(Change {
+                from,
+                to,
+                text: new_text,
+            })
+        })
+        .collect();
+    changes.sort_by_key(|c| (c.from, c.to));
+    let mut end = 0;
+    changes.retain(|c| {
+        let keep = c.from >= end;
+        if keep {
+            end = c.to;
+        }
+        keep
+    });
+    ChangeSet::new(changes)
+}
+
+/// Content changes for an incremental `didChange`. Every event is applied to the result of the
+/// previous one, so the changes are sent back to front and can all use `old_text` positions.
+pub fn changes_to_content_changes(
+    old_text: &Rope,
+    changes: &ChangeSet,
+    encoding: OffsetEncoding,
+) -> Vec<lsp::TextDocumentContentChangeEvent> {
+    changes
+        .changes()
+        .iter()
+        .rev()
+        .map(|change| lsp::TextDocumentContentChangeEvent {
+            range: Some(range_to_lsp_range(
+                old_text,
+                change.from,
+                change.to,
+                encoding,
+            )),
+            range_length: None,
+            text: change.text.clone().unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use kk_core::transaction::{Change, ChangeSet};
+    use lsp_types as lsp;
+    use ropey::Rope;
+
+    use super::{changes_to_content_changes, edits_to_changes, lsp_pos_to_pos, pos_to_lsp_pos};
+    use crate::OffsetEncoding;
+
+    #[test]
+    fn positions_with_encodings() {
+        let text = Rope::from_str("aé😀b\nx\n");
+        // 'b' is char 3, byte 7, utf-16 unit 4
+        assert_eq!(
+            pos_to_lsp_pos(&text, 3, OffsetEncoding::Utf8),
+            lsp::Position::new(0, 7)
+        );
+        assert_eq!(
+            pos_to_lsp_pos(&text, 3, OffsetEncoding::Utf16),
+            lsp::Position::new(0, 4)
+        );
+        assert_eq!(
+            lsp_pos_to_pos(&text, lsp::Position::new(0, 4), OffsetEncoding::Utf16),
+            Some(3)
+        );
+        // past the end of the line sticks to the line end
+        assert_eq!(
+            lsp_pos_to_pos(&text, lsp::Position::new(1, 10), OffsetEncoding::Utf16),
+            Some(6)
+        );
+        assert_eq!(
+            lsp_pos_to_pos(&text, lsp::Position::new(5, 0), OffsetEncoding::Utf16),
+            None
+        );
+    }
+
+    #[test]
+    fn edits_round_trip() {
+        let old = Rope::from_str("one two\nthree\n");
+        let changes = ChangeSet::new(vec![Change::replace(4, 7, "2"), Change::insert(8, ">")]);
+
+        // replaying the content changes in order gives the same text as applying the changeset
+        let mut replayed = old.clone();
+        for event in changes_to_content_changes(&old, &changes, OffsetEncoding::Utf16) {
+            let edit = lsp::TextEdit::new(event.range.unwrap(), event.text);
+            edits_to_changes(&replayed, vec![edit], OffsetEncoding::Utf16).apply(&mut replayed);
+        }
+        let mut applied = old.clone();
+        changes.apply(&mut applied);
+        assert_eq!(replayed, applied);
+        assert_eq!(applied.to_string(), "one 2\n>three\n");
+    }
+}
@@ -0,0 +1,211 @@
+use std::{path::PathBuf, time::Duration};
+
+use kk_core::transaction::{Change, ChangeSet};
+use kk_lsp::{
+    lsp::{self, Url},
+    Client, ServerMessage,
+};
+use ropey::Rope;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+fn uri() -> Url {
+    Url::from_file_path(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test.txt")).unwrap()
+}
+
+async fn start() -> (Client, UnboundedReceiver<(usize, ServerMessage)>) {
+    let (tx, rx) = unbounded_channel();
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let client = Client::start(env!("CARGO_BIN_EXE_fake-lsp"), &[], Some(&root), 7, tx).unwrap();
+    client.initialize().await.unwrap();
+    (client, rx)
+}
+
+/// waits for the next notification with the given method
+async fn notification(
+    rx: &mut UnboundedReceiver<(usize, ServerMessage)>,
+    method: &str,
+) -> serde_json::Value {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match rx.recv().await {
+                Some((_, ServerMessage::Notification(n))) if n.method == method => return n.params,
+                Some(_) => continue,
+                None => panic!("channel closed"),
+            }
+        }
+    })
+    .await
+    .expect("notification in time")
+}
+
+async fn diagnostics(rx: &mut UnboundedReceiver<(usize, ServerMessage)>) -> Vec<lsp::Diagnostic> {
+    let params = notification(rx, "textDocument/publishDiagnostics").await;
+    serde_json::from_value::<lsp::PublishDiagnosticsParams>(params)
+        .unwrap()
+        .diagnostics
+}
+
+#[tokio::test]
+async fn initialize_and_shutdown() {
+    let (client, mut rx) = start().await;
+    assert!(client.is_initialized());
+    assert!(client.capabilities().hover_provider.is_some());
+    assert_eq!(client.id(), 7);
+
+    let params = notification(&mut rx, "window/showMessage").await;
+    assert_eq!(params["message"], "fake-lsp ready");
+
+    client.shutdown_and_exit().await.unwrap();
+    // the server exits and closes its stdout
+    loop {
+        match rx.recv().await {
+            Some((7, ServerMessage::Exited)) => break,
+            Some(_) => continue,
+            None => panic!("channel closed"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn incremental_sync_and_diagnostics() {
+    let (client, mut rx) = start().await;
+    let mut text = Rope::from_str("let a = 1;\n// TODO\n");
+    client.did_open(uri(), 0, &text, "plaintext").unwrap();
+    let diags = diagnostics(&mut rx).await;
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].range.start, lsp::Position::new(1, 3));
+
+    // two changes at once, sent back to front
+    let old = text.clone();
+    let changes = ChangeSet::new(vec![Change::insert(0, "// TODO\n"), Change::delete(11, 19)]);
+    changes.apply(&mut text);
+    assert_eq!(text.to_string(), "// TODO\nlet a = 1;\n");
+    client.did_change(uri(), 1, &old, &text, &changes).unwrap();
+    let diags = diagnostics(&mut rx).await;
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].range.start, lsp::Position::new(0, 3));
+
+    // the server agrees on the text: formatting finds nothing to trim
+    let edits = client
+        .formatting(uri(), lsp::FormattingOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(edits, Some(vec![]));
+}
+
+#[tokio::test]
+async fn language_features() {
+    let (client, mut rx) = start().await;
+    let text = Rope::from_str("foo bar foo  \nfo\n");
+    client.did_open(uri(), 0, &text, "plaintext").unwrap();
+    diagnostics(&mut rx).await;
+
+    let hover = client
+        .hover(uri(), lsp::Position::new(0, 5))
+        .await
+        .unwrap()
+        .unwrap();
+    match hover.contents {
+        lsp::HoverContents::Markup(markup) => assert_eq!(markup.value, "word `bar`"),
+        contents => panic!("unexpected hover {:?}", contents),
+    }
+
+    let definition = client
+        .goto_definition(uri(), lsp::Position::new(0, 9))
+        .await
+        .unwrap();
+    match definition {
+        Some(lsp::GotoDefinitionResponse::Scalar(location)) => {
+            assert_eq!(location.range.start, lsp::Position::new(0, 0))
+        }
+        definition => panic!("unexpected definition {:?}", definition),
+    }
+
+    let references = client
+        .references(uri(), lsp::Position::new(0, 0))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(references.len(), 2);
+
+    let completion = client
+        .completion(uri(), lsp::Position::new(1, 2))
+        .await
+        .unwrap();
+    match completion {
+        Some(lsp::CompletionResponse::Array(items)) => {
+            assert_eq!(items.len(), 1);
+            assert_eq!(items[0].label, "foo");
+        }
+        completion => panic!("unexpected completion {:?}", completion),
+    }
+
+    let rename = client
+        .rename(uri(), lsp::Position::new(0, 0), "baz".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rename.changes.unwrap()[&uri()].len(), 2);
+
+    let formatting = client
+        .formatting(uri(), lsp::FormattingOptions::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        formatting,
+        vec![lsp::TextEdit::new(
+            lsp::Range::new(lsp::Position::new(0, 11), lsp::Position::new(0, 13)),
+            String::new()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn code_actions_and_server_requests() {
+    let (client, mut rx) = start().await;
+    let text = Rope::from_str("TODO\n");
+    client.did_open(uri(), 0, &text, "plaintext").unwrap();
+    let diags = diagnostics(&mut rx).await;
+
+    let actions = client
+        .code_actions(uri(), diags[0].range, diags)
+        .await
+        .unwrap()
+        .unwrap();
+    match &actions[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => {
+            assert_eq!(action.title, "mark as done")
+        }
+        actions => panic!("unexpected actions {:?}", actions),
+    }
+
+    let command = lsp::Command::new(
+        "apply".to_string(),
+        "fake.applyEdit".to_string(),
+        Some(vec![serde_json::json!(uri()), serde_json::json!("// ")]),
+    );
+    client.execute_command(command).await.unwrap();
+    let request = loop {
+        match rx.recv().await {
+            Some((_, ServerMessage::Request(request))) => break request,
+            Some(_) => continue,
+            None => panic!("channel closed"),
+        }
+    };
+    assert_eq!(request.method, "workspace/applyEdit");
+    client
+        .reply(request.id, Ok(serde_json::json!({ "applied": true })))
+        .unwrap();
+
+    // unknown methods come back as errors
+    let err = client
+        .call::<lsp::request::WorkspaceSymbolRequest>(lsp::WorkspaceSymbolParams {
+            query: String::new(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(err, kk_lsp::Error::Rpc(e) if e.code == -32601));
+}